@@ -7,7 +7,7 @@ extern crate fps_counter;
 extern crate time;
 
 use std::cell::{Cell, RefCell};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 #[macro_use]
 extern crate rid3;
@@ -17,10 +17,123 @@ use rid3::glyph::GlyphCache;
 use ui::Px;
 use ui::color::Scheme;
 use ui::draw::DrawCx;
-use ui::event::Dispatch;
+use ui::event::{Command, Dispatch, KeyPress};
+use ui::keymap::{Keymap, Resolved};
 use ui::tab::Tab;
 use ui::text::FontFaces;
 
+/// Run a key press through the keymap, falling back to dispatching it to `root`.
+/// Sets `consumed` if the keymap took the press, so its text input can be dropped.
+#[cfg(feature = "ide")]
+fn press<R, F>(root: &mut R, keymap: &mut Keymap, app_command: F,
+               e: KeyPress, consumed: &mut bool) -> bool
+    where R: Dispatch<KeyPress> + Dispatch<Command>, F: Fn(&str) -> bool {
+    let command = match keymap.resolve(e.into()) {
+        Resolved::Command(command) => command.to_owned(),
+        Resolved::Pending | Resolved::Cancelled => {
+            *consumed = true;
+            return false;
+        }
+        Resolved::Unbound => {
+            *consumed = false;
+            return <R as Dispatch<KeyPress>>::dispatch(root, &e);
+        }
+    };
+    *consumed = true;
+    if app_command(&command) {
+        return false;
+    }
+    <R as Dispatch<Command>>::dispatch(root, &Command(command))
+}
+
+/// What a path picked for a tab is used for.
+#[cfg(feature = "ide")]
+#[derive(Copy, Clone)]
+enum SaveTarget {
+    SaveAs,
+    Rename
+}
+
+/// Save the `i`th tab to `path`, unless another tab already has that file.
+#[cfg(feature = "ide")]
+fn save_to(tabs: &mut ui::tab::Set<ui::editor::Pane>, i: usize, target: SaveTarget, path: PathBuf) {
+    let canonical = std::fs::canonicalize(&path).unwrap_or(path.clone());
+    let taken = tabs.tabs().iter().enumerate().any(|(j, e)| j != i && e.path() == Some(canonical.as_path()));
+    if taken {
+        println!("{} is already open in another tab", path.display());
+        return;
+    }
+    let editor = match tabs.tabs_mut().get_mut(i) {
+        Some(editor) => editor,
+        None => return
+    };
+    let result = match target {
+        SaveTarget::SaveAs => editor.save_as(path),
+        SaveTarget::Rename => editor.rename(path)
+    };
+    if let Err(e) = result {
+        println!("Couldn't save {}: {}", editor.name(), e);
+    }
+}
+
+/// The tab showing `path`, opening it at the end if it isn't open yet.
+#[cfg(feature = "ide")]
+fn tab_for(tabs: &mut ui::tab::Set<ui::editor::Pane>, path: &Path) -> Option<usize> {
+    let path = std::fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    if let Some(i) = tabs.tabs().iter().position(|e| e.path() == Some(path.as_path())) {
+        return Some(i);
+    }
+    match ui::editor::Editor::open(&path) {
+        Ok(editor) => {
+            tabs.push(ui::editor::Pane::new(editor));
+            Some(tabs.tabs().len() - 1)
+        }
+        Err(e) => {
+            println!("Couldn't open {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+/// Switch to the tab showing `path`, opening it after the current one if needed.
+#[cfg(feature = "ide")]
+fn show(tabs: &mut ui::tab::Set<ui::editor::Pane>, path: &Path) -> bool {
+    let path = std::fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    if let Some(i) = tabs.tabs().iter().position(|e| e.path() == Some(path.as_path())) {
+        tabs.set_current(i);
+        return true;
+    }
+    match ui::editor::Editor::open(&path) {
+        Ok(editor) => {
+            tabs.add(ui::editor::Pane::new(editor));
+            true
+        }
+        Err(e) => {
+            println!("Couldn't open {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+/// Show `path` with the caret at `row` and byte `offset`, opening it if needed.
+#[cfg(feature = "ide")]
+fn go_to(tabs: &mut ui::tab::Set<ui::editor::Pane>, path: &Path, row: usize, offset: usize) {
+    if show(tabs, path) {
+        if let Some(editor) = tabs.current_mut() {
+            editor.goto(row, offset);
+        }
+    }
+}
+
+/// Where the caret is in the current tab, unless it's untitled.
+#[cfg(feature = "ide")]
+fn location(tabs: &ui::tab::Set<ui::editor::Pane>) -> Option<(PathBuf, usize, usize)> {
+    tabs.current().and_then(|e| {
+        let view = e.view();
+        e.path().map(|path| (path.to_path_buf(), view.row, view.offset))
+    })
+}
+
 #[cfg(not(feature = "ide"))]
 fn main() { error__please_enable_the_ide_feature_for_rid3 }
 
@@ -40,26 +153,114 @@ fn main() {
         mono_bold: GlyphCache::from_data(include_bytes!("../../assets/Hasklig/Hasklig-Bold.otf"), display.clone()).unwrap()
     });
 
-    let open_queue = RefCell::new(std::env::args().skip(1).map(PathBuf::from).collect::<Vec<_>>());
+    // Files to open, with the row and byte offset to put the caret at.
+    let open_queue = RefCell::new(vec![]);
+    // Paths chosen in the picker, not yet acted upon.
+    let picked = RefCell::new(vec![]);
+    // Changes made from the file tree, which the tabs have to follow.
+    let tree_actions = RefCell::new(vec![]);
+
+    // A directory argument is searched through instead of being opened.
+    let mut project_root = std::env::current_dir().unwrap();
+    for path in std::env::args().skip(1).map(PathBuf::from) {
+        if path.is_dir() {
+            project_root = path;
+        } else {
+            open_queue.borrow_mut().push((path, None));
+        }
+    }
+
+    let new_file = Cell::new(false);
+    let open_file = Cell::new(false);
     let save_current = Cell::new(false);
+    let save_as_current = Cell::new(false);
+    let rename_current = Cell::new(false);
+    let save_all = Cell::new(false);
     let run_current = Cell::new(false);
     let close_current = Cell::new(false);
+    let go_back = Cell::new(false);
+    let go_forward = Cell::new(false);
+
+    // Commands handled here rather than by the widgets.
+    let app_command = |command: &str| {
+        match command {
+            "new" => new_file.set(true),
+            "open" => open_file.set(true),
+            "save" => save_current.set(true),
+            "save-as" => save_as_current.set(true),
+            "rename" => rename_current.set(true),
+            "save-all" => save_all.set(true),
+            "run" => {
+                save_current.set(true);
+                run_current.set(true);
+            }
+            "close" => close_current.set(true),
+            "go-back" => go_back.set(true),
+            "go-forward" => go_forward.set(true),
+            _ => return false
+        }
+        true
+    };
 
     let tool_bar = tool_bar![
-        ui::tool::Button::new("Open", || {
-            open_queue.borrow_mut().extend(ui::dialog::open_file().into_iter())
-        }),
-        ui::tool::Button::new("Save", || save_current.set(true)),
-        ui::tool::Button::new("Run", || {
-            save_current.set(true);
-            run_current.set(true);
-        }),
-        ui::tool::Button::new("Close", || close_current.set(true))
+        ui::tool::Button::new("New", || { app_command("new"); }),
+        ui::tool::Button::new("Open", || { app_command("open"); }),
+        ui::tool::Button::new("Save", || { app_command("save"); }),
+        ui::tool::Button::new("Rename", || { app_command("rename"); }),
+        ui::tool::Button::new("Run", || { app_command("run"); }),
+        ui::tool::Button::new("Close", || { app_command("close"); })
     ];
-    let mut root = flow![down: tool_bar, ui::tab::Set::<ui::editor::Editor>::new()];
+    let picker = ui::picker::Picker::new(|mode: ui::picker::Mode, paths: Vec<PathBuf>| {
+        picked.borrow_mut().push((mode, paths));
+    });
+    let results = ui::results::Panel::new(project_root.clone(), |path: &Path, row: usize, offset: usize| {
+        open_queue.borrow_mut().push((path.to_path_buf(), Some((row, offset))));
+    });
+    let tree = ui::tree::Tree::new(project_root.clone(), |action: ui::tree::Action| {
+        tree_actions.borrow_mut().push(action);
+    });
+    let main = flow![right: tree, ui::tab::Set::<ui::editor::Pane>::new()];
+    let mut root = flow![down: tool_bar, picker, main, results];
+
+    // Bring back the last session, then open whatever else was asked for.
+    rid3::session::restore(&mut root.kids.1.1.0.kids.1);
+    {
+        let tabs = root.kids.1.1.0.kids.1.tabs();
+        open_queue.borrow_mut().retain(|&(ref path, _)| {
+            let path = std::fs::canonicalize(path).ok();
+            path.is_none() || !tabs.iter().any(|tab| {
+                tab.path().and_then(|p| std::fs::canonicalize(p).ok()) == path
+            })
+        });
+    }
+    let mut autosave = rid3::session::Autosave::new();
+    // What the path from the picker is for, and the id of the tab's editor.
+    let mut save_target = None;
+    // Whether untitled tabs are being saved one after the other.
+    let mut saving_all = false;
+    // Where definitions were jumped to from, and where going back came from.
+    let mut back_history: Vec<(PathBuf, usize, usize)> = vec![];
+    let mut forward_history: Vec<(PathBuf, usize, usize)> = vec![];
 
     let (mut x, mut y) = (0.0, 0.0);
     let mut key_tracker = ui::event::KeyTracker::default();
+    let mut click_tracker = ui::event::ClickTracker::default();
+    let mut keymap = Keymap::load();
+    // Whether the text input following the last key press should be ignored.
+    let mut swallow_text = false;
+
+    // Keyboard input goes to the picker, the file tree or the results panel while they have the focus.
+    macro_rules! press {
+        ($e:expr) => (if root.kids.1.0.has_focus() {
+            press(&mut root.kids.1.0, &mut keymap, &app_command, $e, &mut swallow_text)
+        } else if root.kids.1.1.0.kids.0.has_focus() {
+            press(&mut root.kids.1.1.0.kids.0, &mut keymap, &app_command, $e, &mut swallow_text)
+        } else if root.kids.1.1.1.has_focus() {
+            press(&mut root.kids.1.1.1, &mut keymap, &app_command, $e, &mut swallow_text)
+        } else {
+            press(&mut root, &mut keymap, &app_command, $e, &mut swallow_text)
+        })
+    }
     let mut last_update = time::precise_time_ns();
     let mut cursor = ui::draw::MouseCursor::Default;
     let mut fps_counter = fps_counter::FPSCounter::new();
@@ -74,21 +275,23 @@ fn main() {
         for event in display.poll_events() {
             dirty |= match event {
                 E::KeyboardInput(ElementState::Pressed, _, Some(key)) => {
-                    dirty |= root.dispatch(&ui::event::KeyDown(key));
-                    for e in key_tracker.down(key) {
-                        dirty |= root.dispatch(&e);
+                    let presses = key_tracker.down(key);
+                    dirty |= root.dispatch(&ui::event::KeyDown(key, key_tracker.modifiers()));
+                    for e in presses {
+                        dirty |= press!(e);
                     }
                     false
                 }
                 E::KeyboardInput(ElementState::Released, _, Some(key)) => {
-                    dirty |= root.dispatch(&ui::event::KeyUp(key));
-                    for e in key_tracker.up(key) {
-                        dirty |= root.dispatch(&e);
+                    let presses = key_tracker.up(key);
+                    dirty |= root.dispatch(&ui::event::KeyUp(key, key_tracker.modifiers()));
+                    for e in presses {
+                        dirty |= press!(e);
                     }
                     false
                 }
                 E::MouseInput(ElementState::Pressed, MouseButton::Left) => {
-                    root.dispatch(&ui::event::MouseDown::new(x, y))
+                    root.dispatch(&click_tracker.down(x, y))
                 }
                 E::MouseInput(ElementState::Released, MouseButton::Left) => {
                     root.dispatch(&ui::event::MouseUp::new(x, y))
@@ -104,11 +307,26 @@ fn main() {
                     root.dispatch(&ui::event::MouseScroll::with(x, y,
                         ui::event::mouse::Scroll([dx as Px, dy as Px])))
                 }
+                E::ReceivedCharacter(_) if swallow_text => false,
+                E::ReceivedCharacter(c) if root.kids.1.0.has_focus() => {
+                    root.kids.1.0.dispatch(&ui::event::TextInput(c))
+                }
+                E::ReceivedCharacter(c) if root.kids.1.1.0.kids.0.has_focus() => {
+                    root.kids.1.1.0.kids.0.dispatch(&ui::event::TextInput(c))
+                }
+                E::ReceivedCharacter(c) if root.kids.1.1.1.has_focus() => {
+                    root.kids.1.1.1.dispatch(&ui::event::TextInput(c))
+                }
                 E::ReceivedCharacter(c) => {
                     root.dispatch(&ui::event::TextInput(c))
                 }
                 E::Resized(..) => true,
-                E::Closed => break 'main,
+                E::Closed => {
+                    if let Err(e) = autosave.write(&root.kids.1.1.0.kids.1) {
+                        println!("couldn't autosave: {}", e);
+                    }
+                    break 'main;
+                }
                 _ => false
             }
         }
@@ -116,27 +334,139 @@ fn main() {
         let current = time::precise_time_ns();
         let dt = (current - last_update) as f32 / 1e9;
         dirty |= root.dispatch(&ui::event::Update(dt));
+        for editor in root.kids.1.1.0.kids.1.tabs_mut() {
+            dirty |= editor.poll_disk(dt);
+        }
+        click_tracker.update(dt);
+        autosave.update(dt, &root.kids.1.1.0.kids.1);
         for e in key_tracker.update(dt) {
-            dirty |= root.dispatch(&e);
+            dirty |= press!(e);
         }
         last_update = current;
 
-        if save_current.get() {
-            root.kids.1.current_mut().map(|e| e.save());
+        if new_file.get() {
+            root.kids.1.1.0.kids.1.add(ui::editor::Pane::new(ui::editor::Editor::untitled()));
+            new_file.set(false);
+            dirty = true;
+        }
+
+        if open_file.get() {
+            if ui::dialog::external() {
+                open_queue.borrow_mut().extend(ui::dialog::open_file().map(|path| (path, None)));
+            } else {
+                let dir = root.kids.1.1.0.kids.1.current().and_then(|e| e.path())
+                                  .and_then(Path::parent).map_or(project_root.clone(), Path::to_path_buf);
+                root.kids.1.0.show(ui::picker::Mode::Open, dir, "");
+            }
+            open_file.set(false);
+            dirty = true;
+        }
+
+        // Act on what was picked, or stop waiting if the picker was closed.
+        for (mode, paths) in picked.borrow_mut().drain(..) {
+            match mode {
+                ui::picker::Mode::Open => {
+                    open_queue.borrow_mut().extend(paths.into_iter().map(|path| (path, None)));
+                }
+                ui::picker::Mode::Save => {
+                    let tabs = &mut root.kids.1.1.0.kids.1;
+                    if let (Some((target, id)), Some(path)) = (save_target.take(), paths.into_iter().next()) {
+                        // The tab bar can still be used while picking, so go by the tab and not the current one.
+                        match tabs.tabs().iter().position(|e| e.id() == id) {
+                            Some(i) => save_to(tabs, i, target, path),
+                            None => println!("The tab to save as {} was closed", path.display())
+                        }
+                    }
+                }
+            }
+            dirty = true;
+        }
+        if save_target.is_some() && !root.kids.1.0.visible {
+            save_target = None;
+            saving_all = false;
+        }
+
+        let mut choose = None;
+        if save_current.get() || save_as_current.get() {
+            if let Some(editor) = root.kids.1.1.0.kids.1.current_mut() {
+                if save_as_current.get() || editor.path().is_none() {
+                    choose = Some(SaveTarget::SaveAs);
+                } else if let Err(e) = editor.save() {
+                    println!("Couldn't save {}: {}", editor.name(), e);
+                }
+            }
             save_current.set(false);
+            save_as_current.set(false);
+            dirty = true;
+        }
+
+        if rename_current.get() {
+            choose = Some(SaveTarget::Rename);
+            rename_current.set(false);
+        }
+
+        if save_all.get() {
+            for editor in root.kids.1.1.0.kids.1.tabs_mut() {
+                if !editor.is_saved() && editor.path().is_some() {
+                    if let Err(e) = editor.save() {
+                        println!("Couldn't save {}: {}", editor.name(), e);
+                    }
+                }
+            }
+            saving_all = true;
+            save_all.set(false);
+            dirty = true;
+        }
+
+        // Untitled tabs need a path each, so they're saved one at a time.
+        if saving_all && save_target.is_none() && choose.is_none() {
+            let untitled = root.kids.1.1.0.kids.1.tabs().iter().position(|e| !e.is_saved() && e.path().is_none());
+            match untitled {
+                Some(i) => {
+                    root.kids.1.1.0.kids.1.set_current(i);
+                    choose = Some(SaveTarget::SaveAs);
+                }
+                None => saving_all = false
+            }
+        }
+
+        if let Some(target) = choose {
+            let tabs = &mut root.kids.1.1.0.kids.1;
+            let current = tabs.current_index();
+            let path = tabs.current().map(|e| (e.path().map(Path::to_path_buf), e.name(), e.id()));
+            if let Some((path, name, id)) = path {
+                if ui::dialog::external() {
+                    let suggested = path.unwrap_or(PathBuf::from(name));
+                    match ui::dialog::save_file(&suggested) {
+                        Some(path) => save_to(tabs, current, target, path),
+                        None => saving_all = false
+                    }
+                } else {
+                    let dir = path.as_ref().and_then(|p| p.parent()).map_or(project_root.clone(), Path::to_path_buf);
+                    let name = path.as_ref().and_then(|p| p.file_name())
+                                   .map_or(String::new(), |name| name.to_string_lossy().into_owned());
+                    root.kids.1.0.show(ui::picker::Mode::Save, dir, &name);
+                    save_target = Some((target, id));
+                }
+            }
             dirty = true;
         }
 
         if run_current.get() {
-            root.kids.1.current().map(|e| {
-                println!("{}", std::iter::repeat('\n').take(200).collect::<String>());
-                rid3::ide::rustc::compile_and_run(e.path());
+            root.kids.1.1.0.kids.1.current().map(|e| {
+                match e.path() {
+                    Some(path) => {
+                        println!("{}", std::iter::repeat('\n').take(200).collect::<String>());
+                        rid3::ide::rustc::compile_and_run(path);
+                    }
+                    None => println!("Save file first!")
+                }
             });
             run_current.set(false);
         }
 
         if close_current.get() {
-            let is_unsaved = root.kids.1.current().and_then(|e| {
+            let is_unsaved = root.kids.1.1.0.kids.1.current().and_then(|e| {
                 if e.is_saved() {
                     None
                 } else {
@@ -146,20 +476,153 @@ fn main() {
             if is_unsaved {
                 println!("Save file first!");
             } else {
-                root.kids.1.remove();
+                root.kids.1.1.0.kids.1.remove();
                 dirty = true;
             }
             close_current.set(false);
         }
 
+        for action in tree_actions.borrow_mut().drain(..) {
+            use ui::tree::Action;
+
+            let tabs = &mut root.kids.1.1.0.kids.1;
+            match action {
+                Action::Open(path) | Action::Created(path) => {
+                    // Switches to the file if it's already open.
+                    open_queue.borrow_mut().push((path, None));
+                }
+                Action::Renamed(old, new) => {
+                    for editor in tabs.tabs_mut() {
+                        let moved = editor.path().and_then(|path| path.strip_prefix(&old).ok())
+                                          .map(|rest| new.join(rest));
+                        if let Some(path) = moved {
+                            editor.moved_to(path);
+                        }
+                    }
+                }
+                Action::Deleted(path) => {
+                    // Unsaved tabs stay open, so their text can still be saved somewhere.
+                    loop {
+                        let deleted = tabs.tabs().iter().position(|e| {
+                            e.is_saved() && e.path().map_or(false, |p| p.starts_with(&path))
+                        });
+                        match deleted {
+                            Some(i) => { tabs.remove_at(i); }
+                            None => break
+                        }
+                    }
+                }
+            }
+            dirty = true;
+        }
+
         {
             let mut q = open_queue.borrow_mut();
-            for file in q.drain(..) {
-                root.kids.1.add(ui::editor::Editor::open(file));
+            // Files that are already open are switched to instead.
+            for (file, pos) in q.drain(..) {
+                let tabs = &mut root.kids.1.1.0.kids.1;
+                match pos {
+                    Some((row, offset)) => go_to(tabs, &file, row, offset),
+                    None => { show(tabs, &file); }
+                }
                 dirty = true;
             }
         }
 
+        let jump = root.kids.1.1.0.kids.1.current_mut().and_then(|e| e.take_jump());
+        if let Some(jump) = jump {
+            let tabs = &mut root.kids.1.1.0.kids.1;
+            if let Some(path) = tabs.current().and_then(|e| e.path()).map(Path::to_path_buf) {
+                back_history.push((path, jump.from.0, jump.from.1));
+                forward_history.clear();
+            }
+            match jump.to.path {
+                Some(path) => go_to(tabs, &path, jump.to.row, jump.to.offset),
+                None => {
+                    if let Some(editor) = tabs.current_mut() {
+                        editor.goto(jump.to.row, jump.to.offset);
+                    }
+                }
+            }
+            dirty = true;
+        }
+
+        let references = root.kids.1.1.0.kids.1.current_mut().and_then(|e| e.take_references());
+        if let Some(references) = references {
+            let tabs = &mut root.kids.1.1.0.kids.1;
+            let own_path = tabs.current().and_then(|e| e.path()).map(Path::to_path_buf);
+            match references.rename_to {
+                None => {
+                    let hits: Vec<_> = references.references.into_iter().filter_map(|r| {
+                        r.path.or(own_path.clone()).map(|path| rid3::grep::Hit {
+                            path: path,
+                            row: r.row,
+                            range: r.range,
+                            line: r.line
+                        })
+                    }).collect();
+                    let label = format!("{} references to `{}`", hits.len(), references.name);
+                    root.kids.1.1.1.show_hits(label, hits);
+                }
+                Some(new_name) => {
+                    // Group the ranges by tab, opening the other files they're in,
+                    // and only rename if all of them could be opened.
+                    let current = tabs.current_index();
+                    let mut edits: Vec<(usize, Vec<(usize, std::ops::Range<usize>)>)> = vec![];
+                    let mut complete = true;
+                    for r in references.references {
+                        let i = match r.path {
+                            Some(ref path) => tab_for(tabs, path),
+                            None => Some(current)
+                        };
+                        let i = match i {
+                            Some(i) => i,
+                            None => {
+                                complete = false;
+                                break;
+                            }
+                        };
+                        match edits.iter().position(|&(j, _)| j == i) {
+                            Some(k) => edits[k].1.push((r.row, r.range)),
+                            None => edits.push((i, vec![(r.row, r.range)]))
+                        }
+                    }
+                    if complete {
+                        for (i, mut ranges) in edits {
+                            ranges.sort_by(|a, b| (a.0, a.1.start).cmp(&(b.0, b.1.start)));
+                            tabs.tabs_mut()[i].rename_ranges(&ranges, &references.name, &new_name);
+                        }
+                    } else {
+                        println!("Couldn't rename `{}`: not all of its files could be opened", references.name);
+                    }
+                }
+            }
+            dirty = true;
+        }
+
+        if go_back.get() || go_forward.get() {
+            let tabs = &mut root.kids.1.1.0.kids.1;
+            let (from, to) = if go_back.get() {
+                (&mut back_history, &mut forward_history)
+            } else {
+                (&mut forward_history, &mut back_history)
+            };
+            if let Some((path, row, offset)) = from.pop() {
+                to.extend(location(tabs));
+                go_to(tabs, &path, row, offset);
+            }
+            go_back.set(false);
+            go_forward.set(false);
+            dirty = true;
+        }
+
+        {
+            let open = root.kids.1.1.0.kids.1.tabs().iter().filter_map(|e| {
+                e.path().map(|path| (path.to_path_buf(), !e.is_saved()))
+            }).collect();
+            dirty |= root.kids.1.1.0.kids.0.set_open(open);
+        }
+
         if dirty {
             let mut draw_cx = DrawCx::new(renderer, &display, display.draw());
             let [w, h] = draw_cx.dimensions();
@@ -181,7 +644,7 @@ fn main() {
             }
 
             let fps = fps_counter.tick();
-            let tab_title = root.kids.1.current().map(|tab| tab.title());
+            let tab_title = root.kids.1.1.0.kids.1.current().map(|tab| tab.title());
             let title = format!("rid3: {} @ {}FPS", tab_title.as_ref().map_or("", |s| &s[..]), fps);
             display.get_window().map(|w| w.set_title(&title));
 
@@ -0,0 +1,56 @@
+//! Matching names against a few typed characters, as when picking a file or completing.
+
+/// How well `query` matches `name`, if all of its characters appear in order,
+/// ignoring case. Matches at the start of words and runs of characters win.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<i32> {
+    let mut query = query.chars().flat_map(char::to_lowercase).peekable();
+    let mut score = 0;
+    let mut prev: Option<char> = None;
+    let mut prev_matched = false;
+    for c in name.chars() {
+        let q = match query.peek() {
+            Some(&q) => q,
+            None => break
+        };
+        let matched = c.to_lowercase().next() == Some(q);
+        if matched {
+            query.next();
+            score += 1;
+            if prev_matched {
+                score += 4;
+            }
+            let word_start = prev.map_or(true, |p| {
+                !p.is_alphanumeric() || (p.is_lowercase() && c.is_uppercase())
+            });
+            if word_start {
+                score += 6;
+            }
+        } else {
+            score -= 1;
+        }
+        prev = Some(c);
+        prev_matched = matched;
+    }
+    if query.peek().is_some() { None } else { Some(score) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fuzzy_score;
+
+    #[test]
+    fn characters_in_order_ignoring_case() {
+        assert_eq!(fuzzy_score("", "main.rs"), Some(0));
+        assert!(fuzzy_score("MRS", "main.rs").is_some());
+        assert_eq!(fuzzy_score("srm", "main.rs"), None);
+        assert!(fuzzy_score("mains", "main.rs").is_some());
+        assert_eq!(fuzzy_score("main.rss", "main.rs"), None);
+    }
+
+    #[test]
+    fn word_starts_and_runs_win() {
+        assert!(fuzzy_score("fb", "foo_bar") > fuzzy_score("fb", "afxb"));
+        assert!(fuzzy_score("fb", "fooBar") > fuzzy_score("fb", "afxb"));
+        assert!(fuzzy_score("ab", "xabx") > fuzzy_score("ab", "xaxb"));
+    }
+}
@@ -0,0 +1,307 @@
+//! Searching through all the files in a directory tree, on a background thread.
+
+use regex::{self, Regex};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Weak};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use ui::find::Pattern;
+
+pub struct Hit {
+    pub path: PathBuf,
+    pub row: usize,
+    // Byte range of the match in `line`.
+    pub range: Range<usize>,
+    pub line: String
+}
+
+enum Res {
+    Hits(Vec<Hit>),
+    Done
+}
+
+pub struct Grep {
+    // Dropping this stops the search.
+    _lifeline: Arc<()>,
+    res_rx: Receiver<Res>,
+    pub hits: Vec<Hit>,
+    pub done: bool
+}
+
+impl Grep {
+    pub fn start(root: PathBuf, pattern: Pattern) -> Grep {
+        let lifeline = Arc::new(());
+        let lifeline2 = Arc::downgrade(&lifeline);
+        let (res_tx, res_rx) = channel();
+        thread::spawn(move || {
+            let res_tx2 = res_tx.clone();
+            let _ = thread::catch_panic(move || {
+                let mut ignores = vec![];
+                let _ = walk(&root, &mut ignores, &mut |path| {
+                    if lifeline2.upgrade().is_none() {
+                        return Err(());
+                    }
+                    let hits = search_file(path, &pattern);
+                    if !hits.is_empty() {
+                        try!(res_tx.send(Res::Hits(hits)).map_err(|_| ()));
+                    }
+                    Ok(())
+                });
+            });
+            let _ = res_tx2.send(Res::Done);
+        });
+        Grep {
+            _lifeline: lifeline,
+            res_rx: res_rx,
+            hits: vec![],
+            done: false
+        }
+    }
+
+    /// Results found some other way, e.g. references from analysis,
+    /// to be shown like those of a search.
+    pub fn finished(hits: Vec<Hit>) -> Grep {
+        let (_, res_rx) = channel();
+        Grep {
+            _lifeline: Arc::new(()),
+            res_rx: res_rx,
+            hits: hits,
+            done: true
+        }
+    }
+
+    /// Collect the results found so far, returns true if there were any.
+    pub fn update(&mut self) -> bool {
+        let mut dirty = false;
+        while let Ok(res) = self.res_rx.try_recv() {
+            match res {
+                Res::Hits(hits) => self.hits.extend(hits),
+                Res::Done => self.done = true
+            }
+            dirty = true;
+        }
+        dirty
+    }
+}
+
+fn search_file(path: &Path, pattern: &Pattern) -> Vec<Hit> {
+    // Skip anything that isn't UTF-8 text, e.g. binaries.
+    let mut data = String::new();
+    if File::open(path).and_then(|mut f| f.read_to_string(&mut data)).is_err() {
+        return vec![];
+    }
+
+    let mut hits = vec![];
+    for (row, line) in data.split('\n').enumerate() {
+        for range in pattern.matches(line) {
+            hits.push(Hit {
+                path: path.to_path_buf(),
+                row: row,
+                range: range,
+                line: line.trim_right_matches('\r').to_owned()
+            });
+        }
+    }
+    hits
+}
+
+/// Call `f` on every file under `dir`, skipping anything ignored by `.gitignore`.
+fn walk<F>(dir: &Path, ignores: &mut Vec<Ignore>, f: &mut F) -> Result<(), ()>
+           where F: FnMut(&Path) -> Result<(), ()> {
+    let old_len = ignores.len();
+    Ignore::load(dir, ignores);
+
+    let mut entries = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| e.path()).collect::<Vec<_>>(),
+        Err(_) => vec![]
+    };
+    entries.sort();
+
+    let mut result = Ok(());
+    for path in entries {
+        let is_dir = path.is_dir();
+        if path.file_name().map_or(false, |name| name == ".git") {
+            continue;
+        }
+        if Ignore::is_ignored(ignores, &path, is_dir) {
+            continue;
+        }
+        result = if is_dir { walk(&path, ignores, f) } else { f(&path) };
+        if result.is_err() {
+            break;
+        }
+    }
+
+    ignores.truncate(old_len);
+    result
+}
+
+/// A single pattern from a `.gitignore` file.
+pub struct Ignore {
+    // Directory containing the `.gitignore`.
+    base: PathBuf,
+    regex: Regex,
+    // Whether the pattern contains a slash and has to match the whole path
+    // relative to `base`, instead of just the file name.
+    anchored: bool,
+    dir_only: bool,
+    negated: bool
+}
+
+impl Ignore {
+    /// Add the patterns from `dir/.gitignore`, if it exists.
+    pub fn load(dir: &Path, ignores: &mut Vec<Ignore>) {
+        let mut source = String::new();
+        match File::open(dir.join(".gitignore")).and_then(|mut f| f.read_to_string(&mut source)) {
+            Ok(_) => {}
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+            Err(e) => {
+                println!("couldn't read {}: {}", dir.join(".gitignore").display(), e);
+                return;
+            }
+        }
+
+        for line in source.lines() {
+            let line = line.trim_right();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negated, line) = if line.starts_with('!') { (true, &line[1..]) } else { (false, line) };
+            let (dir_only, line) = if line.ends_with('/') {
+                (true, &line[..line.len() - 1])
+            } else {
+                (false, line)
+            };
+            let anchored = line.contains('/');
+            let line = line.trim_left_matches('/');
+
+            match Regex::new(&glob_to_regex(line)) {
+                Ok(regex) => ignores.push(Ignore {
+                    base: dir.to_path_buf(),
+                    regex: regex,
+                    anchored: anchored,
+                    dir_only: dir_only,
+                    negated: negated
+                }),
+                Err(e) => println!("invalid pattern `{}` in {}: {}", line, dir.join(".gitignore").display(), e)
+            }
+        }
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = match path.strip_prefix(&self.base) {
+            Ok(relative) => relative,
+            Err(_) => return false
+        };
+        if self.anchored {
+            let relative = relative.to_string_lossy().replace('\\', "/");
+            self.regex.is_match(&relative)
+        } else {
+            path.file_name().map_or(false, |name| self.regex.is_match(&name.to_string_lossy()))
+        }
+    }
+
+    /// The last matching pattern decides, so later ones can un-ignore with `!`.
+    pub fn is_ignored(ignores: &[Ignore], path: &Path, is_dir: bool) -> bool {
+        ignores.iter().rev().find(|ignore| ignore.matches(path, is_dir))
+               .map_or(false, |ignore| !ignore.negated)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let chars: Vec<char> = glob.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    regex.push_str("(?:.*/)?");
+                    i += 1;
+                } else {
+                    regex.push_str(".*");
+                }
+                i += 1;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                match chars[i..].iter().position(|&c| c == ']') {
+                    Some(len) => {
+                        let class: String = chars[i + 1..i + len].iter().cloned().collect();
+                        regex.push('[');
+                        if class.starts_with('!') {
+                            regex.push('^');
+                            regex.push_str(&class[1..]);
+                        } else {
+                            regex.push_str(&class);
+                        }
+                        regex.push(']');
+                        i += len;
+                    }
+                    None => regex.push_str(r"\[")
+                }
+            }
+            c => regex.push_str(&regex::quote(&c.to_string()))
+        }
+        i += 1;
+    }
+    // A matching directory also covers everything inside it.
+    regex.push_str("(?:/.*)?$");
+    regex
+}
+
+#[cfg(test)]
+mod tests {
+    use regex::Regex;
+    use super::glob_to_regex;
+
+    fn matches(glob: &str, path: &str) -> bool {
+        Regex::new(&glob_to_regex(glob)).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn stars_stay_within_a_directory() {
+        assert!(matches("*.rs", "main.rs"));
+        assert!(!matches("*.rs", "src/main.rs"));
+        assert!(matches("src/*.rs", "src/main.rs"));
+        assert!(!matches("src/*.rs", "src/ui/mod.rs"));
+    }
+
+    #[test]
+    fn double_star_crosses_directories() {
+        assert!(matches("**/*.rs", "main.rs"));
+        assert!(matches("**/*.rs", "src/ui/mod.rs"));
+        assert!(matches("src/**", "src/ui/mod.rs"));
+    }
+
+    #[test]
+    fn question_mark_and_classes() {
+        assert!(matches("?.txt", "a.txt"));
+        assert!(!matches("?.txt", "ab.txt"));
+        assert!(matches("[ab].txt", "b.txt"));
+        assert!(!matches("[!ab].txt", "b.txt"));
+        assert!(matches("[!ab].txt", "c.txt"));
+        assert!(matches("[.txt", "[.txt"));
+    }
+
+    #[test]
+    fn special_characters_are_literal() {
+        assert!(matches("a+b.rs", "a+b.rs"));
+        assert!(!matches("a.rs", "abrs"));
+    }
+
+    #[test]
+    fn directories_cover_their_contents() {
+        assert!(matches("target", "target"));
+        assert!(matches("target", "target/debug/rid3"));
+        assert!(!matches("target", "targets"));
+    }
+}
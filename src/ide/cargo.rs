@@ -0,0 +1,415 @@
+//! Finding out how cargo would build the crate a file belongs to.
+
+extern crate serialize;
+
+use self::serialize::json::Json;
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    Lib,
+    Bin,
+    Test,
+    Example,
+    Bench,
+    Build
+}
+
+impl Kind {
+    fn parse(kind: &str) -> Option<Kind> {
+        match kind {
+            "lib" | "rlib" | "dylib" | "staticlib" => Some(Kind::Lib),
+            "bin" => Some(Kind::Bin),
+            "test" => Some(Kind::Test),
+            "example" => Some(Kind::Example),
+            "bench" => Some(Kind::Bench),
+            "custom-build" => Some(Kind::Build),
+            _ => None
+        }
+    }
+
+    /// Whether dev-dependencies are available, and `cfg(test)` is set.
+    pub fn is_test(self) -> bool {
+        self == Kind::Test || self == Kind::Bench
+    }
+}
+
+pub struct Target {
+    pub name: String,
+    pub kind: Kind,
+    pub root: PathBuf
+}
+
+struct Dependency {
+    // Name of the crate, as used in `extern crate`.
+    name: String,
+    dev: bool,
+    optional: bool
+}
+
+/// A package from `cargo metadata`.
+pub struct Package {
+    pub manifest: PathBuf,
+    pub name: String,
+    pub edition: Option<String>,
+    pub targets: Vec<Target>,
+    // Features enabled by default, including those they enable in turn.
+    pub features: Vec<String>,
+    dependencies: Vec<Dependency>,
+    target_dir: PathBuf
+}
+
+/// Everything needed to analyse or build one target of a package.
+#[derive(Clone)]
+pub struct Crate {
+    pub manifest: PathBuf,
+    pub name: String,
+    pub kind: Kind,
+    /// File the crate is compiled from, e.g. `src/lib.rs`.
+    pub root: PathBuf,
+    /// Not used for analysis, the compiler here has no notion of editions.
+    pub edition: Option<String>,
+    /// In `--cfg` syntax, e.g. `feature="std"`.
+    pub cfgs: Vec<String>,
+    /// Built dependencies, for `--extern name=path`.
+    pub externs: Vec<(String, PathBuf)>,
+    /// Where the dependencies of dependencies are, for `-L dependency=...`.
+    pub deps_dir: PathBuf
+}
+
+/// The `Cargo.toml` closest to `file`, going up from its directory.
+pub fn find_manifest(file: &Path) -> Option<PathBuf> {
+    let file = fs::canonicalize(file).unwrap_or(file.to_path_buf());
+    let mut dir = file.parent();
+    while let Some(d) = dir {
+        let manifest = d.join("Cargo.toml");
+        if manifest.is_file() {
+            return Some(manifest);
+        }
+        dir = d.parent();
+    }
+    None
+}
+
+fn string(json: &Json, key: &str) -> Option<String> {
+    json.find(key).and_then(Json::as_string).map(str::to_owned)
+}
+
+fn crate_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Read the package with the given `Cargo.toml`, through `cargo metadata`.
+pub fn package(manifest: &Path) -> Result<Package, String> {
+    let out = try!(Command::new("cargo")
+        .args(&["metadata", "--no-deps", "--format-version", "1", "--manifest-path"])
+        .arg(manifest)
+        .output().map_err(|e| format!("couldn't run cargo: {}", e)));
+    if !out.status.success() {
+        return Err(String::from_utf8_lossy(&out.stderr).into_owned());
+    }
+    let metadata = try!(str::from_utf8(&out.stdout).map_err(|e| e.to_string())
+                           .and_then(|s| Json::from_str(s).map_err(|e| format!("{:?}", e))));
+
+    let packages = metadata.find("packages").and_then(Json::as_array).map_or(&[][..], |p| &p[..]);
+    let json = try!(packages.iter().find(|p| {
+        string(p, "manifest_path").map_or(false, |path| Path::new(&path) == manifest)
+    }).or(packages.first()).ok_or(format!("no package in {}", manifest.display())));
+
+    let dir = manifest.parent().unwrap_or(Path::new("."));
+    let targets = json.find("targets").and_then(Json::as_array).map_or(vec![], |targets| {
+        targets.iter().filter_map(|target| {
+            let kind = target.find("kind").and_then(Json::as_array)
+                             .and_then(|kinds| kinds.iter().filter_map(Json::as_string).filter_map(Kind::parse).next());
+            match (string(target, "name"), kind, string(target, "src_path")) {
+                (Some(name), Some(kind), Some(root)) => Some(Target {
+                    name: name,
+                    kind: kind,
+                    root: dir.join(root)
+                }),
+                _ => None
+            }
+        }).collect()
+    });
+
+    // Follow the default features through those they enable, which may
+    // also be optional dependencies, or features of dependencies (`dep/feature`).
+    let feature_map = json.find("features").and_then(Json::as_object);
+    let mut features = vec![];
+    let mut enabled_deps = HashSet::new();
+    let mut queue = vec!["default".to_owned()];
+    while let Some(feature) = queue.pop() {
+        let enables = feature_map.and_then(|map| map.get(&feature)).and_then(Json::as_array);
+        match enables {
+            Some(enables) => {
+                if features.contains(&feature) {
+                    continue;
+                }
+                queue.extend(enables.iter().filter_map(Json::as_string).map(str::to_owned));
+                features.push(feature);
+            }
+            None => {
+                let dep = feature.split('/').next().unwrap_or("").to_owned();
+                enabled_deps.insert(dep);
+            }
+        }
+    }
+
+    let dependencies = json.find("dependencies").and_then(Json::as_array).map_or(vec![], |deps| {
+        deps.iter().filter_map(|dep| {
+            string(dep, "name").map(|name| Dependency {
+                optional: dep.find("optional").and_then(Json::as_boolean).unwrap_or(false) &&
+                          !enabled_deps.contains(&name),
+                name: crate_name(&name),
+                dev: string(dep, "kind").map_or(false, |kind| kind == "dev")
+            })
+        }).collect()
+    });
+
+    let target_dir = string(&metadata, "target_directory").map(PathBuf::from)
+        .or_else(|| env::var_os("CARGO_TARGET_DIR").map(PathBuf::from))
+        .unwrap_or(dir.join("target"));
+
+    Ok(Package {
+        manifest: manifest.to_path_buf(),
+        name: try!(string(json, "name").ok_or("package without a name".to_owned())),
+        edition: string(json, "edition"),
+        targets: targets,
+        features: features,
+        dependencies: dependencies,
+        target_dir: target_dir
+    })
+}
+
+/// The most recently built library for the crate `name` in `dir`.
+fn find_rlib(dir: &Path, name: &str) -> Option<PathBuf> {
+    let exact = format!("lib{}.rlib", name);
+    let prefix = format!("lib{}-", name);
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return None
+    };
+    entries.filter_map(|e| e.ok()).filter(|e| {
+        let file_name = e.file_name();
+        let file_name = file_name.to_string_lossy();
+        file_name == exact || (file_name.starts_with(&prefix) && file_name.ends_with(".rlib"))
+    }).filter_map(|e| {
+        e.metadata().and_then(|m| m.modified()).ok().map(|modified| (modified, e.path()))
+    }).max_by_key(|&(modified, _)| modified).map(|(_, path)| path)
+}
+
+impl Package {
+    /// The target `file` is most likely part of: the one it's the root of,
+    /// or else the one whose root is in the closest directory above it,
+    /// preferring the library when several are in the same place.
+    pub fn target_for(&self, file: &Path) -> Option<&Target> {
+        if let Some(target) = self.targets.iter().find(|t| t.root == file) {
+            return Some(target);
+        }
+        self.targets.iter().filter(|t| {
+            t.root.parent().map_or(false, |dir| file.starts_with(dir))
+        }).max_by_key(|t| {
+            let depth = t.root.parent().map_or(0, |dir| dir.components().count());
+            (depth, t.kind == Kind::Lib)
+        })
+    }
+
+    /// How to analyse or build `target`.
+    pub fn crate_for(&self, target: &Target) -> Crate {
+        let deps_dir = self.target_dir.join("debug").join("deps");
+        let mut externs = vec![];
+        {
+            let mut add_extern = |name: &str| {
+                let rlib = find_rlib(&deps_dir, name).or_else(|| find_rlib(&self.target_dir.join("debug"), name));
+                match rlib {
+                    Some(rlib) => externs.push((name.to_owned(), rlib)),
+                    None => println!("couldn't find {} in {}, has it been built?", name, deps_dir.display())
+                }
+            };
+            for dep in &self.dependencies {
+                if !dep.optional && (!dep.dev || target.kind.is_test() || target.kind == Kind::Example) {
+                    add_extern(&dep.name);
+                }
+            }
+            // Everything but the library itself can use it.
+            if target.kind != Kind::Lib && target.kind != Kind::Build {
+                if let Some(lib) = self.targets.iter().find(|t| t.kind == Kind::Lib) {
+                    add_extern(&crate_name(&lib.name));
+                }
+            }
+        }
+
+        let mut cfgs: Vec<_> = self.features.iter().map(|f| format!("feature={:?}", f)).collect();
+        if target.kind.is_test() {
+            cfgs.push("test".to_owned());
+        }
+
+        Crate {
+            manifest: self.manifest.clone(),
+            name: crate_name(&target.name),
+            kind: target.kind,
+            root: target.root.clone(),
+            edition: self.edition.clone(),
+            cfgs: cfgs,
+            externs: externs,
+            deps_dir: deps_dir
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+struct Cached {
+    // When `Cargo.toml` was last changed, as of reading `package`.
+    modified: Option<SystemTime>,
+    package: Package,
+    // Crates by the root of their target, with when the directory
+    // their dependencies were found in was last changed.
+    crates: HashMap<PathBuf, (Option<SystemTime>, Crate)>
+}
+
+/// Packages and crates found so far, by manifest, so `cargo metadata` only
+/// runs again once `Cargo.toml` changes, and the built dependencies are
+/// only looked for again once something was built.
+/// Clones share the same entries, to be handed to other threads.
+#[derive(Clone)]
+pub struct Cache {
+    packages: Arc<Mutex<HashMap<PathBuf, Cached>>>
+}
+
+thread_local! {
+    static CACHE: Cache = Cache {
+        packages: Arc::new(Mutex::new(HashMap::new()))
+    };
+}
+
+/// The cache of the current thread, to use there or pass to another.
+pub fn cache() -> Cache {
+    CACHE.with(|cache| cache.clone())
+}
+
+impl Cache {
+    /// The crate `file` belongs to, if it's in a cargo package.
+    pub fn crate_for(&self, file: &Path) -> Option<Crate> {
+        let file = fs::canonicalize(file).unwrap_or(file.to_path_buf());
+        let manifest = match find_manifest(&file) {
+            Some(manifest) => manifest,
+            None => return None
+        };
+
+        // Held while `cargo metadata` runs, so two threads don't both run it.
+        let mut packages = self.packages.lock().unwrap();
+        let manifest_modified = modified(&manifest);
+        let fresh = packages.get(&manifest).map_or(false, |cached| {
+            manifest_modified.is_some() && cached.modified == manifest_modified
+        });
+        if !fresh {
+            match package(&manifest) {
+                Ok(package) => {
+                    packages.insert(manifest.clone(), Cached {
+                        modified: manifest_modified,
+                        package: package,
+                        crates: HashMap::new()
+                    });
+                }
+                Err(e) => {
+                    packages.remove(&manifest);
+                    println!("couldn't read {}: {}", manifest.display(), e);
+                    return None;
+                }
+            }
+        }
+
+        let cached = packages.get_mut(&manifest).unwrap();
+        let target = match cached.package.target_for(&file) {
+            Some(target) => target,
+            None => return None
+        };
+        let deps_modified = modified(&cached.package.target_dir.join("debug").join("deps"));
+        if let Some(&(when, ref krate)) = cached.crates.get(&target.root) {
+            if deps_modified.is_some() && when == deps_modified {
+                return Some(krate.clone());
+            }
+        }
+        let krate = cached.package.crate_for(target);
+        cached.crates.insert(target.root.clone(), (deps_modified, krate.clone()));
+        Some(krate)
+    }
+}
+
+impl Crate {
+    /// Arguments for cargo to build and run this crate, e.g. `run --bin foo`.
+    pub fn cargo_args(&self) -> Vec<String> {
+        let args: &[&str] = match self.kind {
+            Kind::Lib => &["test", "--lib"],
+            Kind::Bin => &["run", "--bin", &self.name[..]],
+            Kind::Test => &["test", "--test", &self.name[..]],
+            Kind::Example => &["run", "--example", &self.name[..]],
+            Kind::Bench => &["bench", "--bench", &self.name[..]],
+            Kind::Build => &["build"]
+        };
+        args.iter().map(|&arg| arg.to_owned()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use super::{Kind, Package, Target};
+
+    fn package() -> Package {
+        let target = |name: &str, kind, root: &str| Target {
+            name: name.to_owned(),
+            kind: kind,
+            root: PathBuf::from(root)
+        };
+        Package {
+            manifest: PathBuf::from("/p/Cargo.toml"),
+            name: "p".to_owned(),
+            edition: None,
+            targets: vec![
+                target("p", Kind::Bin, "/p/src/main.rs"),
+                target("p", Kind::Lib, "/p/src/lib.rs"),
+                target("tool", Kind::Bin, "/p/src/bin/tool.rs"),
+                target("it", Kind::Test, "/p/tests/it.rs"),
+                target("build-script-build", Kind::Build, "/p/build.rs")
+            ],
+            features: vec![],
+            dependencies: vec![],
+            target_dir: PathBuf::from("/p/target")
+        }
+    }
+
+    fn target_for(file: &str) -> Option<(String, Kind)> {
+        package().target_for(Path::new(file)).map(|t| (t.name.clone(), t.kind))
+    }
+
+    #[test]
+    fn roots_belong_to_their_target() {
+        assert_eq!(target_for("/p/src/main.rs"), Some(("p".to_owned(), Kind::Bin)));
+        assert_eq!(target_for("/p/src/bin/tool.rs"), Some(("tool".to_owned(), Kind::Bin)));
+        assert_eq!(target_for("/p/build.rs"), Some(("build-script-build".to_owned(), Kind::Build)));
+    }
+
+    #[test]
+    fn modules_belong_to_the_closest_target() {
+        assert_eq!(target_for("/p/src/ui/mod.rs"), Some(("p".to_owned(), Kind::Lib)));
+        assert_eq!(target_for("/p/src/bin/tool/args.rs"), Some(("tool".to_owned(), Kind::Bin)));
+        assert_eq!(target_for("/p/tests/common/mod.rs"), Some(("it".to_owned(), Kind::Test)));
+    }
+
+    #[test]
+    fn files_outside_the_package() {
+        assert_eq!(target_for("/q/src/lib.rs"), None);
+    }
+}
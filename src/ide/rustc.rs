@@ -6,25 +6,36 @@ extern crate rustc_metadata;
 extern crate rustc_resolve as resolve;
 extern crate rustc_typeck as typeck;
 
-use self::syntax::codemap::{CodeMap, MultiSpan};
+use self::syntax::ast::{NodeId, CRATE_NODE_ID};
+use self::syntax::codemap::{BytePos, CodeMap, FileLoader, MultiSpan, Span};
 pub use self::syntax::errors::Level;
 use self::syntax::errors::{self, RenderSpan};
 use self::syntax::errors::emitter::Emitter;
 use self::syntax::parse::token;
 use self::rustc::front::map as hir_map;
 use self::rustc::front::map::NodePrinter;
+use self::rustc_front::hir;
 use self::rustc_front::lowering::{lower_crate, LoweringContext};
 use self::rustc_front::print::pprust;
 use self::rustc::session::{self, config};
 use self::rustc_metadata::creader::LocalCrateReader;
 use self::rustc_metadata::cstore::CStore;
 use self::rustc::middle::{self, stability, ty};
+use self::rustc::middle::def::Def;
+use self::rustc::middle::def_id::DefId;
 use self::rustc::dep_graph::DepGraph;
 use self::rustc_driver::driver;
 
+use ide::cargo::{self, Crate};
+use ide::vfs;
+use rope::Rope;
+
 //use std::cell::RefCell;
+use std::cmp::min;
 use std::env;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
 use std::ops::Range;
 use std::path::{Path, PathBuf};
 use std::process::Command;
@@ -64,6 +75,10 @@ pub fn init_env() {
 }
 
 pub fn compile_and_run(path: &Path) {
+    if let Some(krate) = cargo::cache().crate_for(path) {
+        return cargo_run(&krate);
+    }
+
     let path = env::current_dir().unwrap().join(path);
     assert!(path.is_absolute());
     let exe = path.with_extension(EXE_SUFFIX);
@@ -98,15 +113,105 @@ pub fn compile_and_run(path: &Path) {
     }
 }
 
+/// Build and run the target through cargo, so it gets its dependencies.
+fn cargo_run(krate: &Crate) {
+    let mut cmd = Command::new("cargo");
+    cmd.args(&krate.cargo_args()).arg("--manifest-path").arg(&krate.manifest);
+    // See `compile_and_run` for why Windows is different.
+    if cfg!(windows) {
+        match cmd.output() {
+            Err(e) => println!("failed to execute cargo: {}", e),
+            Ok(out) => {
+                println!("{}\n{}", String::from_utf8_lossy(&out.stdout), String::from_utf8_lossy(&out.stderr));
+            }
+        }
+    } else {
+        if let Err(e) = cmd.spawn() {
+            println!("failed to execute cargo: {}", e);
+        }
+    }
+}
+
+/// Reads files from the open buffers, instead of what was last saved.
+struct Loader(vfs::Snapshot);
+
+impl FileLoader for Loader {
+    fn file_exists(&self, path: &Path) -> bool {
+        self.0.exists(path)
+    }
+
+    fn read_file(&self, path: &Path) -> io::Result<String> {
+        self.0.read(path)
+    }
+}
+
+/// How many rows of a definition are shown when peeking at it.
+const PEEK_ROWS: usize = 12;
+
+/// Can't be used as names when renaming.
+const KEYWORDS: &'static [&'static str] = &[
+    "abstract", "alignof", "as", "become", "box", "break", "const", "continue",
+    "crate", "do", "else", "enum", "extern", "false", "final", "fn", "for", "if",
+    "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "offsetof",
+    "override", "priv", "proc", "pub", "pure", "ref", "return", "self", "Self",
+    "sizeof", "static", "struct", "super", "trait", "true", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield"
+];
+
+/// In scope everywhere, through the standard prelude.
+const PRELUDE: &'static [&'static str] = &[
+    "Copy", "Send", "Sized", "Sync", "Drop", "Fn", "FnMut", "FnOnce", "drop", "Box",
+    "ToOwned", "Clone", "PartialEq", "PartialOrd", "Eq", "Ord", "AsRef", "AsMut",
+    "Into", "From", "Default", "Iterator", "Extend", "IntoIterator",
+    "DoubleEndedIterator", "ExactSizeIterator", "Option", "Some", "None", "Result",
+    "Ok", "Err", "SliceConcatExt", "String", "ToString", "Vec"
+];
+
+/// Offered when completing, those of `KEYWORDS` that mean something.
+const COMPLETION_KEYWORDS: &'static [&'static str] = &[
+    "as", "break", "const", "continue", "crate", "else", "enum", "extern", "false",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut",
+    "pub", "ref", "return", "self", "Self", "static", "struct", "super", "trait",
+    "true", "type", "unsafe", "use", "where", "while"
+];
+
 enum Req {
-    TypesAtOffset(usize, Range<usize>)
+    TypesAtOffset(usize, Range<usize>),
+    DefinitionAt(usize),
+    // With the new name, when renaming.
+    ReferencesAt(usize, Option<String>),
+    // With the expression before the `.`, when completing a field or method.
+    CompletionsAt(usize, Option<String>)
 }
 
 enum Res {
     Done,
     Aborted,
     Diagnostic(Diagnostic),
-    TypesAtOffset(usize, Vec<(Range<usize>, String)>)
+    TypesAtOffset(usize, Vec<(Range<usize>, String)>),
+    DefinitionAt(usize, Option<Definition>),
+    ReferencesAt(usize, Result<(String, Vec<Reference>), String>),
+    CompletionsAt(usize, Vec<Completion>)
+}
+
+/// Where something used in the analysed file is defined.
+#[derive(Clone, Debug)]
+pub struct Definition {
+    /// The file it's in, `None` if it's the analysed file itself.
+    pub path: Option<PathBuf>,
+    pub row: usize,
+    /// Byte offset in `row`.
+    pub offset: usize,
+    /// The first few rows of the definition, to peek at.
+    pub preview: Vec<String>
+}
+
+/// Something that could be typed where completions were asked for.
+#[derive(Clone, Debug)]
+pub struct Completion {
+    pub name: String,
+    /// Its type, or how it's declared, if known.
+    pub signature: String
 }
 
 struct Diagnostic {
@@ -119,31 +224,53 @@ struct Diagnostic {
 struct ErrorLogger {
     tx: Sender<Res>,
     codemap: Rc<CodeMap>,
+    // The edited file, if the whole crate is being analysed.
+    path: Option<PathBuf>,
     file_end: usize
 }
 
+impl ErrorLogger {
+    /// Where `sp` is in the edited file, or the start of the file and a prefix
+    /// for the message, if it's in another file of the crate.
+    fn locate(&self, sp: Span) -> (usize, usize, String) {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => {
+                if sp.hi.0 as usize <= self.file_end {
+                    let pos = self.codemap.lookup_char_pos(sp.lo);
+                    return (pos.line - 1, pos.col.0, String::new());
+                }
+                return (0, 0, String::new());
+            }
+        };
+        let pos = self.codemap.lookup_char_pos(sp.lo);
+        if is_same_file(&pos.file.name, path) {
+            (pos.line - 1, pos.col.0, String::new())
+        } else {
+            (0, 0, format!("{}:{}: ", pos.file.name, pos.line))
+        }
+    }
+}
+
+fn is_same_file(name: &str, path: &Path) -> bool {
+    fs::canonicalize(name).map_or(false, |name| name == path)
+}
+
 impl Emitter for ErrorLogger {
     fn emit(&mut self, span: Option<&MultiSpan>,
             msg: &str, _code: Option<&str>, lvl: Level) {
         if msg.starts_with("aborting due to ") {
             return;
         }
-        let (line, col) = match span.map(|s| s.to_span_bounds()) {
-            Some(sp) => {
-                if sp.hi.0 as usize <= self.file_end {
-                    let pos = self.codemap.lookup_char_pos(sp.lo);
-                    (pos.line - 1, pos.col.0)
-                } else {
-                    (0, 0)
-                }
-            }
-            None => (0, 0)
+        let (line, col, prefix) = match span.map(|s| s.to_span_bounds()) {
+            Some(sp) => self.locate(sp),
+            None => (0, 0, String::new())
         };
         let _ = self.tx.send(Res::Diagnostic(Diagnostic {
             line: line,
             col: col,
             level: lvl,
-            message: msg.to_owned()
+            message: prefix + msg
         }));
     }
 
@@ -158,27 +285,794 @@ impl Emitter for ErrorLogger {
     }
 }
 
-fn rustc_thread(input: String, mut lifeline: Arc<()>,
-                rx: Receiver<Req>, tx: Sender<Res>,
+/// A use of something, or where it's declared, found by `Rustc::references_at`.
+#[derive(Clone, Debug)]
+pub struct Reference {
+    /// The file it's in, `None` if it's the analysed file itself.
+    pub path: Option<PathBuf>,
+    pub row: usize,
+    /// Byte range of the name in `row`.
+    pub range: Range<usize>,
+    pub line: String
+}
+
+/// Something that can be referred to by name.
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum Target {
+    // A local variable, or a label.
+    Node(NodeId),
+    Item(DefId),
+    // The struct and its field.
+    Field(DefId, DefId)
+}
+
+fn def_target(def: Def) -> Option<Target> {
+    match def {
+        Def::Local(_, id) | Def::Upvar(_, id, _, _) | Def::Label(id) => Some(Target::Node(id)),
+        Def::PrimTy(..) | Def::SelfTy(..) | Def::Err => None,
+        _ => Some(Target::Item(def.def_id()))
+    }
+}
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn is_ident(name: &str) -> bool {
+    name.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_') &&
+    name.chars().all(is_ident_char) && name != "_"
+}
+
+/// Where `name` is in `text`, as a whole word.
+fn find_word(text: &str, name: &str) -> Vec<Range<usize>> {
+    text.match_indices(name).map(|(i, _)| i..i + name.len()).filter(|r| {
+        !text[..r.start].chars().next_back().map_or(false, is_ident_char) &&
+        !text[r.end..].chars().next().map_or(false, is_ident_char)
+    }).collect()
+}
+
+/// The identifier around `pos`, and where it is.
+fn ident_at(codemap: &CodeMap, pos: usize) -> Option<(String, Range<usize>)> {
+    let found = codemap.lookup_byte_offset(BytePos(pos as u32));
+    let src = match found.fm.src {
+        Some(ref src) => src.clone(),
+        None => return None
+    };
+    let i = found.pos.0 as usize;
+    let start = src[..i].char_indices().rev().take_while(|&(_, c)| is_ident_char(c))
+                        .last().map_or(i, |(j, _)| j);
+    let end = src[i..].char_indices().find(|&(_, c)| !is_ident_char(c)).map_or(src.len(), |(j, _)| i + j);
+    if start == end {
+        return None;
+    }
+    let base = pos - i;
+    Some((src[start..end].to_owned(), base + start..base + end))
+}
+
+/// Where `name` is in `sp`, the last (for uses, e.g. `a::name`) or
+/// first (for declarations, e.g. `fn name()`) time it appears there.
+fn name_in(codemap: &CodeMap, sp: Span, name: &str, last: bool) -> Option<Range<usize>> {
+    let snippet = match codemap.span_to_snippet(sp) {
+        Ok(snippet) => snippet,
+        Err(_) => return None
+    };
+    let found = find_word(&snippet, name);
+    let r = if last { found.last() } else { found.first() };
+    r.map(|r| sp.lo.0 as usize + r.start..sp.lo.0 as usize + r.end)
+}
+
+/// Everything referring to something by name in the crate, with the node doing so.
+fn uses(tcx: &ty::ctxt) -> Vec<(NodeId, Span, Target)> {
+    let mut out = vec![];
+    for (&id, res) in tcx.def_map.borrow().iter() {
+        // Only paths resolved all the way, not e.g. `T::f` before type-checking.
+        if res.depth > 0 {
+            continue;
+        }
+        if let (Some(sp), Some(target)) = (tcx.map.opt_span(id), def_target(res.base_def)) {
+            out.push((id, sp, target));
+        }
+    }
+
+    // Methods and fields are only known after type-checking.
+    let ids: Vec<NodeId> = tcx.node_types().keys().cloned().collect();
+    for id in ids {
+        let expr = match tcx.map.find(id) {
+            Some(hir_map::NodeExpr(expr)) => expr,
+            _ => continue
+        };
+        match expr.node {
+            hir::ExprMethodCall(ref name, _, _) => {
+                let callee = tcx.tables.borrow().method_map.get(&ty::MethodCall::expr(id)).map(|c| c.def_id);
+                if let Some(def_id) = callee {
+                    out.push((id, name.span, Target::Item(def_id)));
+                }
+            }
+            hir::ExprField(ref base, ref name) => {
+                if let ty::TyStruct(adt, _) = tcx.expr_ty_adjusted(base).sty {
+                    if let Some(field) = adt.struct_variant().find_field_named(name.node) {
+                        out.push((id, name.span, Target::Field(adt.did, field.did)));
+                    }
+                }
+            }
+            hir::ExprStruct(_, ref fields, _) => {
+                if let ty::TyStruct(adt, _) = tcx.expr_ty(expr).sty {
+                    for field in fields {
+                        if let Some(def) = adt.struct_variant().find_field_named(field.name.node) {
+                            out.push((id, field.name.span, Target::Field(adt.did, def.did)));
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    out
+}
+
+/// The node declaring `target`, and its span, if it's in this crate.
+fn declaration(tcx: &ty::ctxt, target: Target) -> Option<(NodeId, Span)> {
+    let (id, sp) = match target {
+        Target::Node(id) => (id, tcx.map.opt_span(id)),
+        Target::Item(def_id) => {
+            match tcx.map.as_local_node_id(def_id) {
+                Some(id) => (id, tcx.map.opt_span(id)),
+                None => return None
+            }
+        }
+        // Fields are found through their struct.
+        Target::Field(adt, field) => {
+            let (adt, field) = match (tcx.map.as_local_node_id(adt), tcx.map.as_local_node_id(field)) {
+                (Some(adt), Some(field)) => (adt, field),
+                _ => return None
+            };
+            let sp = match tcx.map.find(adt) {
+                Some(hir_map::NodeItem(item)) => {
+                    match item.node {
+                        hir::ItemStruct(ref data, _) => {
+                            data.fields().iter().find(|f| f.node.id == field).map(|f| f.span)
+                        }
+                        _ => None
+                    }
+                }
+                _ => None
+            };
+            (adt, sp)
+        }
+    };
+    sp.map(|sp| (id, sp))
+}
+
+/// `target` and, if it's a method (or other associated item) of a trait,
+/// the trait's and every impl's one of the same name, as they can only be
+/// renamed together. Fails for those of traits from other crates.
+fn with_trait_items(tcx: &ty::ctxt, target: Target) -> Result<Vec<Target>, String> {
+    let (did, id) = match target {
+        Target::Item(did) => match tcx.map.as_local_node_id(did) {
+            Some(id) => (did, id),
+            None => return Ok(vec![target])
+        },
+        _ => return Ok(vec![target])
+    };
+    let parent = tcx.map.local_def_id(tcx.map.get_parent(id));
+    let trait_did = match tcx.map.find(id) {
+        Some(hir_map::NodeTraitItem(_)) => parent,
+        Some(hir_map::NodeImplItem(_)) => match tcx.impl_trait_ref(parent) {
+            Some(trait_ref) => trait_ref.def_id,
+            None => return Ok(vec![target])
+        },
+        _ => return Ok(vec![target])
+    };
+    let name = tcx.impl_or_trait_item(did).name();
+    if tcx.map.as_local_node_id(trait_did).is_none() {
+        return Err(format!("`{}` is part of implementing `{}`, which isn't declared in this crate",
+                           name, tcx.item_path_str(trait_did)));
+    }
+
+    let mut out: Vec<_> = tcx.trait_items(trait_did).iter().filter(|item| item.name() == name)
+                             .map(|item| Target::Item(item.def_id())).collect();
+    for item in tcx.map.krate().items.values() {
+        if let hir::ItemImpl(_, _, _, Some(_), _, _) = item.node {
+            let impl_did = tcx.map.local_def_id(item.id);
+            if tcx.impl_trait_ref(impl_did).map(|trait_ref| trait_ref.def_id) != Some(trait_did) {
+                continue;
+            }
+            let items = tcx.impl_items.borrow().get(&impl_did).cloned().unwrap_or(vec![]);
+            for item in items {
+                if tcx.impl_or_trait_item(item.def_id()).name() == name {
+                    out.push(Target::Item(item.def_id()));
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Whether an associated item named `new_name` is already next to one of
+/// `targets`, in the same trait or impl, or among the methods of the impl's type.
+fn member_clash(tcx: &ty::ctxt, targets: &[Target], new_name: &str) -> bool {
+    let new_name = token::intern(new_name);
+    targets.iter().any(|&target| {
+        let parent = match target {
+            Target::Item(did) => tcx.map.as_local_node_id(did).map(|id| tcx.map.get_parent(id)),
+            _ => None
+        };
+        let parent = match parent {
+            Some(parent) => parent,
+            None => return false
+        };
+        let parent_did = tcx.map.local_def_id(parent);
+        match tcx.map.find(parent) {
+            Some(hir_map::NodeItem(&hir::Item { node: hir::ItemTrait(..), .. })) => {
+                tcx.trait_items(parent_did).iter().any(|item| item.name() == new_name)
+            }
+            Some(hir_map::NodeItem(&hir::Item { node: hir::ItemImpl(..), .. })) => {
+                let items = tcx.impl_items.borrow().get(&parent_did).cloned().unwrap_or(vec![]);
+                items.iter().any(|item| tcx.impl_or_trait_item(item.def_id()).name() == new_name) ||
+                methods(tcx, tcx.lookup_item_type(parent_did).ty).iter().any(|m| m.name == new_name)
+            }
+            _ => false
+        }
+    })
+}
+
+/// Where a path made of just `name` resolves to something other than
+/// a local variable, e.g. to a glob import or something in the prelude.
+fn resolved_spans(tcx: &ty::ctxt, name: &str) -> Vec<Span> {
+    let codemap = tcx.sess.codemap();
+    let def_map = tcx.def_map.borrow();
+    let found = def_map.iter().filter(|&(_, res)| {
+        res.depth == 0 && match res.base_def {
+            Def::Local(..) | Def::Upvar(..) | Def::Label(..) | Def::Err => false,
+            _ => true
+        }
+    }).filter_map(|(&id, _)| tcx.map.opt_span(id)).filter(|&sp| {
+        codemap.span_to_snippet(sp).ok().map_or(false, |snippet| snippet == name)
+    }).collect();
+    found
+}
+
+/// Whether `name` already means something at `pos`: a local variable or an
+/// item in scope there, or whatever it resolves to (`resolved`) in the module.
+fn in_scope_at(tcx: &ty::ctxt, pos: usize, name: &str, resolved: &[Span]) -> bool {
+    if locals_at(tcx, pos).iter().any(|&(ref local, _)| local == name) {
+        return true;
+    }
+    if items_at(tcx, pos).iter().any(|item| item.name == name) {
+        return true;
+    }
+    let (_, module) = module_at(tcx, pos);
+    resolved.iter().any(|sp| module.lo <= sp.lo && sp.hi <= module.hi)
+}
+
+/// Where everything referring to the same thing as the identifier at `pos`
+/// is, including its declaration, if it's in this crate. When renaming,
+/// fails if `new_name` already means something where the declaration or
+/// a use is, or for a trait's methods if not all of them can be renamed.
+fn references(tcx: &ty::ctxt, pos: usize, new_name: Option<&str>)
+              -> Result<(String, Vec<Range<usize>>), String> {
+    let codemap = tcx.sess.codemap();
+    let name = match ident_at(codemap, pos) {
+        Some((name, _)) => name,
+        None => return Err("there's no name there".to_owned())
+    };
+
+    let uses: Vec<_> = uses(tcx).into_iter().filter_map(|(id, sp, target)| {
+        name_in(codemap, sp, &name, true).map(|r| (id, r, target))
+    }).collect();
+    let at = |r: &Range<usize>| r.start <= pos && pos <= r.end;
+    let target = uses.iter().find(|&&(_, ref r, _)| at(r)).map(|&(_, _, target)| target).or_else(|| {
+        // Not a use, maybe the declaration of something used.
+        uses.iter().map(|&(_, _, target)| target).find(|&target| {
+            declaration(tcx, target).and_then(|(_, sp)| name_in(codemap, sp, &name, false)).map_or(false, |r| at(&r))
+        })
+    });
+    let target = match target {
+        Some(target) => target,
+        None => return Err(format!("couldn't find what `{}` refers to", name))
+    };
+    let targets = match (with_trait_items(tcx, target), new_name) {
+        (Ok(targets), _) => targets,
+        (Err(e), Some(_)) => return Err(e),
+        (Err(_), None) => vec![target]
+    };
+
+    let mut found: Vec<_> = uses.iter().filter(|&&(_, _, t)| targets.contains(&t)).map(|&(_, ref r, _)| r.clone()).collect();
+    let mut declared = false;
+    for &t in &targets {
+        let r = declaration(tcx, t).and_then(|(_, sp)| name_in(codemap, sp, &name, false));
+        if let Some(r) = r {
+            declared |= t == target;
+            found.push(r);
+        }
+    }
+
+    if let Some(new_name) = new_name {
+        if !is_ident(new_name) || KEYWORDS.contains(&new_name) {
+            return Err(format!("`{}` isn't a valid name", new_name));
+        }
+        if !declared {
+            return Err(format!("`{}` isn't declared in this crate", name));
+        }
+        // Fields and associated items only clash with those of the same type,
+        // anything else with whatever is in scope wherever it's used.
+        let is_member = declaration(tcx, target).map_or(false, |(id, _)| match tcx.map.find(id) {
+            Some(hir_map::NodeImplItem(_)) | Some(hir_map::NodeTraitItem(_)) => true,
+            _ => false
+        });
+        let clash = match target {
+            Target::Field(adt, _) => {
+                tcx.lookup_adt_def(adt).struct_variant().find_field_named(token::intern(new_name)).is_some()
+            }
+            _ if is_member => member_clash(tcx, &targets, new_name),
+            _ => {
+                if PRELUDE.contains(&new_name) {
+                    return Err(format!("`{}` would hide the one in the prelude", new_name));
+                }
+                let resolved = resolved_spans(tcx, new_name);
+                found.iter().any(|r| in_scope_at(tcx, r.start, new_name, &resolved))
+            }
+        };
+        if clash {
+            return Err(format!("`{}` is already used where `{}` is", new_name, name));
+        }
+    }
+
+    found.sort_by(|a, b| a.start.cmp(&b.start));
+    found.dedup();
+    Ok((name, found))
+}
+
+/// Turn a range of the crate's source into a `Reference`.
+fn reference(codemap: &CodeMap, r: Range<usize>, path: Option<&Path>) -> Reference {
+    let pos = codemap.lookup_char_pos(BytePos(r.start as u32));
+    let row = pos.line - 1;
+    let line_start = pos.file.lines.borrow()[row].0 as usize;
+    Reference {
+        path: match path {
+            Some(path) if !is_same_file(&pos.file.name, path) => Some(PathBuf::from(&pos.file.name)),
+            _ => None
+        },
+        row: row,
+        range: r.start - line_start..r.end - line_start,
+        line: pos.file.get_line(row).unwrap_or(String::new())
+    }
+}
+
+/// What the path (or local variable, etc.) at `pos` refers to, picking
+/// the innermost one when several contain it, e.g. `a` in `a::b`.
+fn def_at(tcx: &ty::ctxt, pos: usize) -> Option<Def> {
+    let def_map = tcx.def_map.borrow();
+    let found = def_map.iter().filter_map(|(&id, res)| {
+        tcx.map.opt_span(id).map(|sp| (sp, res.base_def))
+    }).filter(|&(sp, _)| {
+        sp.lo.0 as usize <= pos && pos <= sp.hi.0 as usize
+    }).min_by_key(|&(sp, _)| sp.hi.0 - sp.lo.0).map(|(_, def)| def);
+    found
+}
+
+/// The node defining `def`, if it's in this crate.
+fn def_node(tcx: &ty::ctxt, def: Def) -> Option<NodeId> {
+    match def_target(def) {
+        Some(Target::Node(id)) => Some(id),
+        Some(Target::Item(def_id)) => tcx.map.as_local_node_id(def_id),
+        _ => None
+    }
+}
+
+/// Where the node `id` is, given `path` for the analysed file, if it has one.
+fn definition(tcx: &ty::ctxt, id: NodeId, path: Option<&Path>) -> Option<Definition> {
+    let sp = match tcx.map.opt_span(id) {
+        Some(sp) => sp,
+        None => return None
+    };
+    let codemap = tcx.sess.codemap();
+    let (lo, hi) = (codemap.lookup_char_pos(sp.lo), codemap.lookup_char_pos(sp.hi));
+    let row = lo.line - 1;
+    let line_start = lo.file.lines.borrow()[row];
+    let end = min(hi.line, row + PEEK_ROWS);
+    Some(Definition {
+        path: match path {
+            Some(path) if !is_same_file(&lo.file.name, path) => Some(PathBuf::from(&lo.file.name)),
+            _ => None
+        },
+        row: row,
+        offset: (sp.lo.0 - line_start.0) as usize,
+        preview: (row..end).filter_map(|i| lo.file.get_line(i)).collect()
+    })
+}
+
+/// How `ty` is shown to users, without the paths of common types.
+fn clean_ty(ty: ty::Ty) -> String {
+    let clean = regex!(concat![r"\b(",
+        "core::(option::Option|result::Result)|",
+        "collections::(vec::Vec|string::String)",
+    r")\b"]);
+    clean.replace_all(&ty.to_string(), |c: &::regex::Captures| {
+        c.at(0).unwrap().split(':').next_back().unwrap().to_owned()
+    })
+}
+
+fn fn_signature(sig: &ty::FnSig) -> String {
+    let inputs: Vec<_> = sig.inputs.iter().map(|&ty| clean_ty(ty)).collect();
+    let output = match sig.output {
+        ty::FnConverging(ty) if ty.is_nil() => String::new(),
+        ty::FnConverging(ty) => format!(" -> {}", clean_ty(ty)),
+        ty::FnDiverging => " -> !".to_owned()
+    };
+    format!("fn({}){}", inputs.join(", "), output)
+}
+
+/// The declaration in `snippet`, without attributes or a body, on one line.
+fn signature(snippet: &str) -> String {
+    let lines: Vec<_> = snippet.lines().map(str::trim).skip_while(|line| {
+        line.starts_with('#') || line.starts_with("//")
+    }).collect();
+    let decl = lines.join(" ");
+    let end = decl.find(|c: char| c == '{' || c == ';' || c == '=').unwrap_or(decl.len());
+    let words: Vec<_> = decl[..end].split_whitespace().collect();
+    words.join(" ")
+}
+
+/// The block, function or expression (e.g. a closure or `match`) that
+/// the names bound by the pattern `id` can be used in.
+fn scope_of(tcx: &ty::ctxt, mut id: NodeId) -> Option<Span> {
+    loop {
+        let parent = tcx.map.get_parent_node(id);
+        if parent == id {
+            return None;
+        }
+        match tcx.map.find(parent) {
+            Some(hir_map::NodeBlock(_)) | Some(hir_map::NodeExpr(_)) |
+            Some(hir_map::NodeItem(_)) | Some(hir_map::NodeImplItem(_)) |
+            Some(hir_map::NodeTraitItem(_)) => return tcx.map.opt_span(parent),
+            None => return None,
+            _ => id = parent
+        }
+    }
+}
+
+/// Local variables that can be used at `pos`, closest declared first.
+fn locals_at<'tcx>(tcx: &ty::ctxt<'tcx>, pos: usize) -> Vec<(String, ty::Ty<'tcx>)> {
+    let mut found = vec![];
+    for (&id, &ty) in tcx.node_types().iter() {
+        let pat = match tcx.map.find(id) {
+            Some(hir_map::NodeLocal(pat)) => pat,
+            _ => continue
+        };
+        let name = match pat.node {
+            hir::PatIdent(_, ref ident, _) => ident.node.name,
+            _ => continue
+        };
+        let in_scope = scope_of(tcx, id).map_or(false, |sp| {
+            sp.lo.0 as usize <= pos && pos <= sp.hi.0 as usize
+        });
+        if in_scope && pat.span.hi.0 as usize <= pos {
+            found.push((pat.span.lo.0, name.to_string(), ty));
+        }
+    }
+    found.sort_by(|a, b| b.0.cmp(&a.0));
+    found.into_iter().map(|(_, name, ty)| (name, ty)).collect()
+}
+
+/// The type behind any references or boxes.
+fn autoderef<'tcx>(mut ty: ty::Ty<'tcx>) -> ty::Ty<'tcx> {
+    loop {
+        ty = match ty.sty {
+            ty::TyRef(_, mt) => mt.ty,
+            ty::TyBox(inner) => inner,
+            _ => return ty
+        };
+    }
+}
+
+/// Methods of the struct or enum `ty`, from its own impls and trait impls
+/// in this crate (those in other crates aren't looked for).
+fn methods<'tcx>(tcx: &ty::ctxt<'tcx>, ty: ty::Ty<'tcx>) -> Vec<Rc<ty::Method<'tcx>>> {
+    let did = match autoderef(ty).sty {
+        ty::TyStruct(adt, _) | ty::TyEnum(adt, _) => adt.did,
+        _ => return vec![]
+    };
+    tcx.populate_inherent_implementations_for_type_if_necessary(did);
+    let mut impls = tcx.inherent_impls.borrow().get(&did).map_or(vec![], |impls| (**impls).clone());
+    for item in tcx.map.krate().items.values() {
+        if let hir::ItemImpl(_, _, _, Some(_), _, _) = item.node {
+            let impl_did = tcx.map.local_def_id(item.id);
+            let self_did = match tcx.lookup_item_type(impl_did).ty.sty {
+                ty::TyStruct(adt, _) | ty::TyEnum(adt, _) => Some(adt.did),
+                _ => None
+            };
+            if self_did == Some(did) {
+                impls.push(impl_did);
+            }
+        }
+    }
+
+    let mut out = vec![];
+    for impl_did in impls {
+        let items = tcx.impl_items.borrow().get(&impl_did).cloned().unwrap_or(vec![]);
+        for item in items {
+            if let ty::MethodTraitItem(method) = tcx.impl_or_trait_item(item.def_id()) {
+                out.push(method);
+            }
+        }
+    }
+    out
+}
+
+/// The type of the field `name` of `ty`, or of calling its method `name`.
+fn member_type<'tcx>(tcx: &ty::ctxt<'tcx>, ty: ty::Ty<'tcx>, name: &str, call: bool) -> Option<ty::Ty<'tcx>> {
+    let name = token::intern(name);
+    if call {
+        methods(tcx, ty).into_iter().find(|m| m.name == name).and_then(|m| {
+            match m.fty.sig.0.output {
+                ty::FnConverging(ty) => Some(ty),
+                ty::FnDiverging => None
+            }
+        })
+    } else {
+        match autoderef(ty).sty {
+            ty::TyStruct(adt, substs) => adt.struct_variant().find_field_named(name).map(|f| f.ty(tcx, substs)),
+            _ => None
+        }
+    }
+}
+
+/// The type of `receiver`, a local variable followed by any fields
+/// and method calls, e.g. `self.items.iter()`.
+fn receiver_type<'tcx>(tcx: &ty::ctxt<'tcx>, locals: &[(String, ty::Ty<'tcx>)], receiver: &str)
+                       -> Option<ty::Ty<'tcx>> {
+    // Split on the dots outside of arguments and indices.
+    let mut segments = vec![];
+    let (mut depth, mut start) = (0, 0);
+    for (i, c) in receiver.char_indices() {
+        match c {
+            '(' | '[' => depth += 1,
+            ')' | ']' => depth -= 1,
+            '.' if depth == 0 => {
+                segments.push(&receiver[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    segments.push(&receiver[start..]);
+
+    let mut ty = None;
+    for (i, segment) in segments.into_iter().enumerate() {
+        let end = segment.find(|c: char| c == '(' || c == '[').unwrap_or(segment.len());
+        // What indexing returns isn't known.
+        if segment[end..].starts_with('[') {
+            return None;
+        }
+        let (name, call) = (segment[..end].trim(), end < segment.len());
+        ty = match ty {
+            None if i == 0 && !call => locals.iter().find(|&&(ref local, _)| local == name).map(|&(_, ty)| ty),
+            Some(ty) => member_type(tcx, ty, name, call),
+            None => None
+        };
+        if ty.is_none() {
+            return None;
+        }
+    }
+    ty
+}
+
+/// Fields and methods that can follow a `.` after something of type `ty`.
+fn members<'tcx>(tcx: &ty::ctxt<'tcx>, ty: ty::Ty<'tcx>) -> Vec<Completion> {
+    let mut out = vec![];
+    if let ty::TyStruct(adt, substs) = autoderef(ty).sty {
+        for field in &adt.struct_variant().fields {
+            out.push(Completion {
+                name: field.name.to_string(),
+                signature: clean_ty(field.ty(tcx, substs))
+            });
+        }
+    }
+    for method in methods(tcx, ty) {
+        // Associated functions are called on the type instead.
+        if let ty::StaticExplicitSelfCategory = method.explicit_self {
+            continue;
+        }
+        out.push(Completion {
+            name: method.name.to_string(),
+            signature: fn_signature(&method.fty.sig.0)
+        });
+    }
+    out
+}
+
+/// The innermost module around `pos`, and the span of its contents,
+/// which is in another file for `mod foo;`.
+fn module_at(tcx: &ty::ctxt, pos: usize) -> (NodeId, Span) {
+    let krate = tcx.map.krate();
+    let found = krate.items.values().filter_map(|item| match item.node {
+        hir::ItemMod(ref m) if m.inner.lo.0 as usize <= pos && pos <= m.inner.hi.0 as usize => {
+            Some((item.id, m.inner))
+        }
+        _ => None
+    }).min_by_key(|&(_, sp)| sp.hi.0 - sp.lo.0);
+    found.unwrap_or((CRATE_NODE_ID, krate.module.inner))
+}
+
+/// Items declared (or imported) in the module around `pos`,
+/// or in the functions around it.
+fn items_at(tcx: &ty::ctxt, pos: usize) -> Vec<Completion> {
+    let codemap = tcx.sess.codemap();
+    let items = &tcx.map.krate().items;
+    // Modules in other files are declared elsewhere, their contents are what matters.
+    let scope_span = |item: &hir::Item| match item.node {
+        hir::ItemMod(ref m) => m.inner,
+        _ => item.span
+    };
+    let around: Vec<_> = items.values().filter(|&item| {
+        let sp = scope_span(item);
+        sp.lo.0 as usize <= pos && pos <= sp.hi.0 as usize
+    }).collect();
+    let (module, _) = module_at(tcx, pos);
+    let mut scopes: Vec<_> = around.iter().map(|item| item.id).collect();
+    scopes.push(module);
+
+    let mut out = vec![];
+    for item in items.values() {
+        if !scopes.contains(&tcx.map.get_parent(item.id)) {
+            continue;
+        }
+        let decl = match codemap.span_to_snippet(item.span) {
+            Ok(snippet) => signature(&snippet),
+            Err(_) => continue
+        };
+        match item.node {
+            hir::ItemImpl(..) | hir::ItemDefaultImpl(..) | hir::ItemForeignMod(..) => {}
+            hir::ItemUse(ref view_path) => {
+                match view_path.node {
+                    hir::ViewPathSimple(name, _) => {
+                        out.push(Completion { name: name.to_string(), signature: decl });
+                    }
+                    hir::ViewPathList(_, ref list) => {
+                        for entry in list {
+                            if let hir::PathListIdent { name, rename, .. } = entry.node {
+                                out.push(Completion {
+                                    name: rename.unwrap_or(name).to_string(),
+                                    signature: decl.clone()
+                                });
+                            }
+                        }
+                    }
+                    hir::ViewPathGlob(_) => {}
+                }
+            }
+            _ => out.push(Completion { name: item.name.to_string(), signature: decl })
+        }
+    }
+    out
+}
+
+/// A short description of `def`, its type if it has one.
+fn def_signature(tcx: &ty::ctxt, def: Def) -> String {
+    let kind = match def {
+        Def::Fn(did) | Def::Method(did) => {
+            return match tcx.lookup_item_type(did).ty.sty {
+                ty::TyBareFn(_, f) => fn_signature(&f.sig.0),
+                _ => "fn".to_owned()
+            };
+        }
+        Def::Const(did) | Def::Static(did, _) | Def::AssociatedConst(did) => {
+            return clean_ty(tcx.lookup_item_type(did).ty);
+        }
+        Def::Struct(..) => "struct",
+        Def::Enum(..) => "enum",
+        Def::Variant(..) => "variant",
+        Def::Trait(..) => "trait",
+        Def::Mod(..) | Def::ForeignMod(..) => "mod",
+        Def::TyAlias(..) | Def::AssociatedTy(..) => "type",
+        Def::PrimTy(..) => "primitive",
+        _ => ""
+    };
+    kind.to_owned()
+}
+
+/// Whatever is referred to by a plain name somewhere in the crate, which
+/// covers the prelude and imports from other crates as far as they're used.
+fn used_names(tcx: &ty::ctxt) -> Vec<Completion> {
+    let codemap = tcx.sess.codemap();
+    let mut out = vec![];
+    for (&id, res) in tcx.def_map.borrow().iter() {
+        if res.depth > 0 {
+            continue;
+        }
+        // Those only make sense where they're declared.
+        match res.base_def {
+            Def::Local(..) | Def::Upvar(..) | Def::Label(..) | Def::TyParam(..) |
+            Def::SelfTy(..) | Def::Err => continue,
+            _ => {}
+        }
+        let name = tcx.map.opt_span(id).and_then(|sp| codemap.span_to_snippet(sp).ok());
+        if let Some(name) = name {
+            if is_ident(&name) {
+                out.push(Completion {
+                    name: name,
+                    signature: def_signature(tcx, res.base_def)
+                });
+            }
+        }
+    }
+    out
+}
+
+/// What could be typed at `pos`, after `receiver` and a `.` if given,
+/// closest first (locals, then items, then everything else).
+fn completions(tcx: &ty::ctxt, pos: usize, receiver: Option<&str>) -> Vec<Completion> {
+    let locals = locals_at(tcx, pos);
+    let mut all = match receiver {
+        Some(receiver) => receiver_type(tcx, &locals, receiver).map_or(vec![], |ty| members(tcx, ty)),
+        None => {
+            let mut all: Vec<_> = locals.iter().map(|&(ref name, ty)| Completion {
+                name: name.clone(),
+                signature: clean_ty(ty)
+            }).collect();
+            all.extend(items_at(tcx, pos));
+            all.extend(used_names(tcx));
+            all.extend(COMPLETION_KEYWORDS.iter().map(|&keyword| Completion {
+                name: keyword.to_owned(),
+                signature: String::new()
+            }));
+            all
+        }
+    };
+    // Only the first of each name, e.g. a local rather than the item it shadows.
+    let mut seen = HashSet::new();
+    all.retain(|c| seen.insert(c.name.clone()));
+    all
+}
+
+fn rustc_thread(input: Rope, files: vfs::Snapshot, path: Option<PathBuf>, krate: Option<Crate>,
+                mut lifeline: Arc<()>, rx: Receiver<Req>, tx: Sender<Res>,
                 file_end: usize) -> Result<(), usize> {
     macro_rules! still_alive {
         () => (lifeline = match Weak::upgrade(&Arc::downgrade(&{lifeline})) {
                 Some(x) => x, None => return Ok(())
         })
     }
-    let input = config::Input::Str(input);
 
     let rustc_dir_path = get_rustc_dir_path();
 
-    let sessopts = config::Options {
+    let mut sessopts = config::Options {
         maybe_sysroot: Some(rustc_dir_path.parent().unwrap().to_path_buf()),
         ..config::basic_options().clone()
     };
 
-    let codemap = Rc::new(CodeMap::new());
+    // Analyse the whole crate the file is part of, as cargo would build it,
+    // with the buffer standing in for the file. The edition isn't passed on,
+    // this compiler predates them.
+    let (input, crate_name, codemap, path) = match (krate, path) {
+        (Some(krate), Some(path)) => {
+            sessopts.test = krate.kind.is_test();
+            sessopts.cfg = config::parse_cfgspecs(krate.cfgs.clone());
+            sessopts.crate_name = Some(krate.name.clone());
+            sessopts.crate_types = vec![if krate.kind == cargo::Kind::Lib {
+                config::CrateTypeRlib
+            } else {
+                config::CrateTypeExecutable
+            }];
+            for &(ref name, ref rlib) in &krate.externs {
+                sessopts.externs.entry(name.clone()).or_insert(vec![]).push(rlib.display().to_string());
+            }
+            sessopts.search_paths.add_path(&format!("dependency={}", krate.deps_dir.display()),
+                                           config::ErrorOutputType::default());
+
+            let path = fs::canonicalize(&path).unwrap_or(path);
+            let codemap = CodeMap::with_file_loader(Box::new(Loader(files)));
+            (config::Input::File(krate.root), krate.name, codemap, Some(path))
+        }
+        _ => (config::Input::Str(input.to_string()), "rid3".to_owned(), CodeMap::new(), None)
+    };
+
+    let codemap = Rc::new(codemap);
     let emitter = Box::new(ErrorLogger {
         tx: tx.clone(),
         codemap: codemap.clone(),
+        path: path.clone(),
         file_end: file_end
     });
     let diagnostic_handler = errors::Handler::with_emitter(true, false, emitter);
@@ -187,7 +1081,7 @@ fn rustc_thread(input: String, mut lifeline: Arc<()>,
     let sess = session::build_session_(sessopts,
                                        None,
                                        diagnostic_handler,
-                                       codemap,
+                                       codemap.clone(),
                                        cstore.clone());
 
     let cfg = config::build_configuration(&sess);
@@ -196,7 +1090,7 @@ fn rustc_thread(input: String, mut lifeline: Arc<()>,
     let krate = driver::phase_1_parse_input(&sess, cfg, &input);
 
     still_alive!();
-    let krate = driver::phase_2_configure_and_expand(&sess, &cstore, krate, "rid3", None)
+    let krate = driver::phase_2_configure_and_expand(&sess, &cstore, krate, &crate_name, None)
         .expect("phase_2_configure_and_expand aborted");
 
     still_alive!();
@@ -267,8 +1161,26 @@ fn rustc_thread(input: String, mut lifeline: Arc<()>,
 
         for req in rx.iter() {
             still_alive!();
+            // Spans count from the start of the crate's first file,
+            // requests from the start of the edited one.
+            let base = match path {
+                Some(ref path) => {
+                    let files = codemap.files.borrow();
+                    let start = files.iter().find(|f| is_same_file(&f.name, path)).map(|f| f.start_pos.0 as usize);
+                    start
+                }
+                None => Some(0)
+            };
             match req {
                 Req::TypesAtOffset(offset, line) => {
+                    let base = match base {
+                        Some(base) => base,
+                        None => {
+                            let _ = tx.send(Res::TypesAtOffset(offset, vec![]));
+                            continue;
+                        }
+                    };
+                    let (offset, line) = (base + offset, base + line.start..base + line.end);
                     let mut out = vec![];
                     for (&id, ty) in tcx.node_types().iter() {
                         let node =  if let Some(node) = tcx.map.find(id) {
@@ -303,18 +1215,35 @@ fn rustc_thread(input: String, mut lifeline: Arc<()>,
                                         }
                                     }
                                 }
-                                let clean = regex!(concat![r"\b(",
-                                    "core::(option::Option|result::Result)|",
-                                    "collections::(vec::Vec|string::String)",
-                                r")\b"]);
-                                let ty_string = clean.replace_all(&ty.to_string(), |c: &::regex::Captures| {
-                                    c.at(0).unwrap().split(':').next_back().unwrap().to_owned()
-                                });
-                                out.push((lo-line.start..hi-line.start, ty_string));
+                                out.push((lo-line.start..hi-line.start, clean_ty(ty)));
                             }
                         }
                     }
-                    let _ = tx.send(Res::TypesAtOffset(offset, out));
+                    let _ = tx.send(Res::TypesAtOffset(offset - base, out));
+                }
+                Req::DefinitionAt(offset) => {
+                    let found = base.and_then(|base| def_at(tcx, base + offset))
+                                    .and_then(|def| def_node(tcx, def))
+                                    .and_then(|id| definition(tcx, id, path.as_ref().map(|p| p.as_path())));
+                    let _ = tx.send(Res::DefinitionAt(offset, found));
+                }
+                Req::ReferencesAt(offset, new_name) => {
+                    let found = match base {
+                        Some(base) => references(tcx, base + offset, new_name.as_ref().map(|s| &s[..])),
+                        None => Err("the file isn't part of its crate".to_owned())
+                    };
+                    let found = found.map(|(name, ranges)| {
+                        let codemap = tcx.sess.codemap();
+                        let path = path.as_ref().map(|p| p.as_path());
+                        (name, ranges.into_iter().map(|r| reference(codemap, r, path)).collect())
+                    });
+                    let _ = tx.send(Res::ReferencesAt(offset, found));
+                }
+                Req::CompletionsAt(offset, receiver) => {
+                    let found = base.map_or(vec![], |base| {
+                        completions(tcx, base + offset, receiver.as_ref().map(|s| &s[..]))
+                    });
+                    let _ = tx.send(Res::CompletionsAt(offset, found));
                 }
             }
         }
@@ -328,7 +1257,10 @@ pub enum State {
     Compiling,
     Aborted,
     Waiting,
-    TypesAtOffset(usize)
+    TypesAtOffset(usize),
+    DefinitionAt(usize),
+    ReferencesAt(usize),
+    CompletionsAt(usize)
 }
 
 pub struct Rustc {
@@ -340,20 +1272,36 @@ pub struct Rustc {
     // True if error.
     pub diagnostics: HashMap<usize, Vec<(Level, usize, String)>>,
     pub errors: usize,
-    pub types_at_offset: Option<Vec<(Range<usize>, String)>>
+    pub types_at_offset: Option<Vec<(Range<usize>, String)>>,
+    /// Answer to `definition_at`, `Some(None)` if nothing was found.
+    pub definition: Option<Option<Definition>>,
+    /// Answer to `references_at`, the name and where it's used, or why not.
+    pub references: Option<Result<(String, Vec<Reference>), String>>,
+    /// Answer to `completions_at`, unfiltered.
+    pub completions: Option<Vec<Completion>>
 }
 
 impl Rustc {
-    pub fn start(input: String) -> Rustc {
+    /// Start analyzing a snapshot of the source, which only gets
+    /// turned into a String on the background thread, along with
+    /// the rest of the crate the file at `path` belongs to, if any,
+    /// as it is in the open buffers.
+    pub fn start(path: Option<PathBuf>, input: Rope) -> Rustc {
+        let mut files = vfs::snapshot();
+        if let Some(ref path) = path {
+            files.insert(path.clone(), input.clone());
+        }
         let lifeline = Arc::new(());
         let lifeline2 = lifeline.clone();
         let (req_tx, req_rx) = channel();
         let (res_tx, res_rx) = channel();
-        let input_len = input.len();
+        let input_len = input.len_bytes();
+        let cargo = cargo::cache();
         thread::spawn(move || {
             let res_tx2 = res_tx.clone();
             let res = thread::catch_panic(move || {
-                let _ = rustc_thread(input, lifeline2, req_rx, res_tx, input_len);
+                let krate = path.as_ref().and_then(|path| cargo.crate_for(path));
+                let _ = rustc_thread(input, files, path, krate, lifeline2, req_rx, res_tx, input_len);
             });
             if res.is_err() {
                 let _ = res_tx2.send(Res::Aborted);
@@ -367,7 +1315,10 @@ impl Rustc {
             state: State::Compiling,
             diagnostics: HashMap::new(),
             errors: 0,
-            types_at_offset: None
+            types_at_offset: None,
+            definition: None,
+            references: None,
+            completions: None
         }
     }
 
@@ -400,14 +1351,77 @@ impl Rustc {
                         dirty = true;
                     }
                 }
+                Res::DefinitionAt(offset, result) => {
+                    if self.state == State::DefinitionAt(offset) {
+                        self.state = State::Waiting;
+                        self.definition = Some(result);
+                        dirty = true;
+                    }
+                }
+                Res::ReferencesAt(offset, result) => {
+                    if self.state == State::ReferencesAt(offset) {
+                        self.state = State::Waiting;
+                        self.references = Some(result);
+                        dirty = true;
+                    }
+                }
+                Res::CompletionsAt(offset, result) => {
+                    if self.state == State::CompletionsAt(offset) {
+                        self.state = State::Waiting;
+                        self.completions = Some(result);
+                        dirty = true;
+                    }
+                }
             }
         }
         dirty
     }
 
+    /// Whether the analysis is still going, which has to be done before
+    /// asking anything.
+    pub fn is_compiling(&self) -> bool {
+        self.state == State::Compiling
+    }
+
     pub fn types_at_offset(&mut self, offset: usize, line: Range<usize>) {
         self.types_at_offset = None;
         self.state = State::TypesAtOffset(offset);
         let _ = self.req_tx.send(Req::TypesAtOffset(offset, line));
     }
+
+    /// Look up what's referred to at `offset`, answered in `definition`.
+    pub fn definition_at(&mut self, offset: usize) {
+        self.definition = None;
+        self.state = State::DefinitionAt(offset);
+        let _ = self.req_tx.send(Req::DefinitionAt(offset));
+    }
+
+    /// Find everything referring to what's at `offset`, answered in `references`,
+    /// checking that it can be renamed to `new_name`, if given.
+    pub fn references_at(&mut self, offset: usize, new_name: Option<String>) {
+        self.references = None;
+        self.state = State::ReferencesAt(offset);
+        let _ = self.req_tx.send(Req::ReferencesAt(offset, new_name));
+    }
+
+    /// List what could be typed at `offset`, answered in `completions`,
+    /// only fields and methods if it's after `receiver` and a `.`.
+    pub fn completions_at(&mut self, offset: usize, receiver: Option<String>) {
+        self.completions = None;
+        self.state = State::CompletionsAt(offset);
+        let _ = self.req_tx.send(Req::CompletionsAt(offset, receiver));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::find_word;
+
+    #[test]
+    fn whole_words_only() {
+        assert_eq!(find_word("foo food foo_bar afoo foo", "foo"), vec![0..3, 22..25]);
+        assert_eq!(find_word("(foo)+foo.foo", "foo"), vec![1..4, 6..9, 10..13]);
+        assert_eq!(find_word("é foo", "foo"), vec![3..6]);
+        assert_eq!(find_word("bar", "foo"), vec![]);
+    }
 }
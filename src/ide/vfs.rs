@@ -0,0 +1,86 @@
+//! Unsaved text of open buffers, which analysis reads in place of the files on disk.
+
+use rope::Rope;
+
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+thread_local! {
+    // Text by path, with the id of the buffer it came from.
+    static BUFFERS: RefCell<HashMap<PathBuf, (usize, Rope)>> = RefCell::new(HashMap::new());
+    static GENERATION: Cell<usize> = Cell::new(0)
+}
+
+/// Serve `text` from buffer `owner` for `path` until it's saved, closed or moved elsewhere.
+pub fn set(path: &Path, owner: usize, text: Rope) {
+    BUFFERS.with(|b| b.borrow_mut().insert(path.to_path_buf(), (owner, text)));
+    bump();
+}
+
+/// Go back to reading `path` from disk, unless another buffer than `owner`
+/// has taken it over since, e.g. when the file was saved over from there.
+pub fn remove(path: &Path, owner: usize) {
+    let removed = BUFFERS.with(|b| {
+        let mut buffers = b.borrow_mut();
+        if buffers.get(path).map_or(false, |&(o, _)| o == owner) {
+            buffers.remove(path)
+        } else {
+            None
+        }
+    });
+    if removed.is_some() {
+        bump();
+    }
+}
+
+fn bump() {
+    GENERATION.with(|g| g.set(g.get() + 1));
+}
+
+/// Changes whenever any buffer does, to tell when analysis is out of date.
+pub fn generation() -> usize {
+    GENERATION.with(|g| g.get())
+}
+
+/// The buffers as they are now, to be read from another thread.
+pub fn snapshot() -> Snapshot {
+    Snapshot {
+        buffers: BUFFERS.with(|b| {
+            b.borrow().iter().map(|(path, &(_, ref text))| (path.clone(), text.clone())).collect()
+        })
+    }
+}
+
+pub struct Snapshot {
+    buffers: HashMap<PathBuf, Rope>
+}
+
+impl Snapshot {
+    /// Use `text` for `path`, whether it's been edited or not.
+    pub fn insert(&mut self, path: PathBuf, text: Rope) {
+        self.buffers.insert(path, text);
+    }
+
+    fn find(&self, path: &Path) -> Option<&Rope> {
+        self.buffers.get(path).or_else(|| {
+            fs::canonicalize(path).ok().and_then(|path| self.buffers.get(&path))
+        })
+    }
+
+    pub fn exists(&self, path: &Path) -> bool {
+        self.find(path).is_some() || path.is_file()
+    }
+
+    /// The buffer open for `path`, or else the file on disk.
+    pub fn read(&self, path: &Path) -> io::Result<String> {
+        if let Some(text) = self.find(path) {
+            return Ok(text.to_string());
+        }
+        let mut text = String::new();
+        try!(try!(fs::File::open(path)).read_to_string(&mut text));
+        Ok(text)
+    }
+}
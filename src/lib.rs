@@ -18,10 +18,31 @@ extern crate glium;
 #[macro_use(shared_library)]
 extern crate shared_library;
 
+pub mod fuzzy;
 pub mod glyph;
+pub mod grep;
+pub mod rope;
+pub mod seq;
+#[cfg(feature = "ide")]
+pub mod session;
 
 pub mod cfg {
+    use std::env;
+    use std::path::PathBuf;
+
     pub use ui::color::BreezeDark as ColorScheme;
+
+    /// Directory holding user configuration, e.g. `~/.config/rid3`.
+    pub fn dir() -> Option<PathBuf> {
+        let base = if cfg!(windows) {
+            env::var_os("APPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CONFIG_HOME").map(PathBuf::from).or_else(|| {
+                env::home_dir().map(|home| home.join(".config"))
+            })
+        };
+        base.map(|base| base.join("rid3"))
+    }
 }
 
 #[macro_use]
@@ -29,6 +50,8 @@ pub mod ui;
 
 #[cfg(feature = "ide")]
 pub mod ide {
+    pub mod cargo;
     pub mod rustc;
     pub mod highlight;
+    pub mod vfs;
 }
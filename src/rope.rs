@@ -0,0 +1,282 @@
+//! Text storage as a `Seq` of lines, with cached byte and char counts
+//! in every node, for O(log n) edits and offset conversions.
+//! Cloning a `Rope` (e.g. to hand a snapshot over to another thread) is O(1).
+
+use std::cmp::min;
+use std::fmt;
+use std::io::{self, Write};
+use std::ops::{Add, Range, Sub};
+
+use seq::{self, Measure, Seq};
+
+/// Counts for a sequence of lines, each of them including its newline.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+struct Summary {
+    bytes: usize,
+    chars: usize
+}
+
+impl Measure<String> for Summary {
+    fn measure(line: &String) -> Summary {
+        Summary {
+            bytes: line.len() + 1,
+            chars: line.chars().count() + 1
+        }
+    }
+}
+
+impl Add for Summary {
+    type Output = Summary;
+
+    fn add(self, other: Summary) -> Summary {
+        Summary {
+            bytes: self.bytes + other.bytes,
+            chars: self.chars + other.chars
+        }
+    }
+}
+
+impl Sub for Summary {
+    type Output = Summary;
+
+    fn sub(self, other: Summary) -> Summary {
+        Summary {
+            bytes: self.bytes - other.bytes,
+            chars: self.chars - other.chars
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Rope {
+    lines: Seq<String, Summary>
+}
+
+impl Rope {
+    pub fn new() -> Rope {
+        Rope::from_lines(vec![String::new()])
+    }
+
+    pub fn from_lines(lines: Vec<String>) -> Rope {
+        assert!(!lines.is_empty());
+        Rope {
+            lines: Seq::from_vec(lines)
+        }
+    }
+
+    pub fn len_lines(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn len_bytes(&self) -> usize {
+        self.lines.summary().bytes - 1
+    }
+
+    pub fn len_chars(&self) -> usize {
+        self.lines.summary().chars - 1
+    }
+
+    /// The contents of a line, without its newline.
+    pub fn line(&self, row: usize) -> &str {
+        &self.lines[row][..]
+    }
+
+    pub fn lines(&self, range: Range<usize>) -> Lines {
+        Lines {
+            iter: self.lines.iter(range)
+        }
+    }
+
+    pub fn insert_line(&mut self, row: usize, line: String) {
+        self.lines.insert(row, line);
+    }
+
+    pub fn remove_line(&mut self, row: usize) -> String {
+        assert!(self.len_lines() > 1);
+        self.lines.remove(row)
+    }
+
+    /// Modify the contents of a line, which must not gain any newlines.
+    pub fn edit_line<F, R>(&mut self, row: usize, f: F) -> R where F: FnOnce(&mut String) -> R {
+        self.lines.edit(row, f)
+    }
+
+    pub fn insert_str(&mut self, row: usize, offset: usize, s: &str) {
+        if s.is_empty() {
+            return;
+        }
+        self.edit_line(row, |line| {
+            let tail = line.split_off(offset);
+            line.push_str(s);
+            line.push_str(&tail);
+        })
+    }
+
+    pub fn remove_str(&mut self, row: usize, range: Range<usize>) {
+        if range.start == range.end {
+            return;
+        }
+        self.edit_line(row, |line| {
+            let tail = line.split_off(range.end);
+            line.truncate(range.start);
+            line.push_str(&tail);
+        })
+    }
+
+    /// Split a line in two, moving everything after `offset` to a new line.
+    pub fn split_line(&mut self, row: usize, offset: usize) {
+        let tail = self.edit_line(row, |line| line.split_off(offset));
+        self.insert_line(row + 1, tail);
+    }
+
+    /// Join a line with the one following it.
+    pub fn join_lines(&mut self, row: usize) {
+        let next = self.remove_line(row + 1);
+        self.edit_line(row, |line| line.push_str(&next));
+    }
+
+    pub fn line_to_byte(&self, row: usize) -> usize {
+        self.lines.prefix(row).bytes
+    }
+
+    pub fn line_to_char(&self, row: usize) -> usize {
+        self.lines.prefix(row).chars
+    }
+
+    /// The row containing a byte offset, and the offset within that row.
+    pub fn byte_to_line(&self, offset: usize) -> (usize, usize) {
+        let offset = min(offset, self.len_bytes());
+        let (row, acc) = self.lines.seek(offset, |s| s.bytes);
+        (row, offset - acc.bytes)
+    }
+
+    /// The row containing a char index, and the char index within that row.
+    pub fn char_to_line(&self, index: usize) -> (usize, usize) {
+        let index = min(index, self.len_chars());
+        let (row, acc) = self.lines.seek(index, |s| s.chars);
+        (row, index - acc.chars)
+    }
+
+    pub fn byte_to_char(&self, offset: usize) -> usize {
+        let (row, offset) = self.byte_to_line(offset);
+        self.line_to_char(row) + self.line(row)[..offset].chars().count()
+    }
+
+    pub fn char_to_byte(&self, index: usize) -> usize {
+        let (row, index) = self.char_to_line(index);
+        let line = self.line(row);
+        let offset = line.char_indices().nth(index).map_or(line.len(), |(i, _)| i);
+        self.line_to_byte(row) + offset
+    }
+
+    /// Write out all the lines, separated by newlines.
+    pub fn write_to<W: Write>(&self, mut w: W) -> io::Result<()> {
+        for (i, line) in self.lines(0..self.len_lines()).enumerate() {
+            if i > 0 {
+                try!(w.write_all(b"\n"));
+            }
+            try!(w.write_all(line.as_bytes()));
+        }
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a str> for Rope {
+    fn from(s: &'a str) -> Rope {
+        Rope::from_lines(s.split('\n').map(|line| line.to_owned()).collect())
+    }
+}
+
+impl fmt::Display for Rope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, line) in self.lines(0..self.len_lines()).enumerate() {
+            if i > 0 {
+                try!(f.write_str("\n"));
+            }
+            try!(f.write_str(line));
+        }
+        Ok(())
+    }
+}
+
+pub struct Lines<'a> {
+    iter: seq::Iter<'a, String, Summary>
+}
+
+impl<'a> Iterator for Lines<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<&'a str> {
+        self.iter.next().map(|line| &line[..])
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.iter.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(lines: usize) -> String {
+        let lines: Vec<_> = (0..lines).map(|i| format!("line {} é", i)).collect();
+        lines.join("\n")
+    }
+
+    fn check(rope: &Rope, expected: &str) {
+        assert_eq!(rope.to_string(), expected);
+        let lines: Vec<_> = expected.split('\n').collect();
+        assert_eq!(rope.len_lines(), lines.len());
+        assert_eq!(rope.len_bytes(), expected.len());
+        assert_eq!(rope.len_chars(), expected.chars().count());
+        for (row, line) in lines.iter().enumerate() {
+            assert_eq!(rope.line(row), *line);
+        }
+    }
+
+    #[test]
+    fn from_str() {
+        let s = text(1000);
+        check(&Rope::from(&s[..]), &s);
+        check(&Rope::from(""), "");
+    }
+
+    #[test]
+    fn split_and_join_lines() {
+        let mut rope = Rope::from("hello world\nbye");
+        rope.split_line(0, 5);
+        check(&rope, "hello\n world\nbye");
+        rope.join_lines(1);
+        check(&rope, "hello\n worldbye");
+        rope.insert_str(1, 0, "big");
+        rope.remove_str(0, 1..3);
+        check(&rope, "hlo\nbig worldbye");
+    }
+
+    #[test]
+    fn conversions() {
+        let s = text(2000);
+        let rope = Rope::from(&s[..]);
+        let mut byte = 0;
+        let mut chr = 0;
+        for (row, line) in s.split('\n').enumerate() {
+            assert_eq!(rope.line_to_byte(row), byte);
+            assert_eq!(rope.line_to_char(row), chr);
+            assert_eq!(rope.byte_to_line(byte), (row, 0));
+            assert_eq!(rope.char_to_line(chr), (row, 0));
+            // The last char of each line is multi-byte.
+            let last_byte = line.len() - 'é'.len_utf8();
+            let last_char = line.chars().count() - 1;
+            assert_eq!(rope.byte_to_line(byte + last_byte), (row, last_byte));
+            assert_eq!(rope.char_to_line(chr + last_char), (row, last_char));
+            assert_eq!(rope.byte_to_char(byte + last_byte), chr + last_char);
+            assert_eq!(rope.char_to_byte(chr + last_char), byte + last_byte);
+            byte += line.len() + 1;
+            chr += line.chars().count() + 1;
+        }
+        // Past the end clamps to the end.
+        assert_eq!(rope.byte_to_char(s.len() + 10), s.chars().count());
+        assert_eq!(rope.char_to_byte(s.chars().count() + 10), s.len());
+    }
+}
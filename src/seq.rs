@@ -0,0 +1,630 @@
+//! A sequence of arbitrary items stored as a B-tree, for O(log n) inserts,
+//! removals and indexing anywhere in it. Every node can also cache a sum of
+//! measures (e.g. byte counts) of the items below it, for O(log n) seeking.
+//! Holds the lines of a `Rope`, and the per-line state kept alongside them.
+//! Nodes are shared through `Arc` and copied on write, so cloning is O(1).
+
+use std::iter::FromIterator;
+use std::mem;
+use std::ops::{Add, Index, IndexMut, Range, Sub};
+use std::sync::Arc;
+
+const MAX_ITEMS: usize = 64;
+const MAX_CHILDREN: usize = 16;
+
+/// Counts for a single item, cached in every node summed over the items below it.
+pub trait Measure<T>: Copy + Default + Add<Output=Self> + Sub<Output=Self> {
+    fn measure(item: &T) -> Self;
+}
+
+/// The measure of sequences that are only ever indexed by position.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct Unmeasured;
+
+impl Add for Unmeasured {
+    type Output = Unmeasured;
+
+    fn add(self, _: Unmeasured) -> Unmeasured {
+        Unmeasured
+    }
+}
+
+impl Sub for Unmeasured {
+    type Output = Unmeasured;
+
+    fn sub(self, _: Unmeasured) -> Unmeasured {
+        Unmeasured
+    }
+}
+
+impl<T> Measure<T> for Unmeasured {
+    fn measure(_: &T) -> Unmeasured {
+        Unmeasured
+    }
+}
+
+#[derive(Clone)]
+struct Node<T, M> {
+    // Items in this subtree, and the sum of their measures.
+    len: usize,
+    summary: M,
+    data: Data<T, M>
+}
+
+// NB: All leaves are at the same depth.
+#[derive(Clone)]
+enum Data<T, M> {
+    Leaf(Vec<T>),
+    Branch(Vec<Arc<Node<T, M>>>)
+}
+
+impl<T: Clone, M: Measure<T>> Node<T, M> {
+    fn leaf(items: Vec<T>) -> Node<T, M> {
+        Node {
+            len: items.len(),
+            summary: items.iter().fold(M::default(), |s, item| s + M::measure(item)),
+            data: Data::Leaf(items)
+        }
+    }
+
+    fn branch(children: Vec<Arc<Node<T, M>>>) -> Node<T, M> {
+        Node {
+            len: children.iter().fold(0, |len, child| len + child.len),
+            summary: children.iter().fold(M::default(), |s, child| s + child.summary),
+            data: Data::Branch(children)
+        }
+    }
+
+    fn width(&self) -> usize {
+        match self.data {
+            Data::Leaf(ref items) => items.len(),
+            Data::Branch(ref children) => children.len()
+        }
+    }
+
+    fn max_width(&self) -> usize {
+        match self.data {
+            Data::Leaf(_) => MAX_ITEMS,
+            Data::Branch(_) => MAX_CHILDREN
+        }
+    }
+
+    /// Split off the second half of this node.
+    fn split(&mut self) -> Node<T, M> {
+        let right = match self.data {
+            Data::Leaf(ref mut items) => {
+                let at = items.len() / 2;
+                Node::leaf(items.split_off(at))
+            }
+            Data::Branch(ref mut children) => {
+                let at = children.len() / 2;
+                Node::branch(children.split_off(at))
+            }
+        };
+        self.len -= right.len;
+        self.summary = self.summary - right.summary;
+        right
+    }
+
+    fn append(&mut self, other: Node<T, M>) {
+        self.len += other.len;
+        self.summary = self.summary + other.summary;
+        match (&mut self.data, other.data) {
+            (&mut Data::Leaf(ref mut a), Data::Leaf(b)) => a.extend(b),
+            (&mut Data::Branch(ref mut a), Data::Branch(b)) => a.extend(b),
+            _ => unreachable!()
+        }
+    }
+
+    fn insert(&mut self, i: usize, item: T) -> Option<Node<T, M>> {
+        self.len += 1;
+        self.summary = self.summary + M::measure(&item);
+        match self.data {
+            Data::Leaf(ref mut items) => items.insert(i, item),
+            Data::Branch(ref mut children) => {
+                let (c, i) = find_child(children, i);
+                let split = Arc::make_mut(&mut children[c]).insert(i, item);
+                if let Some(right) = split {
+                    children.insert(c + 1, Arc::new(right));
+                }
+            }
+        }
+
+        if self.width() > self.max_width() {
+            Some(self.split())
+        } else {
+            None
+        }
+    }
+
+    fn remove(&mut self, i: usize) -> T {
+        let item = match self.data {
+            Data::Leaf(ref mut items) => items.remove(i),
+            Data::Branch(ref mut children) => {
+                let (c, i) = find_child(children, i);
+                let item = Arc::make_mut(&mut children[c]).remove(i);
+                if children[c].width() < children[c].max_width() / 4 {
+                    merge_child(children, c);
+                }
+                item
+            }
+        };
+        self.len -= 1;
+        self.summary = self.summary - M::measure(&item);
+        item
+    }
+
+    fn get(&self, mut i: usize) -> &T {
+        let mut node = self;
+        loop {
+            match node.data {
+                Data::Leaf(ref items) => return &items[i],
+                Data::Branch(ref children) => {
+                    let (c, j) = find_child(children, i);
+                    node = &*children[c];
+                    i = j;
+                }
+            }
+        }
+    }
+
+    /// Only for `Unmeasured` items, whose changes can't affect any summary.
+    fn get_mut(&mut self, i: usize) -> &mut T {
+        match self.data {
+            Data::Leaf(ref mut items) => &mut items[i],
+            Data::Branch(ref mut children) => {
+                let (c, i) = find_child(children, i);
+                Arc::make_mut(&mut children[c]).get_mut(i)
+            }
+        }
+    }
+
+    /// Modify an item, returning its measures before and after.
+    fn edit<F, R>(&mut self, i: usize, f: F) -> (R, M, M) where F: FnOnce(&mut T) -> R {
+        let (r, before, after) = match self.data {
+            Data::Leaf(ref mut items) => {
+                let before = M::measure(&items[i]);
+                let r = f(&mut items[i]);
+                (r, before, M::measure(&items[i]))
+            }
+            Data::Branch(ref mut children) => {
+                let (c, i) = find_child(children, i);
+                Arc::make_mut(&mut children[c]).edit(i, f)
+            }
+        };
+        self.summary = self.summary - before + after;
+        (r, before, after)
+    }
+}
+
+/// Find the child containing item `i`, or the last child if `i` is past the end.
+fn find_child<T, M>(children: &[Arc<Node<T, M>>], mut i: usize) -> (usize, usize) {
+    for (c, child) in children.iter().enumerate() {
+        if i < child.len || c == children.len() - 1 {
+            return (c, i);
+        }
+        i -= child.len;
+    }
+    unreachable!()
+}
+
+/// Merge an underfull child with one of its siblings, splitting the result if needed.
+fn merge_child<T: Clone, M: Measure<T>>(children: &mut Vec<Arc<Node<T, M>>>, c: usize) {
+    if children.len() < 2 {
+        return;
+    }
+
+    let c = if c > 0 { c - 1 } else { c };
+    let right = match Arc::try_unwrap(children.remove(c + 1)) {
+        Ok(node) => node,
+        Err(node) => (*node).clone()
+    };
+    let split = {
+        let left = Arc::make_mut(&mut children[c]);
+        left.append(right);
+        if left.width() > left.max_width() {
+            Some(left.split())
+        } else {
+            None
+        }
+    };
+    if let Some(split) = split {
+        children.insert(c + 1, Arc::new(split));
+    }
+}
+
+#[derive(Clone)]
+pub struct Seq<T, M = Unmeasured> {
+    root: Arc<Node<T, M>>
+}
+
+impl<T: Clone, M: Measure<T>> Seq<T, M> {
+    pub fn new() -> Seq<T, M> {
+        Seq::from_vec(vec![])
+    }
+
+    pub fn from_vec(items: Vec<T>) -> Seq<T, M> {
+        // Build half-full nodes, bottom-up.
+        let mut level = vec![];
+        let mut leaf = vec![];
+        for item in items {
+            leaf.push(item);
+            if leaf.len() == MAX_ITEMS / 2 {
+                level.push(Arc::new(Node::leaf(mem::replace(&mut leaf, vec![]))));
+            }
+        }
+        if !leaf.is_empty() || level.is_empty() {
+            level.push(Arc::new(Node::leaf(leaf)));
+        }
+
+        while level.len() > 1 {
+            let mut next = vec![];
+            let mut branch = vec![];
+            for node in level {
+                branch.push(node);
+                if branch.len() == MAX_CHILDREN / 2 {
+                    next.push(Arc::new(Node::branch(mem::replace(&mut branch, vec![]))));
+                }
+            }
+            if !branch.is_empty() {
+                next.push(Arc::new(Node::branch(branch)));
+            }
+            level = next;
+        }
+
+        Seq {
+            root: level.pop().unwrap()
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.root.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Sum of the measures of all the items.
+    pub fn summary(&self) -> M {
+        self.root.summary
+    }
+
+    pub fn insert(&mut self, i: usize, item: T) {
+        assert!(i <= self.len());
+        let split = Arc::make_mut(&mut self.root).insert(i, item);
+        if let Some(right) = split {
+            let left = self.root.clone();
+            self.root = Arc::new(Node::branch(vec![left, Arc::new(right)]));
+        }
+    }
+
+    pub fn remove(&mut self, i: usize) -> T {
+        assert!(i < self.len());
+        let item = Arc::make_mut(&mut self.root).remove(i);
+
+        // Shrink the tree while the root has a single child.
+        loop {
+            let child = match self.root.data {
+                Data::Branch(ref children) if children.len() == 1 => children[0].clone(),
+                _ => break
+            };
+            self.root = child;
+        }
+        item
+    }
+
+    pub fn remove_range(&mut self, range: Range<usize>) {
+        assert!(range.start <= range.end && range.end <= self.len());
+        for _ in range.clone() {
+            self.remove(range.start);
+        }
+    }
+
+    /// Modify an item in place, keeping the summaries up to date.
+    pub fn edit<F, R>(&mut self, i: usize, f: F) -> R where F: FnOnce(&mut T) -> R {
+        assert!(i < self.len());
+        Arc::make_mut(&mut self.root).edit(i, f).0
+    }
+
+    /// Sum of the measures of all the items before `i`.
+    pub fn prefix(&self, mut i: usize) -> M {
+        assert!(i <= self.len());
+        let mut node = &*self.root;
+        let mut acc = M::default();
+        loop {
+            match node.data {
+                Data::Leaf(ref items) => {
+                    for item in &items[..i] {
+                        acc = acc + M::measure(item);
+                    }
+                    return acc;
+                }
+                Data::Branch(ref children) => {
+                    let (c, j) = find_child(children, i);
+                    for child in &children[..c] {
+                        acc = acc + child.summary;
+                    }
+                    node = &*children[c];
+                    i = j;
+                }
+            }
+        }
+    }
+
+    /// Find the item containing the `x`th unit, as measured by `f`, or the
+    /// last item if there's no such unit, and the sum of the measures before it.
+    pub fn seek<F>(&self, mut x: usize, f: F) -> (usize, M) where F: Fn(&M) -> usize {
+        assert!(!self.is_empty());
+        let mut node = &*self.root;
+        let mut i = 0;
+        let mut acc = M::default();
+        loop {
+            match node.data {
+                Data::Leaf(ref items) => {
+                    for (j, item) in items.iter().enumerate() {
+                        let s = M::measure(item);
+                        if x < f(&s) || j == items.len() - 1 {
+                            return (i + j, acc);
+                        }
+                        x -= f(&s);
+                        acc = acc + s;
+                    }
+                    unreachable!()
+                }
+                Data::Branch(ref children) => {
+                    for (c, child) in children.iter().enumerate() {
+                        let s = child.summary;
+                        if x < f(&s) || c == children.len() - 1 {
+                            node = &**child;
+                            break;
+                        }
+                        x -= f(&s);
+                        i += child.len;
+                        acc = acc + s;
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn iter(&self, range: Range<usize>) -> Iter<T, M> {
+        assert!(range.start <= range.end && range.end <= self.len());
+
+        let mut stack = vec![];
+        let mut node = &*self.root;
+        let mut i = range.start;
+        loop {
+            match node.data {
+                Data::Leaf(_) => {
+                    stack.push((node, i));
+                    break;
+                }
+                Data::Branch(ref children) => {
+                    let (c, j) = find_child(children, i);
+                    stack.push((node, c + 1));
+                    node = &*children[c];
+                    i = j;
+                }
+            }
+        }
+        Iter {
+            stack: stack,
+            remaining: range.end - range.start
+        }
+    }
+}
+
+impl<T: Clone, M: Measure<T>> Index<usize> for Seq<T, M> {
+    type Output = T;
+
+    fn index(&self, i: usize) -> &T {
+        assert!(i < self.len());
+        self.root.get(i)
+    }
+}
+
+// Measured items have to go through `edit` instead.
+impl<T: Clone> IndexMut<usize> for Seq<T> {
+    fn index_mut(&mut self, i: usize) -> &mut T {
+        assert!(i < self.len());
+        Arc::make_mut(&mut self.root).get_mut(i)
+    }
+}
+
+impl<T: Clone, M: Measure<T>> FromIterator<T> for Seq<T, M> {
+    fn from_iter<I: IntoIterator<Item=T>>(iter: I) -> Seq<T, M> {
+        Seq::from_vec(iter.into_iter().collect())
+    }
+}
+
+pub struct Iter<'a, T: 'a, M: 'a = Unmeasured> {
+    // Nodes being walked, with the index of the next item/child in each.
+    stack: Vec<(&'a Node<T, M>, usize)>,
+    remaining: usize
+}
+
+impl<'a, T, M> Iterator for Iter<'a, T, M> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        while self.remaining > 0 {
+            let (node, i) = match self.stack.last_mut() {
+                Some(top) => {
+                    let current = *top;
+                    top.1 += 1;
+                    current
+                }
+                None => return None
+            };
+            match node.data {
+                Data::Leaf(ref items) => {
+                    if i < items.len() {
+                        self.remaining -= 1;
+                        return Some(&items[i]);
+                    }
+                    self.stack.pop();
+                }
+                Data::Branch(ref children) => {
+                    if i < children.len() {
+                        self.stack.push((&*children[i], 0));
+                    } else {
+                        self.stack.pop();
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::mem;
+    use std::ops::{Add, Sub};
+    use super::*;
+
+    /// Sum of the items, to check that summaries are kept up to date.
+    #[derive(Copy, Clone, Default, PartialEq, Debug)]
+    struct Sum(usize);
+
+    impl Add for Sum {
+        type Output = Sum;
+
+        fn add(self, other: Sum) -> Sum {
+            Sum(self.0 + other.0)
+        }
+    }
+
+    impl Sub for Sum {
+        type Output = Sum;
+
+        fn sub(self, other: Sum) -> Sum {
+            Sum(self.0 - other.0)
+        }
+    }
+
+    impl Measure<usize> for Sum {
+        fn measure(item: &usize) -> Sum {
+            Sum(*item)
+        }
+    }
+
+    fn check_sums(seq: &Seq<usize, Sum>, expected: &[usize]) {
+        let items: Vec<_> = seq.iter(0..seq.len()).cloned().collect();
+        assert_eq!(&items[..], expected);
+        assert_eq!(seq.summary(), Sum(expected.iter().fold(0, |a, b| a + b)));
+
+        let mut acc = 0;
+        for (i, &item) in expected.iter().enumerate() {
+            assert_eq!(seq.prefix(i), Sum(acc));
+            if item > 0 {
+                assert_eq!(seq.seek(acc, |s| s.0), (i, Sum(acc)));
+                assert_eq!(seq.seek(acc + item - 1, |s| s.0), (i, Sum(acc)));
+            }
+            acc += item;
+        }
+        // Past the end is the last item.
+        assert_eq!(seq.seek(acc + 10, |s| s.0).0, expected.len() - 1);
+    }
+
+    fn check<T: Clone + PartialEq + ::std::fmt::Debug>(seq: &Seq<T>, expected: &[T]) {
+        assert_eq!(seq.len(), expected.len());
+        let items: Vec<_> = seq.iter(0..seq.len()).cloned().collect();
+        assert_eq!(&items[..], expected);
+    }
+
+    #[test]
+    fn empty() {
+        let seq: Seq<usize> = Seq::new();
+        assert!(seq.is_empty());
+        check(&seq, &[]);
+    }
+
+    #[test]
+    fn from_vec_spans_several_levels() {
+        let items: Vec<_> = (0..10000).collect();
+        let seq = Seq::from_vec(items.clone());
+        check(&seq, &items);
+        assert_eq!(seq[4321], 4321);
+    }
+
+    #[test]
+    fn insert_splits_nodes() {
+        let mut seq = Seq::new();
+        let mut expected = vec![];
+        for i in 0..5000 {
+            // Alternate between the front, the middle and the end.
+            let at = match i % 3 {
+                0 => 0,
+                1 => expected.len() / 2,
+                _ => expected.len()
+            };
+            seq.insert(at, i);
+            expected.insert(at, i);
+        }
+        check(&seq, &expected);
+    }
+
+    #[test]
+    fn remove_merges_nodes() {
+        let mut expected: Vec<_> = (0..5000).collect();
+        let mut seq = Seq::from_vec(expected.clone());
+        while expected.len() > 1 {
+            let at = (expected.len() * 7 / 13) % expected.len();
+            assert_eq!(seq.remove(at), expected.remove(at));
+        }
+        check(&seq, &expected);
+        seq.remove(0);
+        assert!(seq.is_empty());
+    }
+
+    #[test]
+    fn remove_range() {
+        let mut seq = Seq::from_vec((0..1000).collect());
+        seq.remove_range(100..900);
+        let expected: Vec<_> = (0..100).chain(900..1000).collect();
+        check(&seq, &expected);
+    }
+
+    #[test]
+    fn index_mut_copies_on_write() {
+        let mut seq: Seq<usize> = Seq::from_vec((0..1000).collect());
+        let snapshot = seq.clone();
+        seq[500] = 0;
+        assert_eq!(seq[500], 0);
+        assert_eq!(snapshot[500], 500);
+    }
+
+    #[test]
+    fn summaries() {
+        let mut expected: Vec<_> = (0..3000).map(|i| i % 7).collect();
+        let mut seq: Seq<usize, Sum> = Seq::from_vec(expected.clone());
+        check_sums(&seq, &expected);
+
+        for i in 0..2000 {
+            let at = (i * 13) % expected.len();
+            seq.insert(at, i % 5);
+            expected.insert(at, i % 5);
+        }
+        check_sums(&seq, &expected);
+
+        let snapshot = seq.clone();
+        let snapshot_items = expected.clone();
+        for i in 0..500 {
+            let at = (i * 31) % expected.len();
+            assert_eq!(seq.edit(at, |item| mem::replace(item, 9)), expected[at]);
+            expected[at] = 9;
+        }
+        while expected.len() > 100 {
+            let at = (expected.len() * 5 / 11) % expected.len();
+            assert_eq!(seq.remove(at), expected.remove(at));
+        }
+        check_sums(&seq, &expected);
+        check_sums(&snapshot, &snapshot_items);
+    }
+}
@@ -0,0 +1,228 @@
+//! Keeping the open tabs, and any unsaved text in them, across restarts and crashes.
+
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use cfg;
+use ui::editor::{self, Editor, Pane, View};
+use ui::tab;
+
+/// How often the session is written out, at most.
+const AUTOSAVE_SPACING: f32 = 5.0;
+
+struct SessionTab {
+    // Empty for untitled buffers.
+    path: PathBuf,
+    view: View,
+    // Name of the file in the recovery directory with the unsaved text, if any.
+    recovery: Option<String>
+}
+
+fn session_path() -> Option<PathBuf> {
+    cfg::dir().map(|dir| dir.join("session"))
+}
+
+fn recovery_dir() -> Option<PathBuf> {
+    cfg::dir().map(|dir| dir.join("recovery"))
+}
+
+/// Parse a session file, made of a `current <tab>` line, then one
+/// `tab <row> <offset> <scroll_start> <scroll_sub> <scroll_col> <recovery|-> <path>`
+/// line per tab (with an empty path if untitled), ignoring anything it doesn't understand.
+fn parse(source: &str) -> (usize, Vec<SessionTab>) {
+    let mut current = 0;
+    let mut tabs = vec![];
+    for line in source.lines() {
+        let mut parts = line.splitn(8, ' ');
+        match parts.next() {
+            Some("current") => {
+                current = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+            }
+            Some("tab") => {
+                let numbers: Vec<usize> = parts.by_ref().take(5).filter_map(|s| s.parse().ok()).collect();
+                let (recovery, path) = match (parts.next(), parts.next()) {
+                    (Some(recovery), Some(path)) if numbers.len() == 5 => (recovery, path),
+                    _ => continue
+                };
+                tabs.push(SessionTab {
+                    path: PathBuf::from(path),
+                    view: View {
+                        row: numbers[0],
+                        offset: numbers[1],
+                        scroll_start: numbers[2],
+                        scroll_sub: numbers[3],
+                        scroll_col: numbers[4]
+                    },
+                    recovery: if recovery == "-" { None } else { Some(recovery.to_owned()) }
+                });
+            }
+            _ => {}
+        }
+    }
+    (current, tabs)
+}
+
+/// Open the tabs from the last session, bringing back their unsaved text.
+pub fn restore(tabs: &mut tab::Set<Pane>) {
+    let (session, recovery) = match (session_path(), recovery_dir()) {
+        (Some(session), Some(recovery)) => (session, recovery),
+        _ => return
+    };
+    let mut source = String::new();
+    match File::open(&session).and_then(|mut f| f.read_to_string(&mut source)) {
+        Ok(_) => {}
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return,
+        Err(e) => {
+            println!("couldn't read {}: {}", session.display(), e);
+            return;
+        }
+    }
+
+    let (current, session_tabs) = parse(&source);
+    let mut new_current = tabs.current_index();
+    for (i, tab) in session_tabs.into_iter().enumerate() {
+        let mut editor = if tab.path.as_os_str().is_empty() {
+            Editor::untitled()
+        } else {
+            match Editor::open(&tab.path) {
+                Ok(editor) => editor,
+                Err(e) => {
+                    println!("couldn't reopen {}: {}", tab.path.display(), e);
+                    continue;
+                }
+            }
+        };
+        if let Some(name) = tab.recovery {
+            let mut text = String::new();
+            match File::open(recovery.join(&name)).and_then(|mut f| f.read_to_string(&mut text)) {
+                Ok(_) => editor.recover(&text),
+                Err(e) => println!("couldn't recover {}: {}", editor.name(), e)
+            }
+        }
+        editor.set_view(tab.view);
+        if i == current {
+            new_current = tabs.tabs().len();
+        }
+        tabs.push(Pane::new(editor));
+    }
+    tabs.set_current(new_current);
+}
+
+/// Writes the session every so often, so that `restore` can bring it back.
+pub struct Autosave {
+    // Time since the session was last written.
+    elapsed: f32,
+    // The session file as it was last written.
+    written: String,
+    // Path and edit count of the text last written, by the id of the tab's
+    // editor, which also names the file, as tabs move when others are closed.
+    recovered: HashMap<usize, (Option<PathBuf>, usize)>
+}
+
+impl Autosave {
+    pub fn new() -> Autosave {
+        Autosave {
+            elapsed: 0.0,
+            written: String::new(),
+            recovered: HashMap::new()
+        }
+    }
+
+    pub fn update(&mut self, dt: f32, tabs: &tab::Set<Pane>) {
+        self.elapsed += dt;
+        if self.elapsed >= AUTOSAVE_SPACING {
+            self.elapsed = 0.0;
+            if let Err(e) = self.write(tabs) {
+                println!("couldn't autosave: {}", e);
+            }
+        }
+    }
+
+    /// Write out whatever changed since the last time.
+    pub fn write(&mut self, tabs: &tab::Set<Pane>) -> io::Result<()> {
+        let (session, recovery) = match (session_path(), recovery_dir()) {
+            (Some(session), Some(recovery)) => (session, recovery),
+            _ => return Ok(())
+        };
+        try!(fs::create_dir_all(&recovery));
+
+        // Relative paths would break when started from elsewhere.
+        let cwd = try!(env::current_dir());
+        let mut source = format!("current {}\n", tabs.current_index());
+        let mut recovered = HashMap::new();
+        for editor in tabs.tabs() {
+            let name = if editor.is_saved() {
+                None
+            } else {
+                let name = format!("{}.txt", editor.id());
+                let written = (editor.path().map(Path::to_path_buf), editor.edit_count());
+                if self.recovered.get(&editor.id()) != Some(&written) {
+                    try!(editor::write_atomic(&recovery.join(&name), editor.contents().as_bytes()));
+                }
+                recovered.insert(editor.id(), written);
+                Some(name)
+            };
+            let view = editor.view();
+            let path = editor.path().map_or(String::new(), |path| cwd.join(path).display().to_string());
+            source.push_str(&format!("tab {} {} {} {} {} {} {}\n",
+                                     view.row, view.offset,
+                                     view.scroll_start, view.scroll_sub, view.scroll_col,
+                                     name.as_ref().map_or("-", |s| &s[..]), path));
+        }
+
+        // Remove the text of tabs which were since saved or closed.
+        for entry in try!(fs::read_dir(&recovery)) {
+            let path = try!(entry).path();
+            let used = path.file_stem().and_then(|s| s.to_str()).and_then(|s| s.parse::<usize>().ok())
+                           .map_or(false, |id| recovered.contains_key(&id));
+            if !used {
+                try!(fs::remove_file(&path));
+            }
+        }
+        self.recovered = recovered;
+
+        if source != self.written {
+            // Never leave a half-written session behind, e.g. when crashing right then.
+            try!(editor::write_atomic(&session, source.as_bytes()));
+            self.written = source;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use super::parse;
+
+    #[test]
+    fn tabs_and_current() {
+        let (current, tabs) = parse("current 1\n\
+                                     tab 3 10 2 5 4 - /tmp/a b.rs\n\
+                                     tab 0 0 0 0 0 untitled-1 \n");
+        assert_eq!(current, 1);
+        assert_eq!(tabs.len(), 2);
+
+        assert_eq!(tabs[0].path, PathBuf::from("/tmp/a b.rs"));
+        assert_eq!(tabs[0].recovery, None);
+        let view = tabs[0].view;
+        assert_eq!((view.row, view.offset, view.scroll_start, view.scroll_sub, view.scroll_col), (3, 10, 2, 5, 4));
+
+        assert_eq!(tabs[1].path, PathBuf::from(""));
+        assert_eq!(tabs[1].recovery, Some("untitled-1".to_owned()));
+    }
+
+    #[test]
+    fn skips_what_it_does_not_understand() {
+        let (current, tabs) = parse("version 2\n\
+                                     tab x 0 0 0 0 - /tmp/a.rs\n\
+                                     tab 0 0 0\n\
+                                     tab 1 2 3 4 5 - /tmp/b.rs\n");
+        assert_eq!(current, 0);
+        assert_eq!(tabs.len(), 1);
+        assert_eq!(tabs[0].path, PathBuf::from("/tmp/b.rs"));
+    }
+}
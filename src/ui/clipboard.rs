@@ -0,0 +1,132 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+pub trait Clipboard {
+    fn get(&mut self) -> Option<String>;
+    fn set(&mut self, text: String);
+}
+
+/// Clipboard that only lives as long as the process, used when there
+/// is no system clipboard to talk to (e.g. running headless).
+#[derive(Default)]
+pub struct Local {
+    text: Option<String>
+}
+
+impl Clipboard for Local {
+    fn get(&mut self) -> Option<String> {
+        self.text.clone()
+    }
+    fn set(&mut self, text: String) {
+        self.text = Some(text);
+    }
+}
+
+/// The system clipboard, through the usual command-line helpers.
+/// Keeps a local copy, used whenever those helpers aren't available.
+#[derive(Default)]
+pub struct System {
+    local: Local
+}
+
+#[cfg(target_os = "macos")]
+const GET: (&'static str, &'static [&'static str]) = ("pbpaste", &[]);
+#[cfg(target_os = "macos")]
+const SET: (&'static str, &'static [&'static str]) = ("pbcopy", &[]);
+
+#[cfg(windows)]
+const GET: (&'static str, &'static [&'static str]) = ("powershell", &["-NoProfile", "-Command", "Get-Clipboard -Raw"]);
+#[cfg(windows)]
+const SET: (&'static str, &'static [&'static str]) = ("clip", &[]);
+
+#[cfg(not(any(windows, target_os = "macos")))]
+const GET: (&'static str, &'static [&'static str]) = ("xclip", &["-selection", "clipboard", "-o"]);
+#[cfg(not(any(windows, target_os = "macos")))]
+const SET: (&'static str, &'static [&'static str]) = ("xclip", &["-selection", "clipboard", "-i"]);
+
+impl Clipboard for System {
+    fn get(&mut self) -> Option<String> {
+        let (program, args) = GET;
+        match Command::new(program).args(args).output() {
+            Ok(ref out) if out.status.success() => {
+                Some(String::from_utf8_lossy(&out.stdout).into_owned())
+            }
+            _ => self.local.get()
+        }
+    }
+
+    fn set(&mut self, text: String) {
+        let (program, args) = SET;
+        let child = Command::new(program).args(args).stdin(Stdio::piped()).spawn();
+        match child {
+            Ok(mut child) => {
+                let written = child.stdin.take().unwrap().write_all(text.as_bytes());
+                if let Err(e) = written.and_then(|_| child.wait()) {
+                    println!("couldn't set clipboard: {}", e);
+                }
+            }
+            Err(e) => println!("couldn't set clipboard: {}", e)
+        }
+        self.local.set(text);
+    }
+}
+
+thread_local! {
+    static CLIPBOARD: RefCell<Box<Clipboard>> = RefCell::new(box System::default())
+}
+
+/// Replace the clipboard used by `get` and `set`, e.g. with a `Local` one.
+pub fn install(clipboard: Box<Clipboard>) {
+    CLIPBOARD.with(|c| *c.borrow_mut() = clipboard);
+}
+
+pub fn get() -> Option<String> {
+    CLIPBOARD.with(|c| c.borrow_mut().get())
+}
+
+pub fn set(text: String) {
+    CLIPBOARD.with(|c| c.borrow_mut().set(text));
+}
+
+/// Turn `\r\n` and lone `\r` into `line_ending`.
+pub fn normalize_newlines(text: &str, line_ending: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\r' => {
+                if chars.peek() == Some(&'\n') {
+                    chars.next();
+                }
+                result.push_str(line_ending);
+            }
+            '\n' => result.push_str(line_ending),
+            c => result.push(c)
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get, install, normalize_newlines, set, Local};
+
+    #[test]
+    fn local_round_trip() {
+        install(box Local::default());
+        assert_eq!(get(), None);
+        set("fn main() {}\n".to_owned());
+        assert_eq!(get(), Some("fn main() {}\n".to_owned()));
+        set("again".to_owned());
+        assert_eq!(get(), Some("again".to_owned()));
+    }
+
+    #[test]
+    fn newlines() {
+        assert_eq!(normalize_newlines("a\r\nb\rc\nd", "\n"), "a\nb\nc\nd");
+        assert_eq!(normalize_newlines("\r\r\n\n", "\n"), "\n\n\n");
+        assert_eq!(normalize_newlines("a\nb", "\r\n"), "a\r\nb");
+        assert_eq!(normalize_newlines("no newline", "\n"), "no newline");
+    }
+}
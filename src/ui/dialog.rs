@@ -1,16 +1,43 @@
-use std::path::PathBuf;
+use std::env;
+use std::path::{Path, PathBuf};
 use std::str;
 
+/// Whether to use the system's dialogs, instead of `ui::picker`,
+/// i.e. when `RID3_DIALOG=external` is set.
+pub fn external() -> bool {
+    env::var_os("RID3_DIALOG").map_or(false, |backend| backend == "external")
+}
+
 #[cfg(not(windows))]
 pub fn open_file() -> Option<PathBuf> {
     use std::process::Command;
 
-    let out = if cfg!(target_os = "macos") {
-        Command::new("osascript").args(&["-e", "POSIX path of (choose file)"]).output()
+    if cfg!(target_os = "macos") {
+        run_dialog(Command::new("osascript").args(&["-e", "POSIX path of (choose file)"]))
     } else {
-        Command::new("kdialog").args(&["--getopenfilename", "."]).output()
-    };
-    let out = match out {
+        run_dialog(Command::new("kdialog").args(&["--getopenfilename", "."]))
+    }
+}
+
+/// Ask where to save a file, starting from `suggested`.
+#[cfg(not(windows))]
+pub fn save_file(suggested: &Path) -> Option<PathBuf> {
+    use std::process::Command;
+
+    if cfg!(target_os = "macos") {
+        let name = suggested.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let script = format!("POSIX path of (choose file name default name \"{}\")",
+                             name.replace('\\', "\\\\").replace('"', "\\\""));
+        run_dialog(Command::new("osascript").args(&["-e", &script]))
+    } else {
+        run_dialog(Command::new("kdialog").arg("--getsavefilename").arg(suggested))
+    }
+}
+
+/// Run a dialog program, returning the path it printed if it wasn't cancelled.
+#[cfg(not(windows))]
+fn run_dialog(command: &mut ::std::process::Command) -> Option<PathBuf> {
+    let out = match command.output() {
         Ok(out) => out,
         Err(e) => {
             println!("couldn't open dialog: {}", e);
@@ -22,7 +49,7 @@ pub fn open_file() -> Option<PathBuf> {
         return None;
     }
 
-    Some(PathBuf::from(str::from_utf8(&out.stdout).unwrap().trim()))
+    str::from_utf8(&out.stdout).ok().map(|path| PathBuf::from(path.trim()))
 }
 
 #[cfg(windows)]
@@ -30,7 +57,10 @@ mod comdlg32 {
     extern crate winapi;
     pub use self::winapi::*;
 
-    shared_library!(ComDlg32, "comdlg32", pub fn GetOpenFileNameA(_ofn: *mut OPENFILENAME) -> BOOL,);
+    shared_library!(ComDlg32, "comdlg32",
+        pub fn GetOpenFileNameA(_ofn: *mut OPENFILENAME) -> BOOL,
+        pub fn GetSaveFileNameA(_ofn: *mut OPENFILENAME) -> BOOL,
+    );
 
     pub type LPCTSTR = LPCSTR;
     pub type LPTSTR = LPSTR;
@@ -69,6 +99,13 @@ mod comdlg32 {
             mem::transmute(ComDlg32::get_static_ref().GetOpenFileNameA)
         }
     }
+
+    pub fn get_GetSaveFileNameA() -> unsafe extern "system" fn(*mut OPENFILENAME) -> BOOL {
+        use std::mem;
+        unsafe {
+            mem::transmute(ComDlg32::get_static_ref().GetSaveFileNameA)
+        }
+    }
 }
 
 #[cfg(windows)]
@@ -96,3 +133,33 @@ pub fn open_file() -> Option<PathBuf> {
         None
     }
 }
+
+#[cfg(windows)]
+pub fn save_file(suggested: &Path) -> Option<PathBuf> {
+    use self::comdlg32::*;
+    use std::ffi::CStr;
+    use std::mem;
+
+    // Start with the suggested name, leaving room for the terminator.
+    let mut file = [0u8; 260];
+    let suggested = suggested.to_string_lossy();
+    let len = ::std::cmp::min(suggested.len(), file.len() - 1);
+    file[..len].copy_from_slice(&suggested.as_bytes()[..len]);
+
+    let mut ofn: OPENFILENAME = unsafe { mem::zeroed() };
+    ofn.lStructSize = mem::size_of::<OPENFILENAME>() as DWORD;
+
+    ofn.lpstrFile = file.as_mut_ptr() as LPTSTR;
+    ofn.nMaxFile = 260;
+    ofn.lpstrFilter = b"All\0*.*\0Rust code\0*.RS\0\0".as_ptr() as LPCTSTR;
+    ofn.nFilterIndex = 1;
+    ofn.Flags = 0x00000800 | 0x00000002; // OFN_PATHMUSTEXIST | OFN_OVERWRITEPROMPT;
+
+    if unsafe { get_GetSaveFileNameA()(&mut ofn) } == TRUE {
+        str::from_utf8(unsafe {
+            CStr::from_ptr(ofn.lpstrFile).to_bytes()
+        }).ok().map(PathBuf::from)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,232 @@
+use std::cmp::min;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::iter::once;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Files larger than this are refused, instead of bogging everything down.
+const MAX_SIZE: u64 = 64 << 20;
+/// How much of a file is checked for NUL bytes, which text files don't have.
+const BINARY_CHECK_LEN: usize = 8 << 10;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Encoding {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// Anything that's not valid UTF-8, with each byte as the character
+    /// of the same value, so that it's saved back unchanged.
+    Latin1
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum LineEnding {
+    Lf,
+    CrLf
+}
+
+impl LineEnding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n"
+        }
+    }
+}
+
+/// How a file is stored on disk, to save it back the same way.
+/// The text itself always uses `\n` line endings.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Format {
+    pub encoding: Encoding,
+    pub line_ending: LineEnding,
+    pub bom: bool,
+    /// Whether the file had both kinds of line endings, which are all
+    /// saved as `line_ending`, the one most lines had.
+    pub mixed_line_endings: bool
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format {
+            encoding: Encoding::Utf8,
+            line_ending: LineEnding::Lf,
+            bom: false,
+            mixed_line_endings: false
+        }
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Read a text file, refusing huge and binary ones.
+pub fn load(path: &Path) -> io::Result<(String, Format)> {
+    let mut file = try!(fs::File::open(path));
+    let len = try!(file.metadata()).len();
+    if len > MAX_SIZE {
+        return Err(invalid(format!("file is too large ({} MB)", len >> 20)));
+    }
+    let mut bytes = Vec::with_capacity(len as usize);
+    try!(file.read_to_end(&mut bytes));
+
+    let (text, encoding, bom) = try!(decode(bytes));
+
+    // Go by most lines, files with both kinds get normalized on save.
+    let crlf = text.matches("\r\n").count();
+    let lf = text.matches('\n').count() - crlf;
+    let line_ending = if crlf > lf { LineEnding::CrLf } else { LineEnding::Lf };
+    let text = if crlf > 0 { text.replace("\r\n", "\n") } else { text };
+
+    Ok((text, Format {
+        encoding: encoding,
+        line_ending: line_ending,
+        bom: bom,
+        mixed_line_endings: crlf > 0 && lf > 0
+    }))
+}
+
+fn decode(mut bytes: Vec<u8>) -> io::Result<(String, Encoding, bool)> {
+    if bytes.starts_with(b"\xFF\xFE") {
+        return decode_utf16(&bytes[2..], true).map(|text| (text, Encoding::Utf16Le, true));
+    }
+    if bytes.starts_with(b"\xFE\xFF") {
+        return decode_utf16(&bytes[2..], false).map(|text| (text, Encoding::Utf16Be, true));
+    }
+
+    if bytes[..min(bytes.len(), BINARY_CHECK_LEN)].contains(&0) {
+        return Err(invalid("looks like a binary file".to_owned()));
+    }
+
+    let bom = bytes.starts_with(b"\xEF\xBB\xBF");
+    if bom {
+        bytes.drain(..3);
+    }
+    match String::from_utf8(bytes) {
+        Ok(text) => Ok((text, Encoding::Utf8, bom)),
+        Err(e) => {
+            // Keep the BOM as text, it didn't mean much anyway.
+            let prefix: &[u8] = if bom { b"\xEF\xBB\xBF" } else { b"" };
+            let bytes = prefix.iter().cloned().chain(e.into_bytes());
+            Ok((bytes.map(|b| b as char).collect(), Encoding::Latin1, false))
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], little_endian: bool) -> io::Result<String> {
+    if bytes.len() % 2 != 0 {
+        return Err(invalid("truncated UTF-16".to_owned()));
+    }
+    let units: Vec<u16> = bytes.chunks(2).map(|b| {
+        let (lo, hi) = if little_endian { (b[0], b[1]) } else { (b[1], b[0]) };
+        (hi as u16) << 8 | lo as u16
+    }).collect();
+    String::from_utf16(&units).map_err(|_| invalid("invalid UTF-16".to_owned()))
+}
+
+/// Turn text back into the bytes of a file in `format`, failing if
+/// some characters can't be represented (e.g. `€` in Latin-1).
+pub fn encode(text: &str, format: Format) -> io::Result<Vec<u8>> {
+    let crlf;
+    let text = if format.line_ending == LineEnding::CrLf {
+        crlf = text.replace("\n", "\r\n");
+        &crlf[..]
+    } else {
+        text
+    };
+
+    let mut bytes = Vec::with_capacity(text.len() + 3);
+    match format.encoding {
+        Encoding::Utf8 => {
+            if format.bom {
+                bytes.extend_from_slice(b"\xEF\xBB\xBF");
+            }
+            bytes.extend_from_slice(text.as_bytes());
+        }
+        Encoding::Utf16Le | Encoding::Utf16Be => {
+            let little_endian = format.encoding == Encoding::Utf16Le;
+            for unit in once(0xFEFF).chain(text.encode_utf16()) {
+                let (lo, hi) = (unit as u8, (unit >> 8) as u8);
+                bytes.extend_from_slice(&if little_endian { [lo, hi] } else { [hi, lo] });
+            }
+        }
+        Encoding::Latin1 => {
+            for c in text.chars() {
+                if c as u32 > 0xFF {
+                    return Err(invalid(format!("{:?} can't be saved as Latin-1", c)));
+                }
+                bytes.push(c as u8);
+            }
+        }
+    }
+    Ok(bytes)
+}
+
+/// When the file was last modified, to notice changes made by other programs.
+pub fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Replace the contents of a file, by writing them to a temporary file next
+/// to it, then renaming that over it, so that it's never left half-written.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    // Replace the file a symlink points to, not the symlink.
+    let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => return Err(invalid(format!("{} isn't a file", path.display())))
+    };
+    let temp = path.with_file_name(format!(".{}.rid3-save", name));
+
+    let write = |data: &[u8]| -> io::Result<()> {
+        let mut file = try!(fs::File::create(&temp));
+        try!(file.write_all(data));
+        try!(file.sync_all());
+        if let Ok(metadata) = fs::metadata(&path) {
+            try!(fs::set_permissions(&temp, metadata.permissions()));
+        }
+        fs::rename(&temp, &path)
+    };
+    let result = write(data);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, Encoding};
+
+    fn decoded(bytes: &[u8]) -> (String, Encoding, bool) {
+        decode(bytes.to_vec()).unwrap()
+    }
+
+    #[test]
+    fn utf8_with_and_without_bom() {
+        assert_eq!(decoded(b"caf\xC3\xA9"), ("café".to_owned(), Encoding::Utf8, false));
+        assert_eq!(decoded(b"\xEF\xBB\xBFhi"), ("hi".to_owned(), Encoding::Utf8, true));
+        assert_eq!(decoded(b""), (String::new(), Encoding::Utf8, false));
+    }
+
+    #[test]
+    fn utf16_by_bom() {
+        assert_eq!(decoded(b"\xFF\xFEh\0i\0"), ("hi".to_owned(), Encoding::Utf16Le, true));
+        assert_eq!(decoded(b"\xFE\xFF\0h\0i"), ("hi".to_owned(), Encoding::Utf16Be, true));
+        assert!(decode(b"\xFF\xFEh".to_vec()).is_err());
+    }
+
+    #[test]
+    fn invalid_utf8_falls_back_to_latin1() {
+        assert_eq!(decoded(b"caf\xE9"), ("café".to_owned(), Encoding::Latin1, false));
+        // The BOM can't be dropped when the rest isn't UTF-8 after all.
+        assert_eq!(decoded(b"\xEF\xBB\xBF\xE9"), ("\u{EF}\u{BB}\u{BF}é".to_owned(), Encoding::Latin1, false));
+    }
+
+    #[test]
+    fn refuses_binary() {
+        assert!(decode(b"ELF\0\x01".to_vec()).is_err());
+    }
+}
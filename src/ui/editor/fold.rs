@@ -0,0 +1,146 @@
+/// A collapsed block, shown as its first row followed by a placeholder.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Fold {
+    /// Row that stays visible.
+    pub start: usize,
+    /// Last hidden row.
+    pub end: usize
+}
+
+impl Fold {
+    pub fn hides(&self, row: usize) -> bool {
+        self.start < row && row <= self.end
+    }
+}
+
+/// All the folds in a file, which may be nested.
+pub struct Folds {
+    folds: Vec<Fold>
+}
+
+impl Folds {
+    pub fn new() -> Folds {
+        Folds {
+            folds: vec![]
+        }
+    }
+
+    pub fn add(&mut self, fold: Fold) {
+        if !self.folds.contains(&fold) {
+            self.folds.push(fold);
+        }
+    }
+
+    /// The fold starting at `row`, if it's collapsed.
+    pub fn at(&self, row: usize) -> Option<Fold> {
+        self.folds.iter().find(|f| f.start == row).cloned()
+    }
+
+    pub fn is_hidden(&self, row: usize) -> bool {
+        self.folds.iter().any(|f| f.hides(row))
+    }
+
+    /// The outermost fold hiding `row`, if any.
+    pub fn hiding(&self, row: usize) -> Option<Fold> {
+        self.folds.iter().filter(|f| f.hides(row)).min_by_key(|f| f.start).cloned()
+    }
+
+    /// Remove the fold starting at `row`, returns false if there was none.
+    pub fn remove(&mut self, row: usize) -> bool {
+        let len = self.folds.len();
+        self.folds.retain(|f| f.start != row);
+        self.folds.len() != len
+    }
+
+    /// Expand everything hiding `row`, e.g. to show the caret.
+    pub fn reveal(&mut self, row: usize) -> bool {
+        let len = self.folds.len();
+        self.folds.retain(|f| !f.hides(row));
+        self.folds.len() != len
+    }
+
+    /// Keep up with an edit of the rows `start` through `old_end`, which now end at `new_end`.
+    /// Folds below the edit move along with it, and folds it touched (other than
+    /// by changing a single row in place, e.g. the first one) are expanded.
+    pub fn edit(&mut self, start: usize, old_end: usize, new_end: usize) {
+        let in_place = start == old_end && old_end == new_end;
+        self.folds.retain(|f| start > f.end || old_end < f.start || (in_place && start == f.start));
+        for f in &mut self.folds {
+            if old_end < f.start {
+                f.start = f.start - old_end + new_end;
+                f.end = f.end - old_end + new_end;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Fold, Folds};
+
+    fn folds(all: &[(usize, usize)]) -> Folds {
+        let mut folds = Folds::new();
+        for &(start, end) in all {
+            folds.add(Fold { start: start, end: end });
+        }
+        folds
+    }
+
+    fn ranges(folds: &Folds) -> Vec<(usize, usize)> {
+        folds.folds.iter().map(|f| (f.start, f.end)).collect()
+    }
+
+    #[test]
+    fn edit_above_moves_folds() {
+        let mut f = folds(&[(5, 8), (10, 12)]);
+        // Two rows inserted in row 1.
+        f.edit(1, 1, 3);
+        assert_eq!(ranges(&f), [(7, 10), (12, 14)]);
+        // Rows 0 through 2 joined into one.
+        f.edit(0, 2, 0);
+        assert_eq!(ranges(&f), [(5, 8), (10, 12)]);
+    }
+
+    #[test]
+    fn edit_below_keeps_folds() {
+        let mut f = folds(&[(2, 4)]);
+        f.edit(5, 5, 9);
+        f.edit(6, 8, 6);
+        assert_eq!(ranges(&f), [(2, 4)]);
+    }
+
+    #[test]
+    fn edit_between_folds() {
+        let mut f = folds(&[(0, 2), (6, 8)]);
+        f.edit(4, 4, 5);
+        assert_eq!(ranges(&f), [(0, 2), (7, 9)]);
+    }
+
+    #[test]
+    fn edit_inside_expands() {
+        let mut f = folds(&[(0, 9), (2, 4), (6, 8)]);
+        f.edit(3, 3, 3);
+        // Only the folds containing the edit are gone, the one after it stays.
+        assert_eq!(ranges(&f), [(6, 8)]);
+
+        let mut f = folds(&[(2, 4)]);
+        // Joining the last hidden row with the one after it.
+        f.edit(4, 5, 4);
+        assert!(f.at(2).is_none());
+    }
+
+    #[test]
+    fn edit_of_the_first_row() {
+        // Changing the visible row in place keeps the fold.
+        let mut f = folds(&[(2, 4)]);
+        f.edit(2, 2, 2);
+        assert_eq!(ranges(&f), [(2, 4)]);
+
+        // Splitting or joining it doesn't.
+        f.edit(2, 2, 3);
+        assert!(f.at(2).is_none());
+        let mut f = folds(&[(2, 4)]);
+        f.edit(1, 2, 1);
+        assert!(!f.is_hidden(3));
+    }
+}
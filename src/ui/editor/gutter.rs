@@ -0,0 +1,98 @@
+use cfg::ColorScheme;
+
+use ui::{BB, Px};
+use ui::layout::{CollectCx, CollectBB, Layout};
+use ui::color::Scheme;
+use ui::draw::DrawCx;
+use ui::text;
+
+use super::{has_error, Editor};
+
+/// Line numbers, diagnostic markers and fold toggles, to the left of the text.
+pub struct Gutter {
+    bb: BB<Px>,
+    // Number rows by their distance from the caret's row.
+    pub relative: bool,
+    // Digits in the largest line number.
+    pub digits: usize,
+    char_width: Px
+}
+
+const GUTTER_PADDING: Px = 4.0;
+
+impl Gutter {
+    pub fn new() -> Gutter {
+        Gutter {
+            bb: BB::default(),
+            relative: false,
+            digits: 1,
+            char_width: 0.0
+        }
+    }
+
+    /// Whether `x` is over the fold toggles, on the right edge.
+    pub fn toggle_at(&self, x: Px) -> bool {
+        x >= self.bb.x2 - self.char_width - GUTTER_PADDING * 2.0
+    }
+
+    /// Draw the rows currently visible in `editor`, so they scroll together.
+    pub fn draw(&self, cx: &mut DrawCx, editor: &Editor) {
+        let metrics = editor.font_metrics;
+        let bb = self.bb;
+        cx.fill(bb, ColorScheme.background());
+
+        let caret_row = editor.caret.row;
+        let rustc = editor.new_rustc.as_ref().unwrap_or(&editor.rustc);
+        for (i, &(row, sub)) in editor.visual_rows(editor.visible_rows()).iter().enumerate() {
+            // Wrapped rows are only numbered once.
+            if sub > 0 {
+                continue;
+            }
+            let y = bb.y1 + i as Px * metrics.height;
+
+            if let Some(diagnostics) = rustc.diagnostics.get(&row) {
+                let size = (metrics.height * 0.5).round();
+                let marker = BB::rect(bb.x1 + GUTTER_PADDING, y + ((metrics.height - size) / 2.0).round(),
+                                      size, size);
+                if has_error(diagnostics) {
+                    cx.border(marker, ColorScheme.negative(), size / 2.0, size / 2.0);
+                } else {
+                    cx.fill(marker, ColorScheme.neutral());
+                }
+            }
+
+            let number = if self.relative && row != caret_row {
+                if row < caret_row { caret_row - row } else { row - caret_row }
+            } else {
+                row + 1
+            };
+            let number = number.to_string();
+            let x = bb.x2 - GUTTER_PADDING * 2.0 - (number.len() as Px + 1.0) * metrics.width;
+            let color = if row == caret_row { ColorScheme.normal() } else { ColorScheme.inactive() };
+            cx.text(editor.font, [x, y], color, &number);
+
+            let line = &editor.lines[row];
+            let toggle = if editor.folds.at(row).is_some() {
+                "+"
+            } else if line.braces.1 > 0 || line.region == Some(true) {
+                "-"
+            } else {
+                continue;
+            };
+            cx.text(editor.font, [bb.x2 - GUTTER_PADDING - metrics.width, y], ColorScheme.inactive(), toggle);
+        }
+    }
+}
+
+impl Layout for Gutter {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        let metrics = cx.fonts().metrics(text::Mono);
+        self.char_width = metrics.width;
+        let bb = cx.area(&mut self.bb, "<gutter>");
+        // Room for a marker, the line numbers, then a fold toggle.
+        let width = metrics.height + (self.digits as Px + 1.0) * metrics.width + GUTTER_PADDING * 4.0;
+        cx.distance(bb.x1, bb.x2, width.ceil());
+        bb
+    }
+}
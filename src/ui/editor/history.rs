@@ -0,0 +1,248 @@
+use super::Caret;
+
+/// A single replacement of `removed` by `inserted`, both starting at `start`.
+/// Undoing it is the same replacement with the two strings swapped.
+pub struct Change {
+    pub start: Caret,
+    pub removed: String,
+    pub inserted: String
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Kind {
+    /// Characters typed one after another, merged into a single step.
+    Typing,
+    Other
+}
+
+/// Every selection (start and caret), and which one is the primary.
+pub type Selections = (Vec<(Caret, Caret)>, usize);
+
+/// One undo step, with the selections around it.
+pub struct Group {
+    kind: Kind,
+    pub changes: Vec<Change>,
+    pub before: Selections,
+    pub after: Selections
+}
+
+pub struct History {
+    undo: Vec<Group>,
+    redo: Vec<Group>,
+    current: Option<Group>,
+
+    // Length of the undo stack when the file was last saved,
+    // or None if that state can't be reached anymore.
+    saved: Option<usize>,
+
+    // False after anything (e.g. caret movement) that should
+    // prevent merging the next step into the last one.
+    open: bool
+}
+
+impl History {
+    pub fn new() -> History {
+        History {
+            undo: vec![],
+            redo: vec![],
+            current: None,
+            saved: Some(0),
+            open: false
+        }
+    }
+
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.undo.len())
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved = Some(self.undo.len());
+        self.seal();
+    }
+
+    /// Prevent the next step from being merged into the last one.
+    pub fn seal(&mut self) {
+        self.open = false;
+    }
+
+    /// Start recording a new step, which may get merged into the previous one.
+    pub fn start(&mut self, kind: Kind, before: Selections) {
+        assert!(self.current.is_none());
+
+        // Anything left to redo is lost, possibly including the saved state.
+        self.redo.clear();
+        if self.saved.map_or(false, |saved| saved > self.undo.len()) {
+            self.saved = None;
+        }
+
+        let merge = self.open && kind == Kind::Typing && !self.is_saved() &&
+                    self.undo.last().map_or(false, |last| {
+                        last.kind == kind && last.after == before
+                    });
+        self.current = Some(if merge {
+            self.undo.pop().unwrap()
+        } else {
+            Group {
+                kind: kind,
+                changes: vec![],
+                before: before.clone(),
+                after: before
+            }
+        });
+    }
+
+    pub fn push(&mut self, change: Change) {
+        self.current.as_mut().expect("History::push outside of a step").changes.push(change);
+    }
+
+    pub fn finish(&mut self, after: Selections) {
+        let mut group = self.current.take().expect("History::finish without History::start");
+        if group.changes.is_empty() {
+            return;
+        }
+        group.after = after;
+        self.open = group.kind == Kind::Typing;
+        self.undo.push(group);
+    }
+
+    /// Take the last step, which has to be given back through `undone`.
+    pub fn undo(&mut self) -> Option<Group> {
+        self.seal();
+        self.undo.pop()
+    }
+
+    pub fn undone(&mut self, group: Group) {
+        self.redo.push(group);
+    }
+
+    /// Take the last undone step, which has to be given back through `redone`.
+    pub fn redo(&mut self) -> Option<Group> {
+        self.seal();
+        self.redo.pop()
+    }
+
+    pub fn redone(&mut self, group: Group) {
+        self.undo.push(group);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Caret;
+    use super::{Change, History, Kind, Selections};
+
+    fn at(col: usize) -> Selections {
+        let k = Caret { row: 0, col: col, offset: col };
+        (vec![(k, k)], 0)
+    }
+
+    /// Record inserting `text` at column `col`, with the caret ending up after it.
+    fn step(history: &mut History, kind: Kind, col: usize, text: &str) {
+        history.start(kind, at(col));
+        history.push(Change {
+            start: Caret { row: 0, col: col, offset: col },
+            removed: String::new(),
+            inserted: text.to_owned()
+        });
+        history.finish(at(col + text.len()));
+    }
+
+    fn inserted(history: &mut History) -> Vec<String> {
+        let group = history.undo().unwrap();
+        let inserted = group.changes.iter().map(|c| c.inserted.clone()).collect();
+        history.undone(group);
+        inserted
+    }
+
+    #[test]
+    fn undo_and_redo_steps() {
+        let mut history = History::new();
+        step(&mut history, Kind::Other, 0, "a");
+        step(&mut history, Kind::Other, 1, "b");
+
+        let group = history.undo().unwrap();
+        assert_eq!(group.changes[0].inserted, "b");
+        assert!(group.before == at(1) && group.after == at(2));
+        history.undone(group);
+        assert_eq!(inserted(&mut history), ["a"]);
+        assert!(history.undo().is_none());
+
+        let group = history.redo().unwrap();
+        assert_eq!(group.changes[0].inserted, "a");
+        history.redone(group);
+
+        // A new step drops whatever was left to redo.
+        step(&mut history, Kind::Other, 1, "c");
+        assert!(history.redo().is_none());
+        assert_eq!(inserted(&mut history), ["c"]);
+        assert_eq!(inserted(&mut history), ["a"]);
+    }
+
+    #[test]
+    fn typing_merges() {
+        let mut history = History::new();
+        step(&mut history, Kind::Typing, 0, "a");
+        step(&mut history, Kind::Typing, 1, "b");
+        step(&mut history, Kind::Typing, 2, "c");
+
+        let group = history.undo().unwrap();
+        assert_eq!(group.changes.len(), 3);
+        assert!(group.before == at(0) && group.after == at(3));
+        history.undone(group);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn typing_stops_merging() {
+        let mut history = History::new();
+        step(&mut history, Kind::Typing, 0, "a");
+        // The caret moved away in between.
+        step(&mut history, Kind::Typing, 5, "b");
+        // Something other than typing.
+        step(&mut history, Kind::Other, 6, "c");
+        step(&mut history, Kind::Typing, 7, "d");
+        // Sealed, e.g. by clicking.
+        history.seal();
+        step(&mut history, Kind::Typing, 8, "e");
+        // Saving.
+        history.mark_saved();
+        step(&mut history, Kind::Typing, 9, "f");
+
+        for text in &["f", "e", "d", "c", "b", "a"] {
+            assert_eq!(inserted(&mut history), [*text]);
+        }
+    }
+
+    #[test]
+    fn empty_steps_are_dropped() {
+        let mut history = History::new();
+        step(&mut history, Kind::Other, 0, "a");
+        history.start(Kind::Other, at(1));
+        history.finish(at(1));
+        assert_eq!(inserted(&mut history), ["a"]);
+        assert!(history.undo().is_none());
+    }
+
+    #[test]
+    fn saved_state() {
+        let mut history = History::new();
+        assert!(history.is_saved());
+        step(&mut history, Kind::Other, 0, "a");
+        assert!(!history.is_saved());
+
+        let group = history.undo().unwrap();
+        history.undone(group);
+        assert!(history.is_saved());
+
+        // Redoing gets back to the edit, but a new step can't.
+        let group = history.redo().unwrap();
+        history.redone(group);
+        history.mark_saved();
+        let group = history.undo().unwrap();
+        history.undone(group);
+        step(&mut history, Kind::Other, 0, "b");
+        let group = history.undo().unwrap();
+        history.undone(group);
+        assert!(!history.is_saved());
+    }
+}
@@ -0,0 +1,3755 @@
+use std::borrow::ToOwned;
+use std::cmp::{min, max, Ordering};
+use std::fs;
+use std::io;
+use std::iter::{once, repeat};
+use std::mem;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering, ATOMIC_USIZE_INIT};
+use std::time::SystemTime;
+use std::usize;
+use unicode_width::UnicodeWidthChar;
+
+use cfg::ColorScheme;
+use fuzzy::fuzzy_score;
+use glyph::GlyphMetrics;
+use rope::Rope;
+use seq::Seq;
+
+use ui::{BB, Dir, Px};
+use ui::layout::{CollectCx, CollectBB, Layout};
+use ui::clipboard;
+use ui::color::Scheme;
+use ui::draw::{Draw, DrawCx, MouseCursor};
+use ui::event::*;
+use ui::find::Pattern;
+use ui::tab;
+use ui::text;
+
+use ide::{highlight, rustc, vfs};
+use ide::rustc::{Completion, Definition, Reference, Rustc};
+
+use self::file::{Encoding, Format, LineEnding};
+use self::fold::{Fold, Folds};
+use self::history::{Change, History, Kind};
+
+mod file;
+mod fold;
+mod gutter;
+mod history;
+mod pane;
+mod search;
+
+pub use self::file::write_atomic;
+pub use self::pane::Pane;
+
+/// Caret and scroll position of an editor, e.g. to restore a session.
+#[derive(Copy, Clone, Debug)]
+pub struct View {
+    pub row: usize,
+    pub offset: usize,
+    pub scroll_start: usize,
+    pub scroll_sub: usize,
+    pub scroll_col: usize
+}
+
+/// Something to ask about at the top of the editor.
+#[derive(Copy, Clone, PartialEq)]
+enum Notice {
+    // The file changed on disk while there were unsaved changes.
+    ChangedOnDisk,
+    // Unsaved changes were brought back from a previous session.
+    Recovered,
+    // The file isn't valid UTF-8, so it's read and saved as Latin-1.
+    Latin1,
+    // The file has both LF and CRLF line endings, which are made the same on save.
+    MixedLineEndings
+}
+
+impl Notice {
+    /// What to ask about a file read in `format`, if anything.
+    fn for_format(format: Format) -> Option<Notice> {
+        if format.encoding == Encoding::Latin1 {
+            Some(Notice::Latin1)
+        } else if format.mixed_line_endings {
+            Some(Notice::MixedLineEndings)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct Editor {
+    // Area in which the text is drawn.
+    bb: BB<Px>,
+    font: text::Mono,
+    font_bold: text::MonoBold,
+    font_metrics: GlyphMetrics,
+    over: bool,
+    // Set while the mouse button is held down after clicking on the text.
+    drag: Option<Drag>,
+
+    // Caret is visible between [0, 0.5) and hidden between [0.5, 1).
+    blink_phase: f32,
+
+    scroll_start: usize,
+    // Wrapped segment of `scroll_start` at the top of the viewport.
+    scroll_sub: usize,
+    // First column in the viewport, only used without soft wrap.
+    scroll_col: usize,
+    // Soft-wrap lines at the right edge of the viewport.
+    wrap: bool,
+    folds: Folds,
+
+    selection_start: Caret,
+    caret: Caret,
+    vertical_col: usize,
+    // Selections other than the primary one, as (start, caret) pairs.
+    extra_selections: Vec<(Caret, Caret)>,
+
+    // Position and time since hover started.
+    hover: Option<(Caret, f32)>,
+
+    // Search from the find bar, with its matches highlighted.
+    pattern: Option<Pattern>,
+
+    // Starting row & column, separator column and content.
+    overlay: (usize, usize, usize, Vec<Line>),
+
+    // Unique to this editor, to find its tab again later.
+    id: usize,
+    // Path to the file on disk, none until an untitled buffer is saved.
+    path: Option<PathBuf>,
+    // Number in the title of an untitled buffer.
+    untitled: usize,
+    // Encoding and line endings to save the file with.
+    format: Format,
+    // Modification time of the file when it was last read or written.
+    disk_modified: Option<SystemTime>,
+    // Time since the file was last checked for changes.
+    disk_check: f32,
+    // Shown at the top, until one of its choices is picked.
+    notice: Option<Notice>,
+    // Incremented by every edit, to tell when the text was last autosaved.
+    edits: usize,
+    // Clean-ups done when saving.
+    trim_on_save: bool,
+    final_newline: bool,
+
+    text: Rope,
+    // Highlighting for each line in `text`.
+    lines: Seq<LineInfo>,
+    unsaved: bool,
+
+    history: History,
+    // Rows touched by the edit in progress.
+    edited_rows: Option<Range<usize>>,
+    modifiers: Modifiers,
+
+    rustc: Rustc,
+    new_rustc: Option<Rustc>,
+    rustc_dirty: Range<usize>,
+    // Of the open buffers, as last seen, to reanalyse when others change.
+    vfs_generation: usize,
+    // What to do with the definition asked for, and where the caret was then.
+    lookup: Option<(Lookup, Caret)>,
+    // Where the definition being peeked at (shown in `overlay`) was asked for.
+    peek: Option<Caret>,
+    // Where renaming started, and the new name typed so far (shown in `overlay`).
+    renaming: Option<(Caret, String)>,
+    // The completion popup (shown in `overlay`).
+    completing: Option<Completing>,
+    // Waiting for `take_jump` and `take_references`.
+    jump: Option<Jump>,
+    references: Option<References>
+}
+
+#[derive(Clone, PartialEq)]
+enum Lookup {
+    Jump,
+    Peek,
+    References,
+    // With the new name.
+    Rename(String)
+}
+
+/// Going to a definition, possibly in another file.
+pub struct Jump {
+    /// Row and byte offset of the caret before jumping.
+    pub from: (usize, usize),
+    pub to: Definition
+}
+
+/// Everything referring to something, to list them or rename it.
+pub struct References {
+    pub name: String,
+    pub references: Vec<Reference>,
+    pub rename_to: Option<String>
+}
+
+/// Names that could be typed where the caret is, filtered by what's typed so far.
+struct Completing {
+    // Where the name being completed starts.
+    start: Caret,
+    // Whether the analysis was asked, which waits until it's up to date.
+    requested: bool,
+    // `None` until the analysis answers.
+    candidates: Option<Vec<Completion>>,
+    // What `shown` was filtered by.
+    typed: Option<String>,
+    // Indices of the candidates matching what's typed, best first.
+    shown: Vec<usize>,
+    selected: usize,
+    scroll: usize
+}
+
+#[derive(Copy, Clone)]
+pub struct Caret {
+    row: usize,
+    col: usize,
+    offset: usize
+}
+
+impl Caret {
+    fn advance(&mut self, c: char, forward: bool) {
+        let w = if c == '\t' {
+            // FIXME this won't work backwards.
+            7 - (self.col + 7) % 8
+        } else {
+            UnicodeWidthChar::width(c).unwrap_or(1)
+        };
+        let l = c.len_utf8();
+
+        if forward {
+            self.col += w;
+            self.offset += l;
+        } else {
+            self.col -= w;
+            self.offset -= l;
+        }
+    }
+
+    /// Compute the caret at the end of `text`, were it to be inserted here.
+    fn after(mut self, text: &str) -> Caret {
+        for c in text.chars() {
+            if c == '\n' {
+                self.row += 1;
+                self.col = 0;
+                self.offset = 0;
+            } else {
+                self.advance(c, true);
+            }
+        }
+        self
+    }
+}
+
+impl PartialEq for Caret {
+    fn eq(&self, other: &Caret) -> bool {
+        self.row == other.row && self.col == other.col
+    }
+}
+
+impl Eq for Caret {}
+
+impl PartialOrd for Caret {
+    fn partial_cmp(&self, other: &Caret) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Caret {
+    fn cmp(&self, other: &Caret) -> Ordering {
+        if self.row == other.row {
+            self.col.cmp(&other.col)
+        } else {
+            self.row.cmp(&other.row)
+        }
+    }
+}
+
+/// A selection being made with the mouse.
+#[derive(Copy, Clone)]
+struct Drag {
+    // Selecting characters (1), words (2) or lines (3 and up), by the click count.
+    clicks: usize,
+    // What was selected by the click, which dragging extends from.
+    anchor: (Caret, Caret),
+    pos: [Px; 2],
+    // Rows to autoscroll by but not scrolled yet, while outside the viewport.
+    pending: f32
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum CharClass {
+    Space,
+    Ident,
+    Symbol
+}
+
+impl CharClass {
+    fn of(c: char) -> CharClass {
+        if c.is_whitespace() {
+            CharClass::Space
+        } else if c.is_alphanumeric() || c == '_' {
+            CharClass::Ident
+        } else {
+            CharClass::Symbol
+        }
+    }
+
+    /// Whether `c` continues the word being skipped, which starts at the first non-space.
+    fn continues(class: &mut Option<CharClass>, c: char) -> bool {
+        let c = CharClass::of(c);
+        match *class {
+            Some(class) => class == c,
+            None => {
+                if c != CharClass::Space {
+                    *class = Some(c);
+                }
+                true
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LineInfo {
+    columns: usize,
+    hl_depth: usize,
+    ranges: Vec<(usize, highlight::Style)>,
+    // Unmatched `}` at the start and `{` at the end, outside of strings and comments.
+    braces: (usize, usize),
+    // Whether the line has a `//BEGIN` (true) or `//END` (false) region marker.
+    region: Option<bool>
+}
+
+impl LineInfo {
+    fn new() -> LineInfo {
+        LineInfo {
+            columns: 0,
+            hl_depth: 1,
+            ranges: vec![],
+            braces: (0, 0),
+            region: None
+        }
+    }
+
+    fn update_folding(&mut self, data: &str) {
+        self.braces = (0, 0);
+        self.region = None;
+        let mut offset = 0;
+        for &(len, style) in &self.ranges {
+            let text = &data[offset..offset+len];
+            offset += len;
+            if style == highlight::styles::RegionMarker {
+                if text.starts_with("//BEGIN") {
+                    self.region = Some(true);
+                } else if text.starts_with("//END") {
+                    self.region = Some(false);
+                }
+            } else if style == highlight::styles::Symbol {
+                for c in text.chars() {
+                    match c {
+                        '{' => self.braces.1 += 1,
+                        '}' if self.braces.1 > 0 => self.braces.1 -= 1,
+                        '}' => self.braces.0 += 1,
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn update_columns(&mut self, data: &str) {
+        let mut k = Caret { row: 0, col: 0, offset: 0 };
+        for c in data.chars() {
+            k.advance(c, true);
+        }
+        self.columns = k.col;
+    }
+}
+
+/// A line with its own text, used for overlays.
+#[derive(Debug)]
+struct Line {
+    data: String,
+    info: LineInfo
+}
+
+impl Line {
+    fn new(data: String) -> Line {
+        Line {
+            data: data,
+            info: LineInfo::new()
+        }
+    }
+
+    fn update_columns(&mut self) {
+        self.info.update_columns(&self.data);
+    }
+}
+
+/// Untitled buffers created so far, to number them.
+static UNTITLED_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Editors created so far, to tell them apart.
+static EDITOR_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+impl Editor {
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Editor> {
+        let path: &Path = path.as_ref();
+
+        let (data, format) = try!(file::load(path));
+        // Absolute paths tell whether two tabs have the same file.
+        let path = fs::canonicalize(path).unwrap_or(path.to_path_buf());
+        let mut editor = Editor::with_text(Some(path), &data, format);
+        editor.notice = Notice::for_format(format);
+        Ok(editor)
+    }
+
+    /// An empty buffer without a file, which gets one when it's first saved.
+    pub fn untitled() -> Editor {
+        let mut editor = Editor::with_text(None, "", Format::default());
+        editor.untitled = UNTITLED_COUNT.fetch_add(1, AtomicOrdering::SeqCst) + 1;
+        editor
+    }
+
+    fn with_text(path: Option<PathBuf>, data: &str, format: Format) -> Editor {
+        let text = Rope::from(data);
+        let lines = (0..text.len_lines()).map(|_| LineInfo::new()).collect();
+        let rustc = Rustc::start(path.clone(), text.clone());
+
+        let caret = Caret {
+            row: 0,
+            col: 0,
+            offset: 0
+        };
+
+        let mut editor = Editor {
+            bb: BB::default(),
+            font: text::Mono,
+            font_bold: text::MonoBold,
+            font_metrics: GlyphMetrics::default(),
+            over: false,
+            drag: None,
+            scroll_start: 0,
+            scroll_sub: 0,
+            scroll_col: 0,
+            wrap: false,
+            folds: Folds::new(),
+
+            blink_phase: 0.0,
+
+            selection_start: caret,
+            caret: caret,
+            vertical_col: 0,
+            extra_selections: vec![],
+
+            hover: None,
+            pattern: None,
+            overlay: (0, 0, 0, vec![]),
+
+            disk_modified: path.as_ref().and_then(|path| file::modified(path)),
+            id: EDITOR_COUNT.fetch_add(1, AtomicOrdering::SeqCst),
+            path: path,
+            untitled: 0,
+            format: format,
+            disk_check: 0.0,
+            notice: None,
+            edits: 0,
+            trim_on_save: false,
+            final_newline: false,
+            text: text,
+            lines: lines,
+            unsaved: false,
+
+            history: History::new(),
+            edited_rows: None,
+            modifiers: Modifiers::default(),
+
+            rustc: rustc,
+            new_rustc: None,
+            rustc_dirty: 0..0,
+            vfs_generation: vfs::generation(),
+            lookup: None,
+            peek: None,
+            renaming: None,
+            completing: None,
+            jump: None,
+            references: None
+        };
+
+        let num_lines = editor.lines.len();
+        editor.update_hl(0..num_lines, false);
+
+        editor
+    }
+
+    /// Tells this editor apart from all the others, even ones with the same file.
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_ref().map(|path| path.as_path())
+    }
+
+    /// The path for messages, or `Untitled-N` if there's none yet.
+    pub fn name(&self) -> String {
+        match self.path {
+            Some(ref path) => path.display().to_string(),
+            None => format!("Untitled-{}", self.untitled)
+        }
+    }
+
+    fn file_path(&self) -> io::Result<PathBuf> {
+        self.path.clone().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} has no file yet", self.name()))
+        })
+    }
+
+    pub fn is_saved(&self) -> bool {
+        !self.unsaved
+    }
+
+    /// Write the text back in the format it was read in.
+    pub fn save(&mut self) -> io::Result<()> {
+        let path = try!(self.file_path());
+        println!("Saving {:?}...", path);
+        self.clean_up_whitespace();
+        // Encode first, so that the file is left alone if that fails.
+        let data = try!(file::encode(&self.text.to_string(), self.format));
+        try!(file::write_atomic(&path, &data));
+        self.disk_modified = file::modified(&path);
+        self.notice = None;
+        self.history.mark_saved();
+        self.unsaved = false;
+        self.publish();
+        Ok(())
+    }
+
+    /// Save to a new path, which is then used from here on.
+    pub fn save_as(&mut self, path: PathBuf) -> io::Result<()> {
+        let old = mem::replace(&mut self.path, Some(path.clone()));
+        let result = self.save();
+        match result {
+            Ok(()) => {
+                if let Some(old) = old {
+                    vfs::remove(&old, self.id);
+                }
+                self.path = Some(fs::canonicalize(&path).unwrap_or(path));
+            }
+            Err(_) => self.path = old
+        }
+        result
+    }
+
+    /// Move the file to a new path, leaving any unsaved changes unsaved.
+    /// Untitled buffers have nothing to move, so they're saved instead.
+    pub fn rename(&mut self, path: PathBuf) -> io::Result<()> {
+        let old = match self.path.clone() {
+            Some(old) => old,
+            None => return self.save_as(path)
+        };
+        try!(fs::rename(&old, &path));
+        self.moved_to(fs::canonicalize(&path).unwrap_or(path));
+        Ok(())
+    }
+
+    /// Follow the file to `path`, after it was moved by something else.
+    pub fn moved_to(&mut self, path: PathBuf) {
+        if let Some(ref old) = self.path {
+            vfs::remove(old, self.id);
+        }
+        self.disk_modified = file::modified(&path);
+        self.path = Some(path);
+        self.publish();
+    }
+
+    /// Let the analysis of other files see unsaved text, through `ide::vfs`.
+    fn publish(&mut self) {
+        if let Some(ref path) = self.path {
+            if self.unsaved {
+                vfs::set(path, self.id, self.text.clone());
+            } else {
+                vfs::remove(path, self.id);
+            }
+        }
+        self.vfs_generation = vfs::generation();
+    }
+
+    /// Replace the text with the file on disk, as a step that can be undone.
+    pub fn reload(&mut self) -> io::Result<()> {
+        let path = try!(self.file_path());
+        let (data, format) = try!(file::load(&path));
+        self.format = format;
+        self.disk_modified = file::modified(&path);
+        self.notice = Notice::for_format(format);
+        self.replace_text(&data);
+        self.history.mark_saved();
+        self.unsaved = false;
+        self.publish();
+        Ok(())
+    }
+
+    /// Bring back unsaved text, e.g. from before a crash, keeping what's on disk
+    /// as the saved state, so that it can be reloaded or undone back to.
+    pub fn recover(&mut self, data: &str) {
+        self.replace_text(data);
+        if self.unsaved {
+            self.notice = Some(Notice::Recovered);
+        }
+    }
+
+    /// Text to keep in case rid3 exits without saving it.
+    pub fn contents(&self) -> String {
+        self.text.to_string()
+    }
+
+    /// Number of edits so far, which changes whenever the text does.
+    pub fn edit_count(&self) -> usize {
+        self.edits
+    }
+
+    /// Where the caret and the viewport are, to be restored by `set_view`.
+    pub fn view(&self) -> View {
+        View {
+            row: self.caret.row,
+            offset: self.caret.offset,
+            scroll_start: self.scroll_start,
+            scroll_sub: self.scroll_sub,
+            scroll_col: self.scroll_col
+        }
+    }
+
+    pub fn set_view(&mut self, view: View) {
+        let row = min(view.row, self.text.len_lines() - 1);
+        let line = self.text.line(row);
+        let mut offset = min(view.offset, line.len());
+        while !line.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let k = self.caret_at(row, offset);
+        self.extra_selections.clear();
+        self.selection_start = k;
+        self.caret = k;
+        self.vertical_col = k.col;
+        self.scroll_start = min(view.scroll_start, self.text.len_lines() - 1);
+        self.scroll_sub = view.scroll_sub;
+        self.scroll_col = view.scroll_col;
+    }
+
+    /// Replace all of the text as one step, keeping the caret on the same row and column.
+    fn replace_text(&mut self, data: &str) {
+        if data == self.text.to_string() {
+            return;
+        }
+        let (row, col) = (self.caret.row, self.caret.col);
+        let last = self.text.len_lines() - 1;
+        let end = self.caret_at(last, self.text.line(last).len());
+        self.extra_selections.clear();
+        self.begin_edit(Kind::Other);
+        self.change(Caret { row: 0, col: 0, offset: 0 }..end, data.chars());
+        let k = self.caret_at_col(min(row, self.text.len_lines() - 1), col);
+        self.end_edit(k);
+    }
+
+    /// Dismiss the notice at the top, keeping the text as it is.
+    fn keep_mine(&mut self) {
+        self.notice = None;
+    }
+
+    /// Act on one of the buttons of the notice at the top, see `banner`.
+    fn press_banner(&mut self, button: usize) {
+        match (self.notice, button) {
+            // An untitled buffer has no file to go back to, only being empty.
+            (Some(Notice::Recovered), 0) if self.path.is_none() => {
+                self.replace_text("");
+                self.history.mark_saved();
+                self.unsaved = false;
+            }
+            (Some(Notice::ChangedOnDisk), 0) | (Some(Notice::Recovered), 0) => {
+                if let Err(e) = self.reload() {
+                    println!("Couldn't reload {}: {}", self.name(), e);
+                }
+                return;
+            }
+            (Some(Notice::Latin1), 0) => self.format.encoding = Encoding::Utf8,
+            (Some(Notice::MixedLineEndings), i) => {
+                self.format.line_ending = if i == 0 { LineEnding::Lf } else { LineEnding::CrLf };
+            }
+            _ => {}
+        }
+        self.keep_mine();
+    }
+
+    /// Check the file on disk every so often, see `check_disk`. This is done
+    /// for every tab, so that e.g. a `git checkout` is seen in all of them.
+    pub fn poll_disk(&mut self, dt: f32) -> bool {
+        self.disk_check += dt;
+        if self.disk_check < DISK_CHECK_SPACING {
+            return false;
+        }
+        self.disk_check = 0.0;
+        self.check_disk()
+    }
+
+    /// Look for changes to the file made by other programs, reloading it
+    /// if there's nothing unsaved, or else asking what to do.
+    fn check_disk(&mut self) -> bool {
+        let modified = self.path.as_ref().and_then(|path| file::modified(path));
+        if modified.is_none() || modified == self.disk_modified {
+            return false;
+        }
+        self.disk_modified = modified;
+        if self.unsaved {
+            self.notice = Some(Notice::ChangedOnDisk);
+        } else if let Err(e) = self.reload() {
+            println!("Couldn't reload {}: {}", self.name(), e);
+        }
+        true
+    }
+
+    /// Strip trailing whitespace and end the file with a newline, if enabled,
+    /// as one step that can be undone.
+    fn clean_up_whitespace(&mut self) {
+        let mut trims = vec![];
+        if self.trim_on_save {
+            for (row, line) in self.text.lines(0..self.text.len_lines()).enumerate() {
+                let len = line.trim_right_matches(|c: char| c == ' ' || c == '\t').len();
+                if len < line.len() {
+                    trims.push((row, len..line.len()));
+                }
+            }
+        }
+        let last = self.text.len_lines() - 1;
+        let last_trimmed = trims.last().and_then(|&(row, ref range)| {
+            if row == last { Some(range.start) } else { None }
+        });
+        let last_len = last_trimmed.unwrap_or(self.text.line(last).len());
+        let add_newline = self.final_newline && last_len > 0;
+        if trims.is_empty() && !add_newline {
+            return;
+        }
+
+        let (all, primary) = self.selections();
+        self.begin_edit(Kind::Other);
+        for (row, range) in trims {
+            let (start, end) = (self.caret_at(row, range.start), self.caret_at(row, range.end));
+            self.change(start..end, "".chars());
+        }
+        if add_newline {
+            let end = self.caret_at(last, last_len);
+            self.change(end..end, once('\n'));
+        }
+
+        let clamp = |ed: &Editor, k: Caret| ed.caret_at(k.row, min(k.offset, ed.text.line(k.row).len()));
+        let all: Vec<_> = all.into_iter().map(|(s, k)| (clamp(self, s), clamp(self, k))).collect();
+        self.end_edit_all(all, primary);
+    }
+
+    /// Message of the notice to show above the text, if any, and the labels of
+    /// its two buttons, e.g. to reload the file or keep the text as it is.
+    fn banner(&self) -> Option<(&'static str, [&'static str; 2])> {
+        Some(match self.notice {
+            Some(Notice::ChangedOnDisk) => ("The file has changed on disk.", ["Reload", "Keep mine"]),
+            Some(Notice::Recovered) => ("Unsaved changes were recovered.", ["Discard", "Keep"]),
+            Some(Notice::Latin1) => ("The file isn't valid UTF-8, it was read as Latin-1.",
+                                     ["Save as UTF-8", "Keep Latin-1"]),
+            Some(Notice::MixedLineEndings) => ("The file mixes LF and CRLF line endings.",
+                                               ["Use LF", "Use CRLF"]),
+            None => return None
+        })
+    }
+
+    /// Put the caret at a byte offset in a row, e.g. on a search result.
+    pub fn goto(&mut self, row: usize, offset: usize) {
+        let row = min(row, self.text.len_lines() - 1);
+        let offset = min(offset, self.text.line(row).len());
+        let k = self.caret_at(row, offset);
+        let rows = self.visible_rows();
+        let visible = self.visual_index(self.visual_row(k)).map_or(false, |i| i < rows);
+        self.extra_selections.clear();
+        self.move_to(k, false);
+        self.history.seal();
+
+        // Show a few rows of context above, if the caret wasn't already visible.
+        if !visible {
+            self.scroll_start = row.saturating_sub(3);
+            self.scroll_sub = 0;
+        }
+    }
+
+    /// Where `row` starts in the text last analysed, unless it was edited
+    /// since, or that analysis isn't done yet.
+    fn analysed_row_offset(&self, row: usize) -> Option<usize> {
+        if self.rustc.is_compiling() {
+            return None;
+        }
+        let (start, end) = (self.rustc_dirty.start, self.rustc_dirty.end);
+        let line_offset = self.text.line_to_byte(row);
+        if row < start || (start, end) == (0, 0) {
+            Some(line_offset)
+        } else if row >= end {
+            let to_end = self.text.len_bytes() + 1 - line_offset;
+            Some(self.rustc.file_end + 1 - to_end)
+        } else {
+            None
+        }
+    }
+
+    /// Ask where whatever is at `k` is defined, or used, see `Lookup`.
+    fn look_up(&mut self, k: Caret, lookup: Lookup) {
+        let offset = match self.analysed_row_offset(k.row) {
+            Some(line_offset) => line_offset + k.offset,
+            None => {
+                println!("{} is still being analysed", self.name());
+                return;
+            }
+        };
+        match lookup {
+            Lookup::Jump | Lookup::Peek => self.rustc.definition_at(offset),
+            Lookup::References => self.rustc.references_at(offset, None),
+            Lookup::Rename(ref new_name) => self.rustc.references_at(offset, Some(new_name.clone()))
+        }
+        self.lookup = Some((lookup, self.caret));
+    }
+
+    /// The definition to go to, after e.g. F12, which may be in another file.
+    pub fn take_jump(&mut self) -> Option<Jump> {
+        self.jump.take()
+    }
+
+    /// References found to be listed, or renamed, which may be in other files.
+    pub fn take_references(&mut self) -> Option<References> {
+        self.references.take()
+    }
+
+    /// Hide the peeked at definition, or stop renaming.
+    fn close_peek(&mut self) -> bool {
+        if self.peek.take().is_some() || self.renaming.take().is_some() {
+            self.overlay = (0, 0, 0, vec![]);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Start typing a new name for the identifier at the caret.
+    fn start_rename(&mut self) {
+        let (start, end) = self.word_at(self.caret);
+        let name = self.text_range(start..end);
+        if !name.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_') {
+            println!("There's nothing to rename there");
+            return;
+        }
+        let k = self.caret;
+        self.overlay = self.build_rename_prompt(k, &name, &name);
+        self.renaming = Some((k, name));
+    }
+
+    fn build_rename_prompt(&self, k: Caret, old: &str, new: &str) -> (usize, usize, usize, Vec<Line>) {
+        let label = format!(" Rename {} to ", old);
+        let mut line = Line::new(format!("{}{}_ ", label, new));
+        line.info.ranges.push((label.len(), highlight::Style {
+            color: ColorScheme.neutral(),
+            bold: false
+        }));
+        line.info.ranges.push((new.len() + 2, highlight::Style {
+            color: ColorScheme.normal(),
+            bold: true
+        }));
+        line.update_columns();
+        (k.row + 1, 0, 0, vec![line])
+    }
+
+    fn update_rename_prompt(&mut self) {
+        if let Some((k, ref name)) = self.renaming {
+            let (start, end) = self.word_at(k);
+            let old = self.text_range(start..end);
+            self.overlay = self.build_rename_prompt(k, &old, name);
+        }
+    }
+
+    /// Keys pressed while renaming, which don't get to do anything else.
+    fn rename_key(&mut self, key: Key) {
+        // Return is confirmed by the newline it types, see `confirm_rename`.
+        match key {
+            Key::Escape => {
+                self.close_peek();
+            }
+            Key::Back => {
+                if let Some((_, ref mut name)) = self.renaming {
+                    name.pop();
+                }
+                self.update_rename_prompt();
+            }
+            _ => {}
+        }
+    }
+
+    fn confirm_rename(&mut self) {
+        if let Some((k, name)) = self.renaming.take() {
+            self.overlay = (0, 0, 0, vec![]);
+            let (start, _) = self.word_at(k);
+            self.look_up(start, Lookup::Rename(name));
+        }
+    }
+
+    /// Replace byte ranges of rows, in document order and wherever they still
+    /// hold `old`, with `new`, as a single step that can be undone.
+    pub fn rename_ranges(&mut self, ranges: &[(usize, Range<usize>)], old: &str, new: &str) {
+        let (row, col) = (self.caret.row, self.caret.col);
+        self.extra_selections.clear();
+        self.begin_edit(Kind::Other);
+        // From the end, so that the earlier ranges stay where they are.
+        for &(r, ref range) in ranges.iter().rev() {
+            let matches = r < self.text.len_lines() && {
+                let line = self.text.line(r).as_bytes();
+                range.end <= line.len() && line[range.clone()] == *old.as_bytes()
+            };
+            if matches {
+                let (start, end) = (self.caret_at(r, range.start), self.caret_at(r, range.end));
+                self.change(start..end, new.chars());
+            }
+        }
+        let k = self.caret_at_col(min(row, self.text.len_lines() - 1), col);
+        self.end_edit(k);
+    }
+
+    /// Ask what could be typed at the caret, e.g. after Ctrl+Space or a `.`.
+    fn start_completion(&mut self) {
+        let k = self.caret;
+        let start = {
+            let line = self.text.line(k.row);
+            line[..k.offset].char_indices().rev().take_while(|&(_, c)| c.is_alphanumeric() || c == '_')
+                            .last().map_or(k.offset, |(i, _)| i)
+        };
+        self.hover = None;
+        self.close_peek();
+        self.completing = Some(Completing {
+            start: self.caret_at(k.row, start),
+            requested: false,
+            candidates: None,
+            typed: None,
+            shown: vec![],
+            selected: 0,
+            scroll: 0
+        });
+        self.request_completions();
+    }
+
+    /// Ask for what could be typed where the completion starts, once the
+    /// whole text is analysed, so e.g. locals declared since are known.
+    fn request_completions(&mut self) {
+        let start = match self.completing {
+            Some(ref c) if !c.requested => c.start,
+            _ => return
+        };
+        if self.rustc_dirty != (0..0) || self.completion_prefix().is_none() {
+            return;
+        }
+        let line_offset = match self.analysed_row_offset(start.row) {
+            Some(line_offset) => line_offset,
+            None => return
+        };
+        let receiver = receiver_before(&self.text.line(start.row)[..start.offset]);
+        self.rustc.completions_at(line_offset + start.offset, receiver);
+        if let Some(ref mut c) = self.completing {
+            c.requested = true;
+        }
+    }
+
+    fn close_completion(&mut self) -> bool {
+        if self.completing.take().is_some() {
+            self.overlay = (0, 0, 0, vec![]);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// What's been typed of the name being completed, unless the caret left it.
+    fn completion_prefix(&self) -> Option<String> {
+        let start = match self.completing {
+            Some(ref c) => c.start,
+            None => return None
+        };
+        let k = self.caret;
+        if k.row != start.row || k.offset < start.offset || self.selection_start != k ||
+           !self.extra_selections.is_empty() {
+            return None;
+        }
+        let line = self.text.line(k.row);
+        if !line.is_char_boundary(start.offset) {
+            return None;
+        }
+        let typed = &line[start.offset..k.offset];
+        if typed.chars().all(|c| c.is_alphanumeric() || c == '_') {
+            Some(typed.to_owned())
+        } else {
+            None
+        }
+    }
+
+    /// Match the candidates against what's typed, closing the popup if none do.
+    fn refilter_completions(&mut self, typed: String) {
+        let matched = match self.completing {
+            Some(ref mut c) => {
+                if let Some(ref candidates) = c.candidates {
+                    let mut scored: Vec<_> = candidates.iter().enumerate().filter_map(|(i, candidate)| {
+                        fuzzy_score(&typed, &candidate.name).map(|score| (score, i))
+                    }).collect();
+                    // Stable, so that equally good ones stay closest first.
+                    scored.sort_by(|a, b| b.0.cmp(&a.0));
+                    c.shown = scored.into_iter().map(|(_, i)| i).collect();
+                }
+                c.typed = Some(typed);
+                c.selected = 0;
+                c.scroll = 0;
+                c.candidates.is_none() || !c.shown.is_empty()
+            }
+            None => return
+        };
+        if matched {
+            self.overlay = self.build_completions();
+        } else {
+            self.close_completion();
+        }
+    }
+
+    fn move_completion(&mut self, down: bool) {
+        if let Some(ref mut c) = self.completing {
+            if c.shown.is_empty() {
+                return;
+            }
+            c.selected = if down {
+                (c.selected + 1) % c.shown.len()
+            } else {
+                (c.selected + c.shown.len() - 1) % c.shown.len()
+            };
+            if c.selected < c.scroll {
+                c.scroll = c.selected;
+            } else if c.selected >= c.scroll + COMPLETION_ROWS {
+                c.scroll = c.selected + 1 - COMPLETION_ROWS;
+            }
+        }
+        self.overlay = self.build_completions();
+    }
+
+    /// Replace what's typed of the name with the selected completion.
+    fn accept_completion(&mut self) -> bool {
+        let (start, name) = match self.completing.take() {
+            Some(c) => match (c.candidates, c.shown.get(c.selected)) {
+                (Some(candidates), Some(&i)) => (c.start, candidates[i].name.clone()),
+                _ => return false
+            },
+            None => return false
+        };
+        self.overlay = (0, 0, 0, vec![]);
+        let k = self.caret;
+        self.begin_edit(Kind::Other);
+        let end = self.change(start..k, name.chars());
+        self.end_edit(end);
+        true
+    }
+
+    fn build_completions(&self) -> (usize, usize, usize, Vec<Line>) {
+        let c = match self.completing {
+            Some(ref c) => c,
+            None => return (0, 0, 0, vec![])
+        };
+        let candidates = match c.candidates {
+            Some(ref candidates) => candidates,
+            None => return (0, 0, 0, vec![])
+        };
+        // As wide as the widest name of all, so it doesn't change when scrolling.
+        let width = c.shown.iter().map(|&i| candidates[i].name.chars().count()).max().unwrap_or(0);
+        let end = min(c.scroll + COMPLETION_ROWS, c.shown.len());
+        let mut lines: Vec<_> = c.shown[c.scroll..end].iter().map(|&i| {
+            let candidate = &candidates[i];
+            let padding: String = repeat(' ').take(width - candidate.name.chars().count()).collect();
+            Line::new(format!(" {}{}  {} ", candidate.name, padding, candidate.signature))
+        }).collect();
+
+        let (_, hl) = highlight::Rust::run(lines.iter().map(|line| &line.data[..]));
+        for (line, (_, ranges)) in lines.iter_mut().zip(hl.into_iter()) {
+            line.info.ranges = ranges;
+            line.update_columns();
+        }
+        (c.start.row + 1, c.start.col.saturating_sub(1), width + 1, lines)
+    }
+
+    /// Where the overlay stays shown below, instead of following the mouse.
+    fn overlay_pinned(&self) -> Option<Caret> {
+        self.peek.or(self.renaming.as_ref().map(|&(k, _)| k))
+                 .or(self.completing.as_ref().map(|c| c.start))
+    }
+
+    fn build_peek(&self, k: Caret, def: &Definition) -> (usize, usize, usize, Vec<Line>) {
+        let name = def.path.as_ref().map_or(self.name(), |path| path.display().to_string());
+        let title = format!(" {}:{} ", name, def.row + 1);
+        let mut lines: Vec<_> = def.preview.iter().map(|data| Line::new(format!(" {} ", data))).collect();
+        let (_, hl) = highlight::Rust::run(lines.iter().map(|line| &line.data[..]));
+        for (line, (_, ranges)) in lines.iter_mut().zip(hl.into_iter()) {
+            line.info.ranges = ranges;
+        }
+
+        let mut header = Line::new(title);
+        header.info.ranges.push((header.data.len(), highlight::Style {
+            color: ColorScheme.neutral(),
+            bold: true
+        }));
+        lines.insert(0, header);
+        for line in &mut lines {
+            line.update_columns();
+        }
+        (k.row + 1, 0, 0, lines)
+    }
+
+    fn build_overlay(&self, k: Caret,
+                     diagnostics: &[(rustc::Level, usize, String)],
+                     types: &mut [(Range<usize>, String)])
+                     -> (usize, usize, usize, Vec<Line>) {
+        let row = k.row;
+
+        if types.is_empty() {
+            let mut start_col = self.lines[row].columns;
+            let lines = diagnostics.iter().flat_map(|&(level, col, ref message)| {
+                start_col = min(start_col, col);
+
+                message.split('\n').enumerate().map(move |(i, data)| {
+                    let mut line = Line::new("".to_owned());
+                    if i == 0 {
+                        line.data = format!("{}: ", level);
+                        line.info.ranges.push((line.data.len(), highlight::Style {
+                            color: match level {
+                                rustc::Level::Bug | rustc::Level::Fatal | rustc::Level::Error => {
+                                    ColorScheme.negative()
+                                }
+                                rustc::Level::Warning => ColorScheme.neutral(),
+                                _ => ColorScheme.positive()
+                            },
+                            bold: true
+                        }));
+                    }
+                    line.data.push_str(data);
+                    line.info.ranges.push((data.len(), highlight::styles::NormalText));
+                    line.update_columns();
+                    line
+                })
+            }).collect();
+            return (k.row + 1, start_col, 0, lines);
+        }
+
+        // Ascending by start, descending by width.
+        types.sort_by(|&(ref a, _), &(ref b, _)| {
+            (a.start, b.end - b.start).cmp(&(b.start, a.end - a.start))
+        });
+
+        let line = self.text.line(row);
+        let mut min = line.len();
+        let mut max = 0;
+        for &(ref range, _) in &types[..] {
+            if min > range.start {
+                min = range.start;
+            }
+            if max < range.end {
+                max = range.end;
+            }
+        }
+
+        let mut k = Caret {
+            row: 0,
+            col: 0,
+            offset: 0
+        };
+        for c in line[..min].chars() {
+            k.advance(c, true);
+        }
+        let mut separator = 0;
+        let mut lines: Vec<_> = types.iter().map(|&(ref range, ref ty)| {
+            let mut k = k;
+            let mut s = " ".to_string();
+            for (i, c) in line[min..max].char_indices() {
+                let prev_col = k.col;
+                k.advance(c, true);
+                if range.start <= min + i && min + i < range.end {
+                    s.push(c);
+                } else {
+                    s.extend(repeat(' ').take(k.col - prev_col));
+                }
+            }
+            separator = k.col;
+            s.push_str("  ");
+            s.push_str(ty);
+            s.push(' ');
+            Line::new(s)
+        }).collect();
+
+        let (_, hl) = highlight::Rust::run(lines.iter().map(|line| &line.data[..]));
+        for (line, (_, ranges)) in lines.iter_mut().zip(hl.into_iter()) {
+            line.info.ranges = ranges;
+            line.update_columns();
+        }
+        let start_col = if k.col == 0 { 0 } else { k.col - 1 };
+        (row + 1, start_col, separator - start_col, lines)
+    }
+
+    /// Visual row and (fractional) column at a position, if there's a row there.
+    fn pos_to_visual(&self, [x, y]: [Px; 2]) -> Option<((usize, usize), Px)> {
+        let metrics = self.font_metrics;
+        if metrics.width == 0.0 {
+            return None;
+        }
+
+        let bb = self.bb;
+        if x > bb.x2 || y > bb.y2 {
+            return None;
+        }
+        let [x, y] = [x - bb.x1, y - bb.y1];
+        if x < 0.0 || y < 0.0 {
+            return None;
+        }
+
+        let i = (y / metrics.height) as usize;
+        self.visual_rows(i + 1).get(i).map(|&(row, sub)| {
+            ((row, sub), x / metrics.width + self.first_col(sub) as Px)
+        })
+    }
+
+    fn pos_to_caret(&self, pos: [Px; 2]) -> Option<Caret> {
+        let ((row, _), col) = match self.pos_to_visual(pos) {
+            Some(v) => v,
+            None => return None
+        };
+
+        let mut k = Caret { row: row, col: 0, offset: 0 };
+        for c in self.text.line(row).chars() {
+            let prev_k = k;
+            k.advance(c, true);
+            if k.col as Px > col {
+                return Some(prev_k);
+            }
+        }
+        Some(k)
+    }
+
+    /// Like `pos_to_caret`, but for positions outside the viewport too,
+    /// which go to the closest row and column in it.
+    fn drag_caret(&self, [x, y]: [Px; 2]) -> Caret {
+        let bb = self.bb;
+        let x = x.max(bb.x1).min(bb.x2);
+        let y = y.max(bb.y1).min(bb.y2 - 1.0);
+        self.pos_to_caret([x, y]).unwrap_or_else(|| {
+            // Below the end of the text.
+            let last = self.text.len_lines() - 1;
+            self.caret_at(last, self.text.line(last).len())
+        })
+    }
+
+    /// Move a caret forwards or backwards, wrapping at line ends.
+    fn advance_caret(&self, mut k: Caret, dir: Dir) -> Caret {
+        let line = self.text.line(k.row);
+
+        match dir {
+            Dir::Right => {
+                if let Some(c) = line[k.offset..].chars().next() {
+                    k.advance(c, true);
+                } else if let Some(row) = self.next_shown_row(k.row, true) {
+                    k.row = row;
+                    k.col = 0;
+                    k.offset = 0;
+                }
+            }
+            Dir::Left => {
+                if let Some(c) = line[..k.offset].chars().next_back() {
+                    k.advance(c, false);
+                } else if let Some(row) = self.next_shown_row(k.row, false) {
+                    k.row = row;
+                    k.col = self.lines[k.row].columns;
+                    k.offset = self.text.line(k.row).len();
+                }
+            }
+            Dir::Down => return self.advance_vertically(k, true),
+            Dir::Up => return self.advance_vertically(k, false)
+        }
+        k
+    }
+
+    /// The caret one character or line break away from `k`, even into a fold,
+    /// so deleting next to a fold only joins the rows around it.
+    fn step_caret(&self, k: Caret, forward: bool) -> Caret {
+        let line = self.text.line(k.row);
+        if forward {
+            match line[k.offset..].chars().next() {
+                Some(c) => self.caret_at(k.row, k.offset + c.len_utf8()),
+                None if k.row + 1 < self.text.len_lines() => Caret { row: k.row + 1, col: 0, offset: 0 },
+                None => k
+            }
+        } else {
+            match line[..k.offset].chars().next_back() {
+                Some(c) => self.caret_at(k.row, k.offset - c.len_utf8()),
+                None if k.row > 0 => self.caret_at(k.row - 1, self.text.line(k.row - 1).len()),
+                None => k
+            }
+        }
+    }
+
+    /// Move a caret to the next (or previous) visual row, aiming for
+    /// `k.col`, which is relative to the start of the visual row.
+    fn advance_vertically(&self, k: Caret, down: bool) -> Caret {
+        let v = self.visual_row(self.caret_at(k.row, k.offset));
+        match self.next_visual_row(v, down) {
+            Some((row, sub)) => self.caret_at_col(row, self.segment_col(sub) + k.col),
+            None => self.caret_at_col(k.row, if down { usize::MAX } else { 0 })
+        }
+    }
+
+    /// Move a caret over the next (or previous) word, skipping whitespace before it.
+    /// Words are runs of either identifier characters or other symbols.
+    fn word_caret(&self, k: Caret, dir: Dir) -> Caret {
+        let line = self.text.line(k.row);
+        let at_edge = match dir {
+            Dir::Right => k.offset == line.len(),
+            Dir::Left => k.offset == 0,
+            Dir::Down | Dir::Up => unreachable!()
+        };
+        if at_edge {
+            return self.advance_caret(k, dir);
+        }
+
+        let mut class = None;
+        let mut len = 0;
+        match dir {
+            Dir::Right => {
+                for c in line[k.offset..].chars() {
+                    if !CharClass::continues(&mut class, c) {
+                        break;
+                    }
+                    len += c.len_utf8();
+                }
+                self.caret_at(k.row, k.offset + len)
+            }
+            _ => {
+                for c in line[..k.offset].chars().rev() {
+                    if !CharClass::continues(&mut class, c) {
+                        break;
+                    }
+                    len += c.len_utf8();
+                }
+                self.caret_at(k.row, k.offset - len)
+            }
+        }
+    }
+
+    /// The word (or run of whitespace or symbols) around a caret.
+    fn word_at(&self, k: Caret) -> (Caret, Caret) {
+        let line = self.text.line(k.row);
+        let (before, after) = (&line[..k.offset], &line[k.offset..]);
+        let class = match after.chars().next().or(before.chars().next_back()) {
+            Some(c) => CharClass::of(c),
+            None => return (k, k)
+        };
+        let start = before.char_indices().rev().take_while(|&(_, c)| CharClass::of(c) == class)
+                          .last().map_or(k.offset, |(i, _)| i);
+        let end = after.char_indices().take_while(|&(_, c)| CharClass::of(c) == class)
+                       .last().map_or(k.offset, |(i, c)| k.offset + i + c.len_utf8());
+        (self.caret_at(k.row, start), self.caret_at(k.row, end))
+    }
+
+    /// A whole row, including its line ending.
+    fn line_at(&self, row: usize) -> (Caret, Caret) {
+        let end = if row + 1 < self.text.len_lines() {
+            Caret { row: row + 1, col: 0, offset: 0 }
+        } else {
+            self.caret_at(row, self.text.line(row).len())
+        };
+        (Caret { row: row, col: 0, offset: 0 }, end)
+    }
+
+    fn caret_at(&self, row: usize, offset: usize) -> Caret {
+        let mut k = Caret { row: row, col: 0, offset: 0 };
+        for c in self.text.line(row)[..offset].chars() {
+            k.advance(c, true);
+        }
+        k
+    }
+
+    /// Find the caret position closest to a column, clamping to the end of the line.
+    fn caret_at_col(&self, row: usize, col: usize) -> Caret {
+        let (columns, line) = (self.lines[row].columns, self.text.line(row));
+        if col > columns {
+            return Caret { row: row, col: columns, offset: line.len() };
+        }
+
+        let mut k = Caret { row: row, col: 0, offset: 0 };
+        for c in line.chars() {
+            if k.col >= col {
+                break;
+            }
+            k.advance(c, true);
+        }
+        k
+    }
+
+    fn move_to(&mut self, k: Caret, hold: bool) {
+        self.folds.reveal(k.row);
+        if !hold {
+            self.selection_start = k;
+        }
+        self.caret = k;
+        self.vertical_col = self.visual_col(k);
+        self.blink_phase = 0.0;
+        self.scroll_to_caret(k);
+    }
+
+    /// Scroll just enough for a caret to be in the viewport.
+    fn scroll_to_caret(&mut self, k: Caret) {
+        let v = self.visual_row(k);
+        match self.visual_index(v) {
+            None => {
+                self.scroll_start = v.0;
+                self.scroll_sub = v.1;
+            }
+            Some(i) => {
+                let rows = self.visible_rows();
+                if rows > 0 && i >= rows {
+                    self.scroll_by((i - rows + 1) as isize);
+                }
+            }
+        }
+
+        if self.wrap_cols().is_none() {
+            let cols = self.visible_cols();
+            if k.col < self.scroll_col {
+                self.scroll_col = k.col;
+            } else if cols > 0 && k.col >= self.scroll_col + cols {
+                self.scroll_col = k.col - cols + 1;
+            }
+        }
+    }
+
+    /// Number of rows that fit in the viewport, 0 before the first layout.
+    fn visible_rows(&self) -> usize {
+        let metrics = self.font_metrics;
+        let h = self.bb.height();
+        if metrics.height != 0.0 && h > 0.0 {
+            (h / metrics.height) as usize
+        } else {
+            0
+        }
+    }
+
+    /// Number of columns that fit in the viewport, 0 before the first layout.
+    fn visible_cols(&self) -> usize {
+        let metrics = self.font_metrics;
+        let w = self.bb.width();
+        if metrics.width != 0.0 && w > 0.0 {
+            (w / metrics.width) as usize
+        } else {
+            0
+        }
+    }
+
+    /// Columns in each visual row, when soft-wrapping.
+    fn wrap_cols(&self) -> Option<usize> {
+        match self.visible_cols() {
+            cols if self.wrap && cols > 0 => Some(cols),
+            _ => None
+        }
+    }
+
+    /// Number of visual rows a row is wrapped into.
+    fn wrapped_rows(&self, row: usize) -> usize {
+        match self.wrap_cols() {
+            Some(cols) => max(1, (self.lines[row].columns + cols - 1) / cols),
+            None => 1
+        }
+    }
+
+    /// Visual row of a caret, as its row and the index of the wrapped segment.
+    fn visual_row(&self, k: Caret) -> (usize, usize) {
+        match self.wrap_cols() {
+            Some(cols) => (k.row, min(k.col / cols, self.wrapped_rows(k.row) - 1)),
+            None => (k.row, 0)
+        }
+    }
+
+    /// Column at which a wrapped segment starts.
+    fn segment_col(&self, sub: usize) -> usize {
+        self.wrap_cols().map_or(0, |cols| sub * cols)
+    }
+
+    /// Column of a caret, relative to the start of its visual row.
+    fn visual_col(&self, k: Caret) -> usize {
+        k.col - self.segment_col(self.visual_row(k).1)
+    }
+
+    /// First column shown on a visual row.
+    fn first_col(&self, sub: usize) -> usize {
+        self.segment_col(sub) + self.scroll_col
+    }
+
+    /// The closest row after (or before) `row` that isn't folded away.
+    fn next_shown_row(&self, row: usize, down: bool) -> Option<usize> {
+        if down {
+            let mut next = row + 1;
+            while let Some(fold) = self.folds.hiding(next) {
+                next = fold.end + 1;
+            }
+            if next < self.text.len_lines() { Some(next) } else { None }
+        } else {
+            if row == 0 {
+                return None;
+            }
+            let mut prev = row - 1;
+            while let Some(fold) = self.folds.hiding(prev) {
+                prev = fold.start;
+            }
+            Some(prev)
+        }
+    }
+
+    fn next_visual_row(&self, (row, sub): (usize, usize), down: bool) -> Option<(usize, usize)> {
+        if down {
+            if sub + 1 < self.wrapped_rows(row) {
+                Some((row, sub + 1))
+            } else {
+                self.next_shown_row(row, true).map(|row| (row, 0))
+            }
+        } else {
+            if sub > 0 {
+                Some((row, sub - 1))
+            } else {
+                self.next_shown_row(row, false).map(|row| (row, self.wrapped_rows(row) - 1))
+            }
+        }
+    }
+
+    /// Visual row at the top of the viewport.
+    fn scroll_top(&self) -> (usize, usize) {
+        let mut row = min(self.scroll_start, self.text.len_lines() - 1);
+        while let Some(fold) = self.folds.hiding(row) {
+            row = fold.start;
+        }
+        (row, min(self.scroll_sub, self.wrapped_rows(row) - 1))
+    }
+
+    /// The first `n` visual rows in the viewport (fewer at the end of the file).
+    fn visual_rows(&self, n: usize) -> Vec<(usize, usize)> {
+        let mut rows = vec![];
+        let mut next = Some(self.scroll_top());
+        while let Some(v) = next {
+            if rows.len() >= n {
+                break;
+            }
+            rows.push(v);
+            next = self.next_visual_row(v, true);
+        }
+        rows
+    }
+
+    /// How many visual rows below the top of the viewport a visual row is, if not above it.
+    fn visual_index(&self, (row, sub): (usize, usize)) -> Option<usize> {
+        let (top, top_sub) = self.scroll_top();
+        if (row, sub) < (top, top_sub) {
+            return None;
+        }
+        let (mut rows, mut r) = (0, top);
+        while r < row {
+            rows += self.wrapped_rows(r);
+            r = match self.next_shown_row(r, true) {
+                Some(r) => r,
+                None => break
+            };
+        }
+        Some(rows + sub - top_sub)
+    }
+
+    /// Scroll by a number of visual rows, upwards if negative.
+    fn scroll_by(&mut self, delta: isize) {
+        let mut top = self.scroll_top();
+        for _ in 0..delta.abs() {
+            match self.next_visual_row(top, delta > 0) {
+                Some(v) => top = v,
+                None => break
+            }
+        }
+        self.scroll_start = top.0;
+        self.scroll_sub = top.1;
+    }
+
+    /// Scroll back up while there's empty space below the last row.
+    fn clamp_scroll(&mut self) {
+        let rows = self.visible_rows();
+        while self.visual_rows(rows).len() < rows {
+            let top = self.scroll_top();
+            self.scroll_by(-1);
+            if self.scroll_top() == top {
+                break;
+            }
+        }
+    }
+
+    fn toggle_wrap(&mut self) {
+        self.wrap = !self.wrap;
+        self.scroll_sub = 0;
+        self.scroll_col = 0;
+        let k = self.caret;
+        self.vertical_col = self.visual_col(k);
+        self.scroll_to_caret(k);
+    }
+
+    /// The rows folding at `row` would hide, if it starts a block or a `//BEGIN` region.
+    fn fold_range(&self, row: usize) -> Option<Fold> {
+        let rows = row + 1..self.lines.len();
+        if self.lines[row].region == Some(true) {
+            let mut depth = 1;
+            for i in rows {
+                match self.lines[i].region {
+                    Some(true) => depth += 1,
+                    Some(false) if depth == 1 => return Some(Fold { start: row, end: i }),
+                    Some(false) => depth -= 1,
+                    None => {}
+                }
+            }
+            return None;
+        }
+
+        let mut depth = self.lines[row].braces.1;
+        if depth == 0 {
+            return None;
+        }
+        for i in rows {
+            let (closes, opens) = self.lines[i].braces;
+            if closes >= depth {
+                // Keep e.g. `} else {` visible, as it starts another block.
+                let end = if opens > 0 { i - 1 } else { i };
+                return if end > row { Some(Fold { start: row, end: end }) } else { None };
+            }
+            depth = depth - closes + opens;
+        }
+        None
+    }
+
+    /// Fold the innermost block or region around the caret, returns false if there's none.
+    fn fold_at_caret(&mut self) -> bool {
+        let row = self.caret.row;
+
+        // Go upwards, skipping over blocks and regions that end before the caret.
+        let (mut braces, mut regions) = (0, 0);
+        let mut found = None;
+        for r in (0..row + 1).rev() {
+            let (closes, opens) = self.lines[r].braces;
+            let region = self.lines[r].region;
+            let starts_here = if r == row {
+                opens > 0 || region == Some(true)
+            } else {
+                opens > braces || (region == Some(true) && regions == 0)
+            };
+            if starts_here {
+                if let Some(fold) = self.fold_range(r) {
+                    if fold.end >= row {
+                        found = Some(fold);
+                        break;
+                    }
+                }
+            }
+            braces = braces - min(braces, opens) + closes;
+            match region {
+                Some(true) if regions > 0 => regions -= 1,
+                Some(false) if r != row => regions += 1,
+                _ => {}
+            }
+        }
+
+        match found {
+            Some(fold) => {
+                self.fold(fold);
+                true
+            }
+            None => false
+        }
+    }
+
+    fn fold(&mut self, fold: Fold) {
+        self.folds.add(fold);
+
+        // Move carets out of the hidden rows, to the end of the first one.
+        let end = self.caret_at(fold.start, self.text.line(fold.start).len());
+        let (all, primary) = self.selections();
+        let all = all.into_iter().map(|(s, k)| {
+            (if fold.hides(s.row) { end } else { s }, if fold.hides(k.row) { end } else { k })
+        }).collect();
+        self.set_selections(all, primary);
+        self.history.seal();
+    }
+
+    /// Fold or unfold at `row`, returns false if there's nothing to fold there.
+    fn toggle_fold(&mut self, row: usize) -> bool {
+        if self.folds.remove(row) {
+            return true;
+        }
+        match self.fold_range(row) {
+            Some(fold) => {
+                self.fold(fold);
+                true
+            }
+            None => false
+        }
+    }
+
+    /// Area of the `i`-th visual row in the viewport.
+    fn row_bb(&self, i: usize) -> BB<Px> {
+        let y = self.bb.y1 + i as Px * self.font_metrics.height;
+        BB { y1: y, y2: y + self.font_metrics.height, ..self.bb }
+    }
+
+    /// Area of the columns `c1..c2` on the `i`-th visual row in the viewport,
+    /// clipped to it, and extending to its right edge if `c2` is `usize::MAX`.
+    fn span_bb(&self, i: usize, sub: usize, c1: usize, c2: usize) -> Option<BB<Px>> {
+        let (first, width) = (self.first_col(sub), self.font_metrics.width);
+        let last = first + self.visible_cols();
+        if c1 >= last || c2 <= first {
+            return None;
+        }
+        let bb = self.row_bb(i);
+        let x2 = if c2 == usize::MAX {
+            bb.x2
+        } else {
+            bb.x1 + ((min(c2, last) - first) as Px) * width
+        };
+        Some(BB {
+            x1: bb.x1 + ((max(c1, first) - first) as Px) * width,
+            x2: x2,
+            ..bb
+        })
+    }
+
+    /// Draw the part of a line between the columns in `cols`, starting at `[x, y]`.
+    fn draw_line(&self, cx: &mut DrawCx, line: &LineInfo, data: &str, cols: Range<usize>, [x, y]: [Px; 2]) {
+        let mut k = Caret { row: 0, col: 0, offset: 0 };
+        for &(len, style) in &line.ranges {
+            // Offsets of the first and last visible characters in this range.
+            let mut visible: Option<(Caret, usize)> = None;
+            for c in data[k.offset..k.offset+len].chars() {
+                if k.col >= cols.end {
+                    break;
+                }
+                if k.col >= cols.start {
+                    let start = visible.map_or(k, |(start, _)| start);
+                    visible = Some((start, k.offset + c.len_utf8()));
+                }
+                k.advance(c, true);
+            }
+
+            if let Some((start, end)) = visible {
+                let x = x + ((start.col - cols.start) as Px) * self.font_metrics.width;
+                let data = &data[start.offset..end];
+                if style.bold {
+                    cx.text(self.font_bold, [x, y], style.color, data);
+                } else {
+                    cx.text(self.font, [x, y], style.color, data);
+                }
+            }
+            if k.col >= cols.end {
+                break;
+            }
+        }
+    }
+
+    fn update_hl(&mut self, mut range: Range<usize>, dirty: bool) {
+        if dirty {
+            if self.rustc_dirty == (0..0) {
+                self.rustc_dirty = range.clone();
+            } else {
+                let (start, end) = (self.rustc_dirty.start, self.rustc_dirty.end);
+                self.rustc_dirty = min(range.start, start)..max(range.end, end);
+            }
+            self.new_rustc = Some(Rustc::start(self.path.clone(), self.text.clone()));
+        }
+
+        while self.lines[range.start].hl_depth > 0 && range.start > 0 {
+            range.start -= 1;
+        }
+        while self.lines[range.end - 1].hl_depth > 0 && range.end < self.lines.len() {
+            range.end += 1;
+        }
+
+        let (d, mut hl) = highlight::Rust::run(self.text.lines(range.clone()));
+
+        // Fallback to re-highlight everything until the end.
+        if d > 0 {
+            hl = highlight::Rust::run(self.text.lines(0..self.text.len_lines())).1;
+            range = 0..self.text.len_lines();
+        }
+
+        let lines = range.clone().zip(self.text.lines(range));
+        for ((row, data), (hl_depth, ranges)) in lines.zip(hl.into_iter()) {
+            let line = &mut self.lines[row];
+            line.hl_depth = hl_depth;
+            line.ranges = ranges;
+            line.update_columns(data);
+            line.update_folding(data);
+        }
+    }
+
+    fn remove(&mut self, range: Range<Caret>) {
+        let (s1, s2) = (range.start, range.end);
+
+        if s1.row == s2.row {
+            self.text.remove_str(s1.row, s1.offset..s2.offset);
+            return;
+        }
+
+        // Remove the end of the first line and the start of the last one.
+        let len = self.text.line(s1.row).len();
+        self.text.remove_str(s1.row, s1.offset..len);
+        self.text.remove_str(s2.row, 0..s2.offset);
+
+        // Remove all other lines, then join the first and last ones.
+        for _ in s1.row+1..s2.row {
+            self.text.remove_line(s1.row + 1);
+        }
+        self.text.join_lines(s1.row);
+        self.lines.remove_range(s1.row+1..s2.row+1);
+    }
+
+    fn text_range(&self, range: Range<Caret>) -> String {
+        let (s1, s2) = (range.start, range.end);
+        if s1.row == s2.row {
+            return self.text.line(s1.row)[s1.offset..s2.offset].to_owned();
+        }
+
+        let mut text = self.text.line(s1.row)[s1.offset..].to_owned();
+        for line in self.text.lines(s1.row+1..s2.row) {
+            text.push('\n');
+            text.push_str(line);
+        }
+        text.push('\n');
+        text.push_str(&self.text.line(s2.row)[..s2.offset]);
+        text
+    }
+
+    fn insert_at<C: Iterator<Item=char>>(&mut self, mut k: Caret, chars: C) -> Caret {
+        // Insert runs of characters between newlines all at once.
+        let mut start = k;
+        let mut run = String::new();
+        for c in chars {
+            if c == '\n' {
+                self.text.insert_str(start.row, start.offset, &run);
+                run.clear();
+                self.text.split_line(k.row, k.offset);
+                self.lines.insert(k.row + 1, LineInfo::new());
+                k.row += 1;
+                k.col = 0;
+                k.offset = 0;
+                start = k;
+            } else {
+                run.push(c);
+                k.advance(c, true);
+            }
+        }
+        self.text.insert_str(start.row, start.offset, &run);
+        k
+    }
+
+    /// Replace a range with new text, returning the removed text and the new end.
+    /// Doesn't touch the history, see `change` for that.
+    fn replace<C: Iterator<Item=char>>(&mut self, range: Range<Caret>, chars: C) -> (String, Caret) {
+        let (start, old_end) = (range.start, range.end);
+        let removed = self.text_range(range.clone());
+        self.remove(range);
+        let end = self.insert_at(start, chars);
+        self.folds.edit(start.row, old_end.row, end.row);
+
+        // Rows edited earlier, after this change, have moved along with the text.
+        self.edited_rows = Some(match self.edited_rows.take() {
+            Some(rows) => {
+                let rows_end = if rows.end > old_end.row + 1 {
+                    rows.end - old_end.row + end.row
+                } else {
+                    rows.end
+                };
+                min(rows.start, start.row)..max(rows_end, end.row + 1)
+            }
+            None => start.row..end.row + 1
+        });
+        (removed, end)
+    }
+
+    /// Replace a range with new text as part of the current undo step.
+    fn change<C: Iterator<Item=char>>(&mut self, range: Range<Caret>, chars: C) -> Caret {
+        let mut chars = chars.peekable();
+        if range.start == range.end && chars.peek().is_none() {
+            return range.end;
+        }
+        let start = range.start;
+        let (removed, end) = self.replace(range, chars);
+        let inserted = self.text_range(start..end);
+        self.history.push(Change {
+            start: start,
+            removed: removed,
+            inserted: inserted
+        });
+        end
+    }
+
+    fn begin_edit(&mut self, kind: Kind) {
+        let before = self.selections();
+        self.history.start(kind, before);
+        self.edited_rows = None;
+    }
+
+    fn end_edit(&mut self, k: Caret) {
+        self.move_to(k, false);
+        let after = self.selections();
+        self.history.finish(after);
+        self.refresh_edited();
+    }
+
+    /// Like `end_edit`, but leave several selections behind.
+    fn end_edit_all(&mut self, all: Vec<(Caret, Caret)>, primary: usize) {
+        self.restore_selections(all, primary);
+        let after = self.selections();
+        self.history.finish(after);
+        self.refresh_edited();
+    }
+
+    /// Replace all the selections and scroll to the primary caret.
+    fn restore_selections(&mut self, all: Vec<(Caret, Caret)>, primary: usize) {
+        self.move_to(all[primary].1, false);
+        self.set_selections(all, primary);
+    }
+
+    fn refresh_edited(&mut self) {
+        if let Some(rows) = self.edited_rows.take() {
+            let end = min(rows.end, self.lines.len());
+            let start = min(rows.start, end - 1);
+            self.update_hl(start..end, true);
+            self.edits += 1;
+            self.publish();
+        }
+        self.unsaved = !self.history.is_saved();
+    }
+
+    /// Replace every selection with new text, as a single undo step.
+    fn insert<C: Iterator<Item=char>>(&mut self, kind: Kind, chars: C) {
+        let text: String = chars.collect();
+        self.edit_all(kind, |_, _, s1, s2| (s1..s2, text.clone()));
+    }
+
+    /// All the selections as (start, caret) pairs in document order,
+    /// and the index of the primary one.
+    fn selections(&self) -> (Vec<(Caret, Caret)>, usize) {
+        let primary = (self.selection_start, self.caret);
+        let mut all = self.extra_selections.clone();
+        all.push(primary);
+        all.sort_by(|a, b| min(a.0, a.1).cmp(&min(b.0, b.1)));
+        let i = all.iter().position(|&(s, k)| s == primary.0 && k == primary.1).unwrap();
+        (all, i)
+    }
+
+    /// Replace all the selections, merging the ones that overlap.
+    fn set_selections(&mut self, mut all: Vec<(Caret, Caret)>, primary: usize) {
+        let primary = all[primary];
+        all.sort_by(|a, b| min(a.0, a.1).cmp(&min(b.0, b.1)));
+
+        let mut merged: Vec<(Caret, Caret)> = vec![];
+        let mut new_primary = 0;
+        for (s, k) in all {
+            let (s1, s2) = (min(s, k), max(s, k));
+            let overlaps = merged.last().map_or(false, |&(ls, lk)| {
+                let end = max(ls, lk);
+                s1 < end || (s1 == end && (s1 == s2 || ls == lk))
+            });
+            let i = if overlaps { merged.len() - 1 } else { merged.len() };
+            if (s, k) == primary {
+                new_primary = i;
+            }
+            if overlaps {
+                let last = &mut merged[i];
+                let (l1, l2) = (min(last.0, last.1), max(last.0, last.1));
+                let end = max(l2, s2);
+                *last = if last.1 < last.0 { (end, l1) } else { (l1, end) };
+            } else {
+                merged.push((s, k));
+            }
+        }
+
+        let (s, k) = merged.remove(new_primary);
+        self.selection_start = s;
+        self.caret = k;
+        self.extra_selections = merged;
+    }
+
+    /// Run `f` with each selection in turn as the primary one, e.g. to move every caret.
+    fn each_selection<F: FnMut(&mut Editor)>(&mut self, mut f: F) {
+        let extra = mem::replace(&mut self.extra_selections, vec![]);
+        let (s, k, vertical_col) = (self.selection_start, self.caret, self.vertical_col);
+        let scroll = (self.scroll_start, self.scroll_sub, self.scroll_col);
+
+        let mut all = vec![];
+        for (s, k) in extra {
+            self.selection_start = s;
+            self.caret = k;
+            self.vertical_col = self.visual_col(k);
+            f(self);
+            all.push((self.selection_start, self.caret));
+        }
+
+        // The primary selection goes last, to keep its caret in view.
+        self.selection_start = s;
+        self.caret = k;
+        self.vertical_col = vertical_col;
+        let (start, sub, col) = scroll;
+        self.scroll_start = start;
+        self.scroll_sub = sub;
+        self.scroll_col = col;
+        f(self);
+        let vertical_col = self.vertical_col;
+        all.push((self.selection_start, self.caret));
+
+        let primary = all.len() - 1;
+        self.set_selections(all, primary);
+        self.vertical_col = vertical_col;
+    }
+
+    /// Replace a range around every selection, as a single undo step.
+    /// `f` gets the index of a selection (in document order) and its sorted
+    /// ends, and returns the range to replace and the text to replace it with.
+    fn edit_all<F>(&mut self, kind: Kind, mut f: F)
+                   where F: FnMut(&Editor, usize, Caret, Caret) -> (Range<Caret>, String) {
+        self.edit_all_at(kind, |ed, i, s1, s2| {
+            let (range, text) = f(ed, i, s1, s2);
+            let len = text.len();
+            (range, text, len)
+        });
+    }
+
+    /// Like `edit_all`, but `f` also returns how much of the new text
+    /// (in bytes) goes before the caret, e.g. to put it between brackets.
+    fn edit_all_at<F>(&mut self, kind: Kind, mut f: F)
+                      where F: FnMut(&Editor, usize, Caret, Caret) -> (Range<Caret>, String, usize) {
+        self.edit_all_select(kind, |ed, i, s1, s2| {
+            let (range, text, before_caret) = f(ed, i, s1, s2);
+            (range, text, before_caret..before_caret)
+        });
+    }
+
+    /// Like `edit_all`, but `f` also returns which part of the new text
+    /// (in bytes) to select afterwards.
+    fn edit_all_select<F>(&mut self, kind: Kind, mut f: F)
+                          where F: FnMut(&Editor, usize, Caret, Caret) -> (Range<Caret>, String, Range<usize>) {
+        let (all, primary) = self.selections();
+        self.begin_edit(kind);
+
+        // Go backwards, so that each change only moves the carets after it.
+        let mut carets = vec![];
+        let mut next_start = None;
+        for (i, &(s, k)) in all.iter().enumerate().rev() {
+            let (range, text, selected) = f(self, i, min(s, k), max(s, k));
+            let range = match next_start {
+                Some(next) => min(range.start, next)..min(range.end, next),
+                None => range
+            };
+            let end = self.change(range.clone(), text.chars());
+            for &mut (ref mut s, ref mut k) in &mut carets {
+                *s = self.shift_caret(*s, range.end, end);
+                *k = self.shift_caret(*k, range.end, end);
+            }
+            carets.push((range.start.after(&text[..selected.start]),
+                         range.start.after(&text[..selected.end])));
+            next_start = Some(range.start);
+        }
+        carets.reverse();
+
+        self.end_edit_all(carets, primary);
+    }
+
+    /// Move a caret after a change that used to end at `old_end`, but now ends at `new_end`.
+    fn shift_caret(&self, k: Caret, old_end: Caret, new_end: Caret) -> Caret {
+        if k.row == old_end.row {
+            self.caret_at(new_end.row, new_end.offset + k.offset - old_end.offset)
+        } else {
+            Caret { row: k.row - old_end.row + new_end.row, ..k }
+        }
+    }
+
+    /// Add a caret on the row above (or below) the topmost (or bottommost) one.
+    fn add_caret_vertically(&mut self, down: bool) -> bool {
+        let (all, _) = self.selections();
+        let k = if down { all[all.len() - 1].1 } else { all[0].1 };
+        let row = if down {
+            if k.row + 1 >= self.text.len_lines() {
+                return false;
+            }
+            k.row + 1
+        } else {
+            if k.row == 0 {
+                return false;
+            }
+            k.row - 1
+        };
+
+        let new = self.caret_at_col(row, k.col);
+        self.extra_selections.push((self.selection_start, self.caret));
+        self.move_to(new, false);
+        self.history.seal();
+        true
+    }
+
+    /// Select the next occurrence of the primary selection, keeping the current one.
+    /// With nothing selected, select the word around the caret instead.
+    fn add_next_occurrence(&mut self) -> bool {
+        let (s1, s2) = (self.selection_start, self.caret);
+        let (s1, s2) = (min(s1, s2), max(s1, s2));
+
+        if s1 == s2 {
+            let (start, end) = {
+                let line = self.text.line(s1.row);
+                let is_ident = |&(_, c): &(usize, char)| CharClass::of(c) == CharClass::Ident;
+                let start = line[..s1.offset].char_indices().rev().take_while(&is_ident)
+                                             .last().map_or(s1.offset, |(i, _)| i);
+                let end = line[s1.offset..].char_indices().take_while(&is_ident)
+                                           .last().map_or(s1.offset, |(i, c)| s1.offset + i + c.len_utf8());
+                (start, end)
+            };
+            if start == end {
+                return false;
+            }
+            self.selection_start = self.caret_at(s1.row, start);
+            let k = self.caret_at(s1.row, end);
+            self.move_to(k, true);
+            self.history.seal();
+            return true;
+        }
+
+        if s1.row != s2.row {
+            return false;
+        }
+        let pattern = {
+            let text = &self.text.line(s1.row)[s1.offset..s2.offset];
+            match Pattern::new(text, false, true, false) {
+                Ok(pattern) => pattern,
+                Err(_) => return false
+            }
+        };
+        let (start, end) = match self.find_from(&pattern, s2, true) {
+            Some(found) => found,
+            None => return false
+        };
+
+        // Stop once all the occurrences are selected.
+        let (all, _) = self.selections();
+        if all.iter().any(|&(s, k)| min(s, k) == start) {
+            return false;
+        }
+        self.extra_selections.push((self.selection_start, self.caret));
+        self.selection_start = start;
+        self.move_to(end, true);
+        self.history.seal();
+        true
+    }
+
+    /// Find the closest match after (or before) `k`, wrapping around the file.
+    fn find_from(&self, pattern: &Pattern, k: Caret, forward: bool) -> Option<(Caret, Caret)> {
+        let num_lines = self.text.len_lines();
+
+        // Go through all the rows once, then through k's row again, for
+        // the matches on the other side of k, after wrapping around.
+        for i in 0..num_lines + 1 {
+            let row = if forward {
+                (k.row + i) % num_lines
+            } else {
+                (k.row + num_lines * 2 - i) % num_lines
+            };
+            let matches = pattern.matches(self.text.line(row));
+            let found = if forward {
+                matches.into_iter().find(|m| {
+                    row != k.row || (i == 0) == (m.start >= k.offset)
+                })
+            } else {
+                matches.into_iter().rev().find(|m| {
+                    row != k.row || (i == 0) == (m.end <= k.offset)
+                })
+            };
+            if let Some(m) = found {
+                return Some((self.caret_at(row, m.start), self.caret_at(row, m.end)));
+            }
+        }
+        None
+    }
+
+    /// Move the primary caret (extending the selection with `shift`).
+    fn navigate(&mut self, key: Key, ctrl: bool, shift: bool) {
+        let (s1, s2) = (self.selection_start, self.caret);
+        let mut k = s2;
+        let (s1, s2) = (min(s1, s2), max(s1, s2));
+
+        match key {
+            // Collapse the selection to the side being moved towards.
+            Key::Left | Key::Right if !ctrl && !shift && s1 != s2 => {
+                self.move_to(if key == Key::Left { s1 } else { s2 }, false);
+            }
+            Key::Left if ctrl => {
+                k = self.word_caret(k, Dir::Left);
+                self.move_to(k, shift);
+            }
+            Key::Right if ctrl => {
+                k = self.word_caret(k, Dir::Right);
+                self.move_to(k, shift);
+            }
+            Key::Left => {
+                k = self.advance_caret(k, Dir::Left);
+                self.move_to(k, shift);
+            }
+            Key::Right => {
+                k = self.advance_caret(k, Dir::Right);
+                self.move_to(k, shift);
+            }
+            Key::Down => {
+                k.col = self.vertical_col;
+                let k2 = self.advance_caret(k, Dir::Down);
+                self.move_to(k2, shift);
+                self.vertical_col = k.col;
+            }
+            Key::Up => {
+                k.col = self.vertical_col;
+                let k2 = self.advance_caret(k, Dir::Up);
+                self.move_to(k2, shift);
+                self.vertical_col = k.col;
+            }
+            Key::Home if ctrl => {
+                self.move_to(Caret { row: 0, col: 0, offset: 0 }, shift);
+            }
+            Key::End if ctrl => {
+                let last = self.text.len_lines() - 1;
+                k = self.caret_at(last, self.text.line(last).len());
+                self.move_to(k, shift);
+            }
+            // Alternate between the first non-blank character and the start of the line.
+            Key::Home => {
+                let indent = {
+                    let line = self.text.line(k.row);
+                    line.len() - line.trim_left().len()
+                };
+                k = self.caret_at(k.row, if k.offset == indent { 0 } else { indent });
+                self.move_to(k, shift);
+            }
+            Key::End => {
+                k = self.caret_at(k.row, self.text.line(k.row).len());
+                self.move_to(k, shift);
+            }
+            Key::PageUp | Key::PageDown => {
+                let rows = max(self.visible_rows(), 1);
+                let down = key == Key::PageDown;
+                self.scroll_by(if down { rows as isize } else { -(rows as isize) });
+                self.clamp_scroll();
+
+                let mut v = self.visual_row(k);
+                for _ in 0..rows {
+                    match self.next_visual_row(v, down) {
+                        Some(next) => v = next,
+                        None => break
+                    }
+                }
+                let col = self.vertical_col;
+                k = self.caret_at_col(v.0, self.segment_col(v.1) + col);
+                self.move_to(k, shift);
+                self.vertical_col = col;
+            }
+            _ => {}
+        }
+    }
+
+    /// Characters of a row outside of strings and comments, with their offsets.
+    fn code_chars(&self, row: usize) -> Vec<(usize, char)> {
+        let line = self.text.line(row);
+        let mut chars = vec![];
+        let mut offset = 0;
+        for &(len, style) in &self.lines[row].ranges {
+            // The highlighting can lag behind edits to the same row.
+            if offset + len > line.len() || !line.is_char_boundary(offset + len) {
+                break;
+            }
+            if style == highlight::styles::Symbol {
+                chars.extend(line[offset..offset+len].char_indices().map(|(i, c)| (offset + i, c)));
+            }
+            offset += len;
+        }
+        chars
+    }
+
+    /// Whether `k` is in code, as opposed to a string or a comment, going by
+    /// the character after it (or the one before it, at the end of a line).
+    fn in_code(&self, k: Caret) -> bool {
+        let mut end = 0;
+        let mut style = None;
+        for &(len, s) in &self.lines[k.row].ranges {
+            style = Some(s);
+            end += len;
+            if k.offset < end {
+                break;
+            }
+        }
+        style.map_or(true, |s| s == highlight::styles::Symbol)
+    }
+
+    /// Leading whitespace of a row.
+    fn indent_of(&self, row: usize) -> &str {
+        let line = self.text.line(row);
+        let len = line.len() - line.trim_left_matches(|c: char| c == ' ' || c == '\t').len();
+        &line[..len]
+    }
+
+    /// Find the `other` bracket which balances the `c` bracket at `offset` in `row`,
+    /// going forwards (or backwards) and skipping strings and comments.
+    fn scan_brackets(&self, row: usize, offset: usize, c: char, other: char, forward: bool) -> Option<Caret> {
+        let rows: Box<Iterator<Item=usize>> = if forward {
+            Box::new(row..self.text.len_lines())
+        } else {
+            Box::new((0..row + 1).rev())
+        };
+        let mut depth = 1;
+        for r in rows {
+            let mut chars = self.code_chars(r);
+            if !forward {
+                chars.reverse();
+            }
+            for (o, ch) in chars {
+                if r == row && (if forward { o <= offset } else { o >= offset }) {
+                    continue;
+                }
+                if ch == c {
+                    depth += 1;
+                } else if ch == other {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some(self.caret_at(r, o));
+                    }
+                }
+            }
+        }
+        None
+    }
+
+    /// The bracket matching the one at `offset` in `row`, if that's a bracket in code.
+    fn matching_bracket(&self, row: usize, offset: usize) -> Option<Caret> {
+        let c = match self.code_chars(row).into_iter().find(|&(o, _)| o == offset) {
+            Some((_, c)) => c,
+            None => return None
+        };
+        for &(open, close) in BRACKETS {
+            if c == open {
+                return self.scan_brackets(row, offset, open, close, true);
+            }
+            if c == close {
+                return self.scan_brackets(row, offset, close, open, false);
+            }
+        }
+        None
+    }
+
+    /// The bracket next to the caret (after it, or else before it) and its match.
+    fn bracket_pair(&self) -> Option<(Caret, Caret)> {
+        let k = self.caret;
+        let before = self.text.line(k.row)[..k.offset].chars().next_back()
+                                                        .map_or(k.offset, |c| k.offset - c.len_utf8());
+        for &offset in &[k.offset, before] {
+            if let Some(other) = self.matching_bracket(k.row, offset) {
+                return Some((self.caret_at(k.row, offset), other));
+            }
+        }
+        None
+    }
+
+    /// Break the line at a selection, indenting the new line like the current
+    /// one, or by one more level after an opening bracket.
+    fn newline(&self, s1: Caret, s2: Caret) -> (Range<Caret>, String, usize) {
+        let indent = self.indent_of(s1.row).to_owned();
+        let prev = self.code_chars(s1.row).into_iter().rev().find(|&(o, c)| {
+            o < s1.offset && !c.is_whitespace()
+        }).map(|(_, c)| c);
+
+        // Don't carry the whitespace after the caret over to the new line.
+        let rest = &self.text.line(s2.row)[s2.offset..];
+        let trimmed = rest.trim_left_matches(|c: char| c == ' ' || c == '\t');
+        let end = self.caret_at(s2.row, s2.offset + rest.len() - trimmed.len());
+
+        let mut text = format!("\n{}", indent);
+        let bracket = BRACKETS.iter().find(|&&(open, _)| prev == Some(open));
+        if let Some(&(_, close)) = bracket {
+            text.push_str(INDENT);
+            // Put the closing bracket on its own line, e.g. for `{|}`.
+            if trimmed.starts_with(close) {
+                let before_caret = text.len();
+                text.push('\n');
+                text.push_str(&indent);
+                return (s1..end, text, before_caret);
+            }
+        }
+        let len = text.len();
+        (s1..end, text, len)
+    }
+
+    /// Whether the empty pair around `k` is in code, where `type_char` would
+    /// have inserted both halves, so that backspace can delete them together.
+    fn pair_in_code(&self, k: Caret) -> bool {
+        let line = self.text.line(k.row);
+        let open = match line[..k.offset].chars().next_back() {
+            Some(c) => (k.offset - c.len_utf8(), c),
+            None => return false
+        };
+        if open.1 == '"' {
+            // The opening quote is part of the string, so look at what's before it.
+            match line[..open.0].chars().next_back() {
+                Some(c) => self.in_code(self.caret_at(k.row, open.0 - c.len_utf8())),
+                None => true
+            }
+        } else {
+            self.in_code(self.caret_at(k.row, open.0))
+        }
+    }
+
+    /// Type `c` over a selection, closing brackets and quotes, skipping
+    /// over closing ones that are already there, and dedenting `}`.
+    fn type_char(&self, c: char, s1: Caret, s2: Caret) -> (Range<Caret>, String, usize) {
+        let line = self.text.line(s2.row);
+        let next = line[s2.offset..].chars().next();
+        let close = BRACKETS.iter().find(|&&(open, _)| c == open).map(|&(_, close)| close)
+                            .or(if c == '"' { Some('"') } else { None });
+
+        if s1 == s2 && next == Some(c) {
+            // A closing quote is part of the string, unlike a closing bracket,
+            // so look at what's before the caret to tell it from an opening one.
+            let skip = if c == '"' {
+                let before = line[..s2.offset].chars().next_back().map_or(0, |p| s2.offset - p.len_utf8());
+                s2.offset > 0 && !self.in_code(self.caret_at(s2.row, before))
+            } else {
+                BRACKETS.iter().any(|&(_, close)| c == close) && self.in_code(s2)
+            };
+            if skip {
+                // Just step over it, without an edit.
+                let end = self.caret_at(s2.row, s2.offset + c.len_utf8());
+                return (end..end, String::new(), 0);
+            }
+        }
+
+        // Line up `}` with the line of the matching `{`, if it's the first thing on its line.
+        let at_indent = s2.offset == self.indent_of(s2.row).len();
+        if c == '}' && s1 == s2 && at_indent && self.in_code(s2) {
+            let indent = match self.scan_brackets(s2.row, s2.offset, '}', '{', false) {
+                Some(open) => self.indent_of(open.row).to_owned(),
+                None => {
+                    let indent = self.indent_of(s2.row);
+                    indent[..indent.len() - min(indent.len(), INDENT.len())].to_owned()
+                }
+            };
+            let text = format!("{}}}", indent);
+            let len = text.len();
+            return (self.caret_at(s2.row, 0)..s2, text, len);
+        }
+
+        if let Some(close) = close {
+            if s1 != s2 {
+                let text = format!("{}{}{}", c, self.text_range(s1..s2), close);
+                let len = text.len();
+                return (s1..s2, text, len);
+            }
+            let before_close = next.map_or(true, |n| n.is_whitespace() || ")]},;".contains(n));
+            if self.in_code(s1) && before_close {
+                return (s1..s2, format!("{}{}", c, close), c.len_utf8());
+            }
+        }
+        (s1..s2, c.to_string(), c.len_utf8())
+    }
+
+    fn undo(&mut self) -> bool {
+        let group = match self.history.undo() {
+            Some(group) => group,
+            None => return false
+        };
+        self.edited_rows = None;
+        for change in group.changes.iter().rev() {
+            let end = change.start.after(&change.inserted);
+            self.replace(change.start..end, change.removed.chars());
+        }
+        let (all, primary) = group.before.clone();
+        self.restore_selections(all, primary);
+
+        self.history.undone(group);
+        self.refresh_edited();
+        true
+    }
+
+    fn redo(&mut self) -> bool {
+        let group = match self.history.redo() {
+            Some(group) => group,
+            None => return false
+        };
+        self.edited_rows = None;
+        for change in &group.changes {
+            let end = change.start.after(&change.removed);
+            self.replace(change.start..end, change.inserted.chars());
+        }
+        let (all, primary) = group.after.clone();
+        self.restore_selections(all, primary);
+
+        self.history.redone(group);
+        self.refresh_edited();
+        true
+    }
+
+    /// Copy the selections to the clipboard, one per line, returning false if they're all empty.
+    fn copy(&mut self) -> bool {
+        let (all, _) = self.selections();
+        let texts: Vec<_> = all.into_iter().filter(|&(s, k)| s != k).map(|(s, k)| {
+            self.text_range(min(s, k)..max(s, k))
+        }).collect();
+        if texts.is_empty() {
+            return false;
+        }
+        let text = texts.join("\n");
+        clipboard::set(match self.format.line_ending {
+            LineEnding::Lf => text,
+            line_ending => text.replace("\n", line_ending.as_str())
+        });
+        self.history.seal();
+        true
+    }
+
+    /// Paste at every caret, or one line at each caret if there are as many lines as carets.
+    fn paste(&mut self) -> bool {
+        let text = match clipboard::get() {
+            Some(text) => text,
+            None => return false
+        };
+        let text = clipboard::normalize_newlines(&text, "\n");
+
+        let carets = self.extra_selections.len() + 1;
+        let lines: Vec<&str> = text.split('\n').collect();
+        let per_caret = carets > 1 && lines.len() == carets;
+        self.edit_all(Kind::Other, |_, i, s1, s2| {
+            (s1..s2, if per_caret { lines[i].to_owned() } else { text.clone() })
+        });
+        true
+    }
+
+    /// Select a whole row, including its line ending.
+    fn select_line(&mut self, row: usize) {
+        let (start, end) = self.line_at(row);
+        self.extra_selections.clear();
+        self.selection_start = start;
+        self.move_to(end, true);
+        self.history.seal();
+    }
+
+    /// Start selecting with the mouse, by characters, words or lines.
+    fn start_drag(&mut self, clicks: usize, k: Caret, pos: [Px; 2]) {
+        let anchor = match clicks {
+            1 => (k, k),
+            2 => self.word_at(k),
+            _ => self.line_at(k.row)
+        };
+        self.selection_start = anchor.0;
+        self.move_to(anchor.1, true);
+        self.drag = Some(Drag {
+            clicks: clicks,
+            anchor: anchor,
+            pos: pos,
+            pending: 0.0
+        });
+    }
+
+    /// Handle a click in the gutter next to `pos`, which either toggles
+    /// a fold or selects the whole line, returning false if no row is there.
+    fn click_gutter(&mut self, pos: [Px; 2], toggle: bool) -> bool {
+        let row = self.pos_to_visual([self.bb.x1, pos[1]]).map(|((row, _), _)| row);
+        if let Some(row) = row {
+            if toggle {
+                self.toggle_fold(row);
+            } else {
+                // Dragging from there selects more lines.
+                self.extra_selections.clear();
+                self.history.seal();
+                let k = Caret { row: row, col: 0, offset: 0 };
+                self.start_drag(3, k, pos);
+            }
+        }
+        self.hover.take().is_some() | self.close_peek() | row.is_some()
+    }
+
+    /// Extend the selection started by `start_drag` to include `k`,
+    /// keeping the units (words or lines) whole.
+    fn extend_drag(&mut self, k: Caret) {
+        let drag = match self.drag {
+            Some(drag) => drag,
+            None => return
+        };
+        let (start, end) = match drag.clicks {
+            1 => (k, k),
+            2 => self.word_at(k),
+            _ => self.line_at(k.row)
+        };
+        let (a1, a2) = drag.anchor;
+        if start < a1 {
+            self.selection_start = a2;
+            self.move_to(start, true);
+        } else {
+            self.selection_start = a1;
+            self.move_to(max(end, a2), true);
+        }
+    }
+
+    fn select_all(&mut self) {
+        self.extra_selections.clear();
+        let last = self.text.len_lines() - 1;
+        let end = self.caret_at(last, self.text.line(last).len());
+        self.selection_start = Caret { row: 0, col: 0, offset: 0 };
+        self.move_to(end, true);
+        self.history.seal();
+    }
+
+    /// Rows covered by the selections, merged into blocks where they touch.
+    /// A selection ending at the start of a row doesn't cover that row.
+    fn selected_rows(&self) -> Vec<Range<usize>> {
+        let mut blocks: Vec<Range<usize>> = vec![];
+        for (s, k) in self.selections().0 {
+            let (s1, s2) = (min(s, k), max(s, k));
+            let end = if s2.row > s1.row && s2.offset == 0 { s2.row } else { s2.row + 1 };
+            let merged = match blocks.last_mut() {
+                Some(last) if s1.row <= last.end => {
+                    last.end = max(last.end, end);
+                    true
+                }
+                _ => false
+            };
+            if !merged {
+                blocks.push(s1.row..end);
+            }
+        }
+        blocks
+    }
+
+    /// Duplicate each selection after itself and select the copy,
+    /// or the whole row below itself, for empty selections.
+    fn duplicate(&mut self) {
+        self.edit_all_select(Kind::Other, |ed, _, s1, s2| {
+            if s1 != s2 {
+                let text = ed.text_range(s1..s2);
+                let len = text.len();
+                return (s2..s2, text, 0..len);
+            }
+            let line = ed.text.line(s2.row);
+            let end = ed.caret_at(s2.row, line.len());
+            let offset = 1 + s2.offset;
+            (end..end, format!("\n{}", line), offset..offset)
+        });
+    }
+
+    /// Swap the selected rows with the row above (or below) them, keeping them selected.
+    fn move_lines(&mut self, down: bool) -> bool {
+        let blocks = self.selected_rows();
+        let at_edge = if down {
+            blocks.last().map_or(true, |b| b.end >= self.text.len_lines())
+        } else {
+            blocks.first().map_or(true, |b| b.start == 0)
+        };
+        if at_edge {
+            return false;
+        }
+
+        let (all, primary) = self.selections();
+        self.begin_edit(Kind::Other);
+        for block in blocks {
+            let other = if down { block.end } else { block.start - 1 };
+            let moved = self.text.lines(block.clone()).collect::<Vec<_>>().join("\n");
+            let text = if down {
+                format!("{}\n{}", self.text.line(other), moved)
+            } else {
+                format!("{}\n{}", moved, self.text.line(other))
+            };
+            let (first, last) = (min(block.start, other), max(block.end - 1, other));
+            let end = self.caret_at(last, self.text.line(last).len());
+            self.change(Caret { row: first, col: 0, offset: 0 }..end, text.chars());
+        }
+
+        let shift = |k: Caret| Caret { row: if down { k.row + 1 } else { k.row - 1 }, ..k };
+        let all: Vec<_> = all.into_iter().map(|(s, k)| (shift(s), shift(k))).collect();
+        self.end_edit_all(all, primary);
+        true
+    }
+
+    /// Delete the selected rows, leaving a caret on the row after each block.
+    fn delete_lines(&mut self) {
+        let blocks = self.selected_rows();
+        let (all, primary) = self.selections();
+        let primary_row = min(all[primary].0, all[primary].1).row;
+
+        self.begin_edit(Kind::Other);
+        let (mut carets, mut new_primary, mut removed) = (vec![], 0, 0);
+        for block in blocks {
+            let col = all.iter().map(|&(_, k)| k).find(|k| block.start <= k.row && k.row < block.end)
+                         .map_or(0, |k| k.col);
+            if block.start <= primary_row && primary_row < block.end {
+                new_primary = carets.len();
+            }
+
+            // Rows move up as the ones before them are deleted.
+            let (start, end) = (block.start - removed, block.end - removed);
+            let len = self.text.len_lines();
+            let range = if end < len {
+                Caret { row: start, col: 0, offset: 0 }..Caret { row: end, col: 0, offset: 0 }
+            } else {
+                // Also delete the line ending before the last row.
+                let from = if start > 0 {
+                    self.caret_at(start - 1, self.text.line(start - 1).len())
+                } else {
+                    Caret { row: 0, col: 0, offset: 0 }
+                };
+                from..self.caret_at(end - 1, self.text.line(end - 1).len())
+            };
+            self.change(range, "".chars());
+            removed += len - self.text.len_lines();
+
+            let row = min(start, self.text.len_lines() - 1);
+            carets.push(self.caret_at_col(row, col));
+        }
+
+        let all = carets.into_iter().map(|k| (k, k)).collect();
+        self.end_edit_all(all, new_primary);
+    }
+
+    /// Join each block of selected rows, or each row with the next one,
+    /// replacing the indentation in between with a single space.
+    fn join_lines(&mut self) -> bool {
+        let blocks = self.selected_rows();
+        let last = self.text.len_lines() - 1;
+        if blocks.iter().all(|b| b.start == last) {
+            return false;
+        }
+        let (all, primary) = self.selections();
+        let primary_row = min(all[primary].0, all[primary].1).row;
+
+        self.begin_edit(Kind::Other);
+        let (mut carets, mut new_primary, mut removed) = (vec![], 0, 0);
+        for block in blocks {
+            if block.start <= primary_row && primary_row < block.end {
+                new_primary = carets.len();
+            }
+            let (start, end) = (block.start - removed, block.end - removed);
+            let end = min(max(end, start + 2), self.text.len_lines());
+
+            let mut text = self.text.line(start).to_owned();
+            let mut joint = None;
+            for line in self.text.lines(start + 1..end) {
+                let len = text.trim_right().len();
+                text.truncate(len);
+                joint = joint.or(Some(len));
+                let line = line.trim_left();
+                if !line.is_empty() && !text.is_empty() {
+                    text.push(' ');
+                }
+                text.push_str(line);
+            }
+
+            let len = self.text.len_lines();
+            let range_end = self.caret_at(end - 1, self.text.line(end - 1).len());
+            self.change(Caret { row: start, col: 0, offset: 0 }..range_end, text.chars());
+            removed += len - self.text.len_lines();
+            carets.push(self.caret_at(start, joint.unwrap_or(text.len())));
+        }
+
+        let all = carets.into_iter().map(|k| (k, k)).collect();
+        self.end_edit_all(all, new_primary);
+        true
+    }
+
+    /// Comment out the selected rows with `//`, or uncomment them if they all are.
+    fn toggle_comment(&mut self) -> bool {
+        let rows: Vec<usize> = self.selected_rows().into_iter().flat_map(|b| b)
+                                   .filter(|&row| !self.text.line(row).trim().is_empty()).collect();
+        if rows.is_empty() {
+            return false;
+        }
+        let uncomment = rows.iter().all(|&row| self.text.line(row).trim_left().starts_with("//"));
+        // Line the comments up with the least indented row.
+        let indent = rows.iter().map(|&row| self.indent_of(row).len()).min().unwrap();
+
+        let (all, primary) = self.selections();
+        self.begin_edit(Kind::Other);
+        // Where `//` was inserted or removed on each row, and its length.
+        let mut edits = vec![];
+        for row in rows {
+            let start = if uncomment { self.indent_of(row).len() } else { indent };
+            let k = self.caret_at(row, start);
+            let len = if uncomment {
+                let len = if self.text.line(row)[start + 2..].starts_with(' ') { 3 } else { 2 };
+                let end = self.caret_at(row, start + len);
+                self.change(k..end, "".chars());
+                len
+            } else {
+                self.change(k..k, "// ".chars());
+                3
+            };
+            edits.push((row, start, len));
+        }
+
+        let shift = |ed: &Editor, k: Caret| {
+            match edits.iter().find(|e| e.0 == k.row) {
+                Some(&(_, start, len)) if k.offset > start => {
+                    let offset = if uncomment { max(k.offset, start + len) - len } else { k.offset + len };
+                    ed.caret_at(k.row, offset)
+                }
+                _ => k
+            }
+        };
+        let all: Vec<_> = all.into_iter().map(|(s, k)| (shift(self, s), shift(self, k))).collect();
+        self.end_edit_all(all, primary);
+        true
+    }
+
+    /// Wrap each selection in `/* */`, or unwrap it if it already is.
+    fn toggle_block_comment(&mut self) {
+        self.edit_all_select(Kind::Other, |ed, _, s1, s2| {
+            let text = ed.text_range(s1..s2);
+            if text.len() >= 4 && text.starts_with("/*") && text.ends_with("*/") {
+                let inner = text[2..text.len() - 2].to_owned();
+                let len = inner.len();
+                (s1..s2, inner, 0..len)
+            } else {
+                let len = text.len() + 4;
+                (s1..s2, format!("/*{}*/", text), if s1 == s2 { 2..2 } else { 0..len })
+            }
+        });
+    }
+}
+
+impl Layout for Editor {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        if self.font_metrics.width == 0.0 {
+            self.font_metrics = cx.fonts().metrics(self.font);
+        }
+        cx.area(&mut self.bb, "<editor>")
+    }
+}
+
+impl tab::Tab for Editor {
+    fn title(&self) -> String {
+        let mut title = match self.path {
+            Some(ref path) => path.file_name().unwrap().to_string_lossy().into_owned(),
+            None => format!("Untitled-{}", self.untitled)
+        };
+        if self.unsaved {
+            title.push('*');
+        }
+        title
+    }
+}
+
+impl Drop for Editor {
+    fn drop(&mut self) {
+        // Whatever wasn't saved is gone, analysis should see the file on disk again,
+        // unless it's another tab's unsaved text.
+        if let Some(ref path) = self.path {
+            vfs::remove(path, self.id);
+        }
+    }
+}
+
+impl Draw for Editor {
+    fn draw(&self, cx: &mut DrawCx) {
+        let metrics = self.font_metrics;
+        assert!(metrics.width != 0.0);
+
+        if self.over {
+            cx.cursor(MouseCursor::Text);
+        }
+
+        let bb = self.bb;
+        let rows = self.visual_rows(self.visible_rows());
+        let cols = self.visible_cols();
+
+        cx.fill(bb, ColorScheme.back_view());
+
+        let (s1, s2) = (self.selection_start, self.caret);
+        for (i, &(row, _)) in rows.iter().enumerate() {
+            if row == s2.row {
+                cx.fill(self.row_bb(i), ColorScheme.back_view_alt());
+            }
+        }
+
+        // Search matches.
+        if let Some(ref pattern) = self.pattern {
+            let mut color = ColorScheme.hover();
+            color[3] = 0.4;
+            for (i, &(row, sub)) in rows.iter().enumerate() {
+                for m in pattern.matches(self.text.line(row)) {
+                    let (k1, k2) = (self.caret_at(row, m.start), self.caret_at(row, m.end));
+                    if let Some(bb) = self.span_bb(i, sub, k1.col, k2.col) {
+                        cx.fill(bb, color);
+                    }
+                }
+            }
+        }
+
+        // Selections, the primary one last.
+        let selections = self.extra_selections.iter().chain(once(&(s1, s2)));
+        for &(s1, s2) in selections.clone() {
+            let (s1, s2) = (min(s1, s2), max(s1, s2));
+            if s1 == s2 {
+                continue;
+            }
+            for (i, &(row, sub)) in rows.iter().enumerate() {
+                if row < s1.row || row > s2.row {
+                    continue;
+                }
+                // All but the last row are selected up to the right edge.
+                let c1 = if row == s1.row { s1.col } else { 0 };
+                let c2 = if row == s2.row { s2.col } else { usize::MAX };
+                if let Some(bb) = self.span_bb(i, sub, c1, c2) {
+                    cx.fill(bb, ColorScheme.focus());
+                }
+            }
+        }
+
+        // The bracket next to the caret, and its match.
+        if let Some((a, b)) = self.bracket_pair() {
+            for &k in &[a, b] {
+                let v = self.visual_row(k);
+                let bracket = rows.iter().position(|&r| r == v).and_then(|i| {
+                    self.span_bb(i, v.1, k.col, k.col + 1)
+                });
+                if let Some(bracket) = bracket {
+                    cx.border(bracket, ColorScheme.inactive(), 1.0, 0.0);
+                }
+            }
+        }
+
+        // The actual text in each visual row.
+        for (i, &(row, sub)) in rows.iter().enumerate() {
+            let y = bb.y1 + i as Px * metrics.height;
+            let first = self.first_col(sub);
+            self.draw_line(cx, &self.lines[row], self.text.line(row), first..first + cols, [bb.x1, y]);
+
+            // Placeholder for the rows folded away, after the last visual row.
+            if sub + 1 == self.wrapped_rows(row) && self.folds.at(row).is_some() {
+                let col = self.lines[row].columns + 1;
+                if let Some(pb) = self.span_bb(i, sub, col, col + 3) {
+                    cx.border(pb, ColorScheme.inactive(), 1.0, 0.0);
+                    cx.text(self.font, [pb.x1, y], ColorScheme.inactive(), "...");
+                }
+            }
+        }
+
+        // Carets on top of everything else.
+        if self.blink_phase < BLINK_SPACING {
+            for &(_, k) in selections {
+                let v = self.visual_row(k);
+                let first = self.first_col(v.1);
+                let i = match rows.iter().position(|&r| r == v) {
+                    Some(i) if k.col >= first => i,
+                    _ => continue
+                };
+                let y = bb.y1 + i as Px * metrics.height;
+                let w = 2.0;
+                let x = bb.x1 + ((k.col - first) as Px) * metrics.width;
+                cx.fill(BB::rect(x.min(bb.x2 - w), y, w, metrics.height), ColorScheme.normal());
+            }
+        }
+
+        let (_, start_col, separator, ref overlay) = self.overlay;
+        if overlay.is_empty() {
+            return;
+        }
+        // Right below the hovered (or peeked from) visual row, if it's visible.
+        let hover = match (self.overlay_pinned(), self.hover) {
+            (Some(k), _) | (None, Some((k, _))) => self.visual_row(k),
+            (None, None) => return
+        };
+        let row = match rows.iter().position(|&r| r == hover) {
+            Some(i) => i + 1,
+            None => return
+        };
+        let start_col = start_col.saturating_sub(self.first_col(hover.1));
+        let selected = self.completing.as_ref().map(|c| c.selected - c.scroll);
+
+        cx.draw_overlay(|cx| {
+            let max_col = overlay.iter().map(|line| line.info.columns).max().unwrap();
+            let bb = BB {
+                x1: bb.x1 + (start_col as Px) * metrics.width,
+                x2: bb.x1 + ((start_col + max_col) as Px) * metrics.width,
+                y1: bb.y1 + (row as Px) * metrics.height + 2.0,
+                y2: bb.y1 + ((row + overlay.len()) as Px) * metrics.height + 2.0
+            };
+
+            // Draw border.
+            cx.fill(BB {
+                x1: bb.x1 - 2.0, x2: bb.x2 + 2.0,
+                y1: bb.y1 - 4.0, y2: bb.y2 + 4.0
+            }, ColorScheme.active());
+
+            for (i, line) in overlay.iter().enumerate() {
+                let y = bb.y1 + i as Px * metrics.height;
+                let mut back_bb = BB {
+                    x1: bb.x1, x2: bb.x2,
+                    y1: y, y2: y + metrics.height
+                };
+                if i == 0 {
+                    back_bb.y1 -= 2.0;
+                }
+                if i == overlay.len() - 1 {
+                    back_bb.y2 += 2.0;
+                }
+                cx.fill(back_bb, if i % 2 == 0 { ColorScheme.back_view_alt() } else { ColorScheme.back_view() });
+                if Some(i) == selected {
+                    cx.border(back_bb, ColorScheme.focus(), 1.0, 0.0);
+                }
+
+                let mut draw_k = Caret {
+                    row: 0,
+                    col: 0,
+                    offset: 0
+                };
+                for &(len, style) in &line.info.ranges {
+                    let x = bb.x1 + (draw_k.col as Px) * metrics.width;
+                    let data = &line.data[draw_k.offset..draw_k.offset+len];
+                    if style.bold {
+                        cx.text(self.font_bold, [x, y], style.color, data);
+                    } else {
+                        cx.text(self.font, [x, y], style.color, data);
+                    }
+                    for c in data.chars() {
+                        draw_k.advance(c, true);
+                    }
+                }
+            }
+
+            // Draw separator.
+            if separator > 0 {
+                let separator = bb.x1 + (separator as Px + 1.0) * metrics.width;
+                cx.fill(BB {
+                    x1: separator - 1.0, x2: separator + 1.0,
+                    y1: bb.y1, y2: bb.y2
+                }, ColorScheme.active());
+            }
+        });
+    }
+}
+
+impl Dispatch<MouseDown> for Editor {
+    fn dispatch(&mut self, ev: &MouseDown) -> bool {
+        if !self.bb.contains([ev.x, ev.y]) {
+            return false;
+        }
+
+        let mut dirty = self.close_peek() | self.close_completion();
+
+        if let Some(k) = self.pos_to_caret([ev.x, ev.y]) {
+            // Ctrl+click goes to the definition of what's clicked on.
+            if self.modifiers.ctrl {
+                self.look_up(k, Lookup::Jump);
+                return dirty | self.hover.take().is_some();
+            }
+
+            self.history.seal();
+            // Alt+click adds a caret, keeping the existing ones.
+            if self.modifiers.alt {
+                if ev.clicks() == 1 {
+                    self.extra_selections.push((self.selection_start, self.caret));
+                }
+            } else {
+                self.extra_selections.clear();
+            }
+
+            // Shift+click extends the selection from where it started.
+            if self.modifiers.shift && !self.modifiers.alt {
+                let start = self.selection_start;
+                self.drag = Some(Drag {
+                    clicks: 1,
+                    anchor: (start, start),
+                    pos: [ev.x, ev.y],
+                    pending: 0.0
+                });
+                self.extend_drag(k);
+            } else {
+                self.start_drag(ev.clicks(), k, [ev.x, ev.y]);
+            }
+            dirty = true;
+        }
+
+        dirty | self.hover.take().is_some()
+    }
+}
+
+impl Dispatch<MouseUp> for Editor {
+    fn dispatch(&mut self, _: &MouseUp) -> bool {
+        self.drag = None;
+        // Merge the dragged selection with any other ones it now overlaps.
+        if !self.extra_selections.is_empty() {
+            let (all, primary) = self.selections();
+            self.set_selections(all, primary);
+            return true;
+        }
+        false
+    }
+}
+
+impl Dispatch<MouseMove> for Editor {
+    fn dispatch(&mut self, ev: &MouseMove) -> bool {
+        let over = self.bb.contains([ev.x, ev.y]);
+        let mut dirty = false;
+        if over != self.over { self.over = over; dirty = true; }
+
+        // Keep selecting past the edges of the viewport, see the autoscroll in `Update`.
+        if let Some(ref mut drag) = self.drag {
+            drag.pos = [ev.x, ev.y];
+        }
+        if self.drag.is_some() {
+            let k = self.drag_caret([ev.x, ev.y]);
+            self.extend_drag(k);
+            dirty = true;
+        }
+
+        if let Some(k) = self.pos_to_caret([ev.x, ev.y]) {
+            let mut k = k;
+            if let Some((_, col)) = self.pos_to_visual([ev.x, ev.y]) {
+                k.col = col as usize;
+            }
+            let over_caret = self.selection_start == k && self.caret == k;
+            if self.drag.is_none() && !(self.hover.is_none() && over_caret) {
+                if self.hover.map(|(k, _)| k) != Some(k) {
+                    self.hover = Some((k, 0.0));
+                }
+            }
+        }
+
+        dirty
+    }
+}
+
+impl Dispatch<MouseScroll> for Editor {
+    fn dispatch(&mut self, ev: &MouseScroll) -> bool {
+        let metrics = self.font_metrics;
+        if metrics.width == 0.0 {
+            return false;
+        }
+
+        let [dx, dy] = ev.delta();
+        // Shift turns vertical scrolling into horizontal scrolling.
+        let (dx, dy) = if self.modifiers.shift && dx == 0.0 { (dy, 0.0) } else { (dx, dy) };
+        if dx == 0.0 && dy == 0.0 {
+            return false;
+        }
+
+        let (top, sx) = (self.scroll_top(), self.scroll_col);
+        if dy != 0.0 {
+            self.scroll_by(-dy as isize);
+            if dy < 0.0 {
+                self.clamp_scroll();
+            }
+        }
+
+        // Only scroll as far as the longest row in view needs.
+        if dx != 0.0 && self.wrap_cols().is_none() {
+            let columns = self.visual_rows(self.visible_rows()).iter().map(|&(row, _)| {
+                self.lines[row].columns
+            }).max().unwrap_or(0);
+            let max_col = (columns + 1).saturating_sub(self.visible_cols());
+            self.scroll_col = if dx > 0.0 {
+                sx.saturating_sub(dx as usize)
+            } else {
+                min(sx + (-dx) as usize, max(max_col, sx))
+            };
+        }
+
+        (self.scroll_top() != top || self.scroll_col != sx) | self.dispatch(&MouseMove::new(ev.x, ev.y))
+    }
+}
+
+/// One level of indentation.
+const INDENT: &'static str = "    ";
+const BRACKETS: &'static [(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+
+const BLINK_SPACING: f32 = 0.5;
+const HOVER_DELAY: f32 = 1.0;
+const COMPLETION_ROWS: usize = 10;
+const DISK_CHECK_SPACING: f32 = 1.0;
+// Rows per second when dragging just outside the viewport, going faster further out.
+const AUTOSCROLL_SPEED: f32 = 10.0;
+
+impl Dispatch<Update> for Editor {
+    fn dispatch(&mut self, &Update(dt): &Update) -> bool {
+        let mut dirty = false;
+
+        let blink = (self.blink_phase + dt) % (BLINK_SPACING * 2.0);
+        dirty |= (blink >= BLINK_SPACING) != (self.blink_phase >= BLINK_SPACING);
+        self.blink_phase = blink;
+
+        // Scroll while a selection is dragged above or below the viewport.
+        if let Some(drag) = self.drag {
+            let (y, bb, height) = (drag.pos[1], self.bb, self.font_metrics.height);
+            let outside = if y < bb.y1 { y - bb.y1 } else if y > bb.y2 { y - bb.y2 } else { 0.0 };
+            if outside != 0.0 && height > 0.0 {
+                let pending = drag.pending + AUTOSCROLL_SPEED * (1.0 + outside.abs() / height) * dt;
+                let rows = pending as isize;
+                if let Some(ref mut drag) = self.drag {
+                    drag.pending = pending - rows as f32;
+                }
+                if rows > 0 {
+                    self.scroll_by(if outside < 0.0 { -rows } else { rows });
+                    self.clamp_scroll();
+                    let k = self.drag_caret(drag.pos);
+                    self.extend_drag(k);
+                    dirty = true;
+                }
+            }
+        }
+
+        {
+            let ready = if let Some(ref mut new_rustc) = self.new_rustc {
+                dirty |= new_rustc.update();
+                new_rustc.state == rustc::State::Waiting
+            } else {
+                false
+            };
+
+            // No errors, save new rustc.
+            if ready {
+                self.rustc = self.new_rustc.take().unwrap();
+                self.rustc_dirty = 0..0;
+                // Ask the new one instead, the old one won't answer anymore.
+                if let Some(ref mut c) = self.completing {
+                    if c.candidates.is_none() {
+                        c.requested = false;
+                    }
+                }
+            } else {
+                dirty |= self.rustc.update();
+            }
+            self.request_completions();
+        }
+
+        // Another buffer changed, which can change what this one means.
+        if self.vfs_generation != vfs::generation() {
+            self.vfs_generation = vfs::generation();
+            if self.path.is_some() {
+                self.new_rustc = Some(Rustc::start(self.path.clone(), self.text.clone()));
+            }
+        }
+
+        // Show hover overlay.
+        if let Some((hk, ht)) = self.hover {
+            self.hover = Some((hk, ht + dt));
+            let shown = ht < HOVER_DELAY && ht + dt >= HOVER_DELAY;
+            if shown && self.overlay_pinned().is_none() && hk.col < self.lines[hk.row].columns {
+                if let Some(line_offset) = self.analysed_row_offset(hk.row) {
+                    let line_range = line_offset..line_offset+self.text.line(hk.row).len();
+
+                    // Send request for types under cursor.
+                    self.rustc.types_at_offset(line_offset + hk.offset, line_range);
+                    dirty = true;
+                    self.overlay = (0, 0, 0, vec![]);
+                }
+            }
+        }
+
+        // Answer to F12 and such.
+        if let Some(found) = self.rustc.definition.take() {
+            match (self.lookup.take(), found) {
+                (Some((Lookup::Jump, from)), Some(def)) => {
+                    self.jump = Some(Jump {
+                        from: (from.row, from.offset),
+                        to: def
+                    });
+                }
+                (Some((Lookup::Peek, from)), Some(def)) => {
+                    self.hover = None;
+                    self.overlay = self.build_peek(from, &def);
+                    self.peek = Some(from);
+                }
+                (Some(_), None) => println!("No definition found"),
+                (None, _) => {}
+            }
+            dirty = true;
+        }
+        if let Some(found) = self.rustc.references.take() {
+            let rename_to = match self.lookup.take() {
+                Some((Lookup::Rename(new_name), _)) => Some(new_name),
+                _ => None
+            };
+            match found {
+                Ok((name, references)) => {
+                    self.references = Some(References {
+                        name: name,
+                        references: references,
+                        rename_to: rename_to
+                    });
+                }
+                Err(e) => println!("Can't {}: {}", if rename_to.is_some() { "rename" } else { "find references" }, e)
+            }
+            dirty = true;
+        }
+
+        if self.completing.is_some() {
+            if let Some(found) = self.rustc.completions.take() {
+                if let Some(ref mut c) = self.completing {
+                    c.candidates = Some(found);
+                    c.typed = None;
+                }
+            }
+            match self.completion_prefix() {
+                Some(typed) => {
+                    if self.completing.as_ref().map_or(false, |c| c.typed.as_ref() != Some(&typed)) {
+                        self.refilter_completions(typed);
+                        dirty = true;
+                    }
+                }
+                None => dirty |= self.close_completion()
+            }
+        }
+
+        // Hide overlay, unless it's pinned, see `overlay_pinned`.
+        if self.overlay_pinned().is_none() {
+            if self.hover.map(|(_, ht)| ht).unwrap_or(0.0) < HOVER_DELAY {
+                dirty |= !self.overlay.3.is_empty();
+                self.overlay = (0, 0, 0, vec![]);
+                // Clear pending requests.
+                if let rustc::State::TypesAtOffset(_) = self.rustc.state {
+                    self.rustc.state = rustc::State::Waiting;
+                }
+                self.rustc.types_at_offset = None;
+            } else if self.overlay.3.is_empty() {
+                let (hk, _) = self.hover.unwrap();
+                let mut types = self.rustc.types_at_offset.take();
+                {
+                    let diagnostics = &self.new_rustc.as_ref().unwrap_or(&self.rustc).diagnostics;
+                    self.overlay = self.build_overlay(hk, diagnostics.get(&hk.row).unwrap_or(&vec![]),
+                                                      types.as_mut().unwrap_or(&mut vec![]));
+                }
+                self.rustc.types_at_offset = types;
+                dirty |= !self.overlay.3.is_empty();
+            }
+        }
+
+        dirty
+    }
+}
+
+impl Dispatch<TextInput> for Editor {
+    fn dispatch(&mut self, ev: &TextInput) -> bool {
+        let mut dirty = false;
+
+        // Control characters for shortcuts are handled by KeyPress. AltGr comes
+        // as Ctrl+Alt on Windows, and types e.g. `{` or `@` on some layouts.
+        if self.modifiers.ctrl && !self.modifiers.alt {
+            return false;
+        }
+
+        if self.renaming.is_some() {
+            if ev.0 == '\n' || ev.0 == '\r' {
+                self.confirm_rename();
+                return true;
+            }
+            if let Some((_, ref mut name)) = self.renaming {
+                if ev.0.is_alphanumeric() || ev.0 == '_' {
+                    name.push(ev.0);
+                }
+            }
+            self.update_rename_prompt();
+            return true;
+        }
+
+        let shown = self.completing.as_ref().map_or(false, |c| !c.shown.is_empty());
+        if shown && (ev.0 == '\t' || ev.0 == '\n' || ev.0 == '\r') {
+            return self.accept_completion();
+        }
+
+        match ev.0 {
+            // Ignore backspace, escape and delete.
+            '\x08' | '\x1b' | '\x7f' => {}
+            '\t' => {
+                self.edit_all(Kind::Other, |_, _, s1, s2| {
+                    (s1..s2, repeat(' ').take(4 - s1.col % 4).collect())
+                });
+                dirty = true;
+            }
+            '\n' => {
+                self.edit_all_at(Kind::Other, |ed, _, s1, s2| ed.newline(s1, s2));
+                dirty = true;
+            }
+            c => {
+                let kind = if self.selection_start == self.caret { Kind::Typing } else { Kind::Other };
+                self.edit_all_at(kind, |ed, _, s1, s2| ed.type_char(c, s1, s2));
+                dirty = true;
+            }
+        }
+
+        dirty |= self.hover.take().is_some();
+        dirty |= self.close_peek();
+
+        // Fields and methods are offered as soon as there's something to look them up on.
+        if ev.0 == '.' && self.extra_selections.is_empty() {
+            let k = self.caret;
+            if receiver_before(&self.text.line(k.row)[..k.offset]).is_some() {
+                self.start_completion();
+            }
+        }
+
+        dirty
+    }
+}
+
+impl Dispatch<KeyDown> for Editor {
+    fn dispatch(&mut self, &KeyDown(_, modifiers): &KeyDown) -> bool {
+        self.modifiers = modifiers;
+        false
+    }
+}
+
+impl Dispatch<KeyUp> for Editor {
+    fn dispatch(&mut self, &KeyUp(_, modifiers): &KeyUp) -> bool {
+        self.modifiers = modifiers;
+        false
+    }
+}
+
+impl Dispatch<Command> for Editor {
+    fn dispatch(&mut self, &Command(ref command): &Command) -> bool {
+        let dirty = self.hover.take().is_some() | self.close_peek();
+        match &command[..] {
+            "undo" => self.undo() | dirty,
+            "redo" => self.redo() | dirty,
+            "select-all" => {
+                self.select_all();
+                true
+            }
+            "copy" => {
+                self.copy();
+                dirty
+            }
+            "cut" => {
+                if self.copy() {
+                    self.insert(Kind::Other, "".chars());
+                    true
+                } else {
+                    dirty
+                }
+            }
+            "paste" => self.paste() | dirty,
+            "add-next-occurrence" => self.add_next_occurrence() | dirty,
+            "add-caret-above" => self.add_caret_vertically(false) | dirty,
+            "add-caret-below" => self.add_caret_vertically(true) | dirty,
+            "toggle-wrap" => {
+                self.toggle_wrap();
+                true
+            }
+            "fold" => self.fold_at_caret() | dirty,
+            "unfold" => {
+                let row = self.caret.row;
+                self.folds.remove(row) | dirty
+            }
+            "duplicate" => {
+                self.duplicate();
+                true
+            }
+            "move-lines-up" => self.move_lines(false) | dirty,
+            "move-lines-down" => self.move_lines(true) | dirty,
+            "delete-line" => {
+                self.delete_lines();
+                true
+            }
+            "join-lines" => self.join_lines() | dirty,
+            "toggle-comment" => self.toggle_comment() | dirty,
+            "toggle-block-comment" => {
+                self.toggle_block_comment();
+                true
+            }
+            "reload" => {
+                if let Err(e) = self.reload() {
+                    println!("Couldn't reload {}: {}", self.name(), e);
+                }
+                true
+            }
+            "keep-mine" => {
+                self.keep_mine();
+                true
+            }
+            "toggle-trim-on-save" => {
+                self.trim_on_save = !self.trim_on_save;
+                dirty
+            }
+            "toggle-final-newline" => {
+                self.final_newline = !self.final_newline;
+                dirty
+            }
+            "go-to-definition" => {
+                let k = self.caret;
+                self.look_up(k, Lookup::Jump);
+                dirty
+            }
+            "peek-definition" => {
+                let k = self.caret;
+                self.look_up(k, Lookup::Peek);
+                dirty
+            }
+            "find-references" => {
+                let k = self.caret;
+                self.look_up(k, Lookup::References);
+                dirty
+            }
+            "rename-symbol" => {
+                self.start_rename();
+                true
+            }
+            "complete" => {
+                self.start_completion();
+                true
+            }
+            _ => dirty
+        }
+    }
+}
+
+impl Dispatch<KeyPress> for Editor {
+    fn dispatch(&mut self, &KeyPress(key, modifiers): &KeyPress) -> bool {
+        let (ctrl, shift) = (modifiers.ctrl, modifiers.shift);
+
+        if self.renaming.is_some() {
+            self.rename_key(key);
+            return true;
+        }
+
+        // Other keys edit or move as usual, see `completion_prefix`.
+        if self.completing.is_some() {
+            match key {
+                Key::Up | Key::Down if !ctrl && !shift => {
+                    self.move_completion(key == Key::Down);
+                    return true;
+                }
+                Key::Escape => return self.close_completion(),
+                _ => {}
+            }
+        }
+
+        let mut dirty = false;
+
+        dirty |= self.hover.take().is_some();
+        dirty |= self.close_peek();
+
+        match key {
+            // Shortcuts are bound through the keymap, see `Dispatch<Command>`.
+            _ if ctrl && !is_navigation(key) => return dirty,
+            Key::Delete => {
+                self.edit_all(Kind::Other, |ed, _, s1, s2| {
+                    let s2 = if s1 == s2 { ed.step_caret(s1, true) } else { s2 };
+                    (s1..s2, String::new())
+                });
+            }
+            Key::Back => {
+                self.edit_all(Kind::Other, |ed, _, s1, s2| {
+                    if s1 != s2 {
+                        return (s1..s2, String::new());
+                    }
+                    // Delete both halves of an empty pair, e.g. `(|)`.
+                    let line = ed.text.line(s2.row);
+                    let (prev, next) = (line[..s2.offset].chars().next_back(), line[s2.offset..].chars().next());
+                    let pair = BRACKETS.iter().chain(once(&('"', '"'))).any(|&(open, close)| {
+                        prev == Some(open) && next == Some(close)
+                    }) && ed.pair_in_code(s2);
+                    let end = if pair { ed.caret_at(s2.row, s2.offset + 1) } else { s2 };
+                    (ed.step_caret(s2, false)..end, String::new())
+                });
+            }
+            Key::Escape if !self.extra_selections.is_empty() => {
+                self.extra_selections.clear();
+            }
+            // Scrolling by pages only makes sense for a single caret.
+            Key::PageUp | Key::PageDown => {
+                self.extra_selections.clear();
+                self.navigate(key, ctrl, shift);
+            }
+            _ if is_navigation(key) => {
+                self.each_selection(|ed| ed.navigate(key, ctrl, shift));
+            }
+            _ => return dirty
+        }
+        if is_navigation(key) {
+            self.history.seal();
+        }
+        true
+    }
+}
+
+/// Whether any of a row's diagnostics are errors, as opposed to e.g. warnings.
+fn has_error(diagnostics: &[(rustc::Level, usize, String)]) -> bool {
+    diagnostics.iter().any(|&(level, _, _)| {
+        match level {
+            rustc::Level::Bug | rustc::Level::Fatal | rustc::Level::Error => true,
+            _ => false
+        }
+    })
+}
+
+/// The expression a `.` at the end of `before` follows, e.g. `self.items.iter()`
+/// in `for x in self.items.iter().`, if it's a chain of names and calls.
+fn receiver_before(before: &str) -> Option<String> {
+    if !before.ends_with('.') || before.ends_with("..") {
+        return None;
+    }
+    let end = before.len() - 1;
+    let mut start = end;
+    let mut depth = 0;
+    for (i, c) in before[..end].char_indices().rev() {
+        match c {
+            ')' | ']' => depth += 1,
+            '(' | '[' if depth > 0 => depth -= 1,
+            _ if depth > 0 => {}
+            c if c.is_alphanumeric() || c == '_' || c == '.' => {}
+            _ => break
+        }
+        start = i;
+    }
+    let receiver = &before[start..end];
+    if depth == 0 && receiver.chars().next().map_or(false, |c| c.is_alphabetic() || c == '_') {
+        Some(receiver.to_owned())
+    } else {
+        None
+    }
+}
+
+fn is_navigation(key: Key) -> bool {
+    match key {
+        Key::Left | Key::Right | Key::Down | Key::Up |
+        Key::Home | Key::End | Key::PageUp | Key::PageDown => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ui::clipboard::{self, Local};
+    use ui::event::{Command, Dispatch, Key, KeyPress, Modifiers, TextInput};
+    use super::{receiver_before, Editor};
+    use super::file::Format;
+    use super::fold::Fold;
+    use super::history::Kind;
+
+    fn editor(text: &str) -> Editor {
+        Editor::with_text(None, text, Format::default())
+    }
+
+    fn press(ed: &mut Editor, key: Key) {
+        ed.dispatch(&KeyPress(key, Modifiers::default()));
+    }
+
+    fn put_caret(ed: &mut Editor, row: usize, offset: usize) {
+        let k = ed.caret_at(row, offset);
+        ed.move_to(k, false);
+    }
+
+    fn command(ed: &mut Editor, name: &str) {
+        ed.dispatch(&Command(name.to_owned()));
+    }
+
+    const FOLDED: &'static str = "fn f() {\n    a();\n    b();\n}\nfn g() {}";
+
+    #[test]
+    fn back_after_a_fold_only_joins_rows() {
+        let mut ed = editor(FOLDED);
+        ed.fold(Fold { start: 0, end: 3 });
+        put_caret(&mut ed, 4, 0);
+        press(&mut ed, Key::Back);
+        assert_eq!(ed.text.to_string(), "fn f() {\n    a();\n    b();\n}fn g() {}");
+        // The rows it touched are shown again.
+        assert!(!ed.folds.is_hidden(3));
+        assert_eq!((ed.caret.row, ed.caret.offset), (3, 1));
+    }
+
+    #[test]
+    fn delete_before_a_fold_only_joins_rows() {
+        let mut ed = editor(FOLDED);
+        ed.fold(Fold { start: 0, end: 2 });
+        put_caret(&mut ed, 0, 8);
+        press(&mut ed, Key::Delete);
+        assert_eq!(ed.text.to_string(), "fn f() {    a();\n    b();\n}\nfn g() {}");
+        assert!(!ed.folds.is_hidden(1));
+        assert_eq!((ed.caret.row, ed.caret.offset), (0, 8));
+    }
+
+    #[test]
+    fn paste_is_one_undo_step() {
+        clipboard::install(box Local::default());
+        clipboard::set("x\r\ny\rz".to_owned());
+        let mut ed = editor("ab");
+        put_caret(&mut ed, 0, 1);
+        command(&mut ed, "paste");
+        assert_eq!(ed.text.to_string(), "ax\ny\nzb");
+        command(&mut ed, "undo");
+        assert_eq!(ed.text.to_string(), "ab");
+        command(&mut ed, "redo");
+        assert_eq!(ed.text.to_string(), "ax\ny\nzb");
+    }
+
+    #[test]
+    fn undo_restores_the_selection() {
+        let mut ed = editor("hello world");
+        put_caret(&mut ed, 0, 6);
+        let end = ed.caret_at(0, 11);
+        ed.move_to(end, true);
+        ed.dispatch(&TextInput('x'));
+        ed.dispatch(&TextInput('y'));
+        assert_eq!(ed.text.to_string(), "hello xy");
+
+        // Replacing the selection and typing on are separate steps.
+        command(&mut ed, "undo");
+        assert_eq!(ed.text.to_string(), "hello x");
+        assert_eq!(ed.caret.offset, 7);
+        command(&mut ed, "undo");
+        assert_eq!(ed.text.to_string(), "hello world");
+        assert_eq!((ed.selection_start.offset, ed.caret.offset), (6, 11));
+        command(&mut ed, "redo");
+        assert_eq!(ed.text.to_string(), "hello x");
+        assert!(ed.selection_start == ed.caret);
+    }
+
+    #[test]
+    fn edit_at_every_caret_is_one_undo_step() {
+        let mut ed = editor("ab\ncd\nef");
+        let all = (0..3).map(|row| (ed.caret_at(row, 1), ed.caret_at(row, 2))).collect();
+        ed.set_selections(all, 0);
+        ed.edit_all(Kind::Other, |_, i, s1, s2| (s1..s2, i.to_string()));
+        assert_eq!(ed.text.to_string(), "a0\nc1\ne2");
+
+        command(&mut ed, "undo");
+        assert_eq!(ed.text.to_string(), "ab\ncd\nef");
+        let (all, primary) = ed.selections();
+        let offsets: Vec<_> = all.iter().map(|&(s, k)| (s.row, s.offset, k.offset)).collect();
+        assert_eq!(offsets, [(0, 1, 2), (1, 1, 2), (2, 1, 2)]);
+        assert_eq!(primary, 0);
+
+        command(&mut ed, "redo");
+        assert_eq!(ed.text.to_string(), "a0\nc1\ne2");
+        assert_eq!(ed.extra_selections.len(), 2);
+    }
+
+    #[test]
+    fn receiver_of_a_chain() {
+        assert_eq!(receiver_before("for x in self.items.iter()."), Some("self.items.iter()".to_owned()));
+        assert_eq!(receiver_before("    foo(a, b[0])."), Some("foo(a, b[0])".to_owned()));
+        assert_eq!(receiver_before("x."), Some("x".to_owned()));
+    }
+
+    #[test]
+    fn no_receiver() {
+        assert_eq!(receiver_before("x"), None);
+        assert_eq!(receiver_before("for i in 0.."), None);
+        assert_eq!(receiver_before("1."), None);
+        assert_eq!(receiver_before("x + ."), None);
+        assert_eq!(receiver_before("a]."), None);
+    }
+}
@@ -0,0 +1,195 @@
+use std::ops::{Deref, DerefMut};
+
+use cfg::ColorScheme;
+
+use ui::{BB, Px};
+use ui::layout::{CollectCx, CollectBB, Layout};
+use ui::color::Scheme;
+use ui::draw::{Draw, DrawCx};
+use ui::event::*;
+use ui::find::{Pattern, Search};
+use ui::tab::Tab;
+use ui::text;
+
+use super::Editor;
+use super::gutter::Gutter;
+
+/// An `Editor` with its gutter on the left, as shown in a tab,
+/// and room above them for the editor's notice, if it has one.
+pub struct Pane {
+    bb: BB<Px>,
+    banner: BB<Px>,
+    gutter: Gutter,
+    editor: Editor
+}
+
+const BANNER_PADDING: Px = 4.0;
+
+impl Pane {
+    pub fn new(editor: Editor) -> Pane {
+        Pane {
+            bb: BB::default(),
+            banner: BB::default(),
+            gutter: Gutter::new(),
+            editor: editor
+        }
+    }
+
+    /// The buttons of the notice, with their labels, see `Editor::banner`.
+    fn buttons(&self) -> Option<[(&'static str, BB<Px>); 2]> {
+        let (_, labels) = match self.editor.banner() {
+            Some(banner) => banner,
+            None => return None
+        };
+        let metrics = self.editor.font_metrics;
+        let banner = self.banner;
+        let mut x = banner.x2;
+        let mut button = |label: &'static str| {
+            let w = label.len() as Px * metrics.width + BANNER_PADDING * 2.0;
+            x -= w + BANNER_PADDING;
+            (label, BB::rect(x, banner.y1 + BANNER_PADDING / 2.0, w, metrics.height + BANNER_PADDING))
+        };
+        let second = button(labels[1]);
+        let first = button(labels[0]);
+        Some([first, second])
+    }
+}
+
+impl Deref for Pane {
+    type Target = Editor;
+    fn deref(&self) -> &Editor {
+        &self.editor
+    }
+}
+
+impl DerefMut for Pane {
+    fn deref_mut(&mut self) -> &mut Editor {
+        &mut self.editor
+    }
+}
+
+impl Layout for Pane {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        self.gutter.digits = self.editor.text.len_lines().to_string().len();
+        let banner_height = if self.editor.banner().is_some() {
+            cx.fonts().metrics(text::Mono).height + BANNER_PADDING * 2.0
+        } else {
+            0.0
+        };
+
+        let bb = cx.area(&mut self.bb, "<pane>");
+        let nb = cx.area(&mut self.banner, "<banner>");
+        let gb = self.gutter.collect(cx);
+        let eb = self.editor.collect(cx);
+        cx.equal(bb.x1, nb.x1);
+        cx.equal(nb.x2, bb.x2);
+        cx.equal(bb.y1, nb.y1);
+        cx.distance(nb.y1, nb.y2, banner_height);
+        cx.equal(bb.x1, gb.x1);
+        cx.equal(gb.x2, eb.x1);
+        cx.equal(eb.x2, bb.x2);
+        for b in &[gb, eb] {
+            cx.equal(nb.y2, b.y1);
+            cx.equal(b.y2, bb.y2);
+        }
+        bb
+    }
+}
+
+impl Tab for Pane {
+    fn title(&self) -> String {
+        self.editor.title()
+    }
+}
+
+impl Draw for Pane {
+    fn draw(&self, cx: &mut DrawCx) {
+        self.gutter.draw(cx, &self.editor);
+        self.editor.draw(cx);
+
+        if let (Some((message, _)), Some(buttons)) = (self.editor.banner(), self.buttons()) {
+            let (banner, font) = (self.banner, self.editor.font);
+            cx.fill(banner, ColorScheme.back_view_alt());
+            cx.fill(BB { y1: banner.y2 - 1.0, ..banner }, ColorScheme.neutral());
+            cx.text(font, [banner.x1 + BANNER_PADDING, banner.y1 + BANNER_PADDING],
+                    ColorScheme.neutral(), message);
+            for &(label, bb) in &buttons {
+                cx.border(bb, ColorScheme.inactive(), 1.0, 0.0);
+                cx.text(font, [bb.x1 + BANNER_PADDING, bb.y1 + BANNER_PADDING / 2.0],
+                        ColorScheme.normal(), label);
+            }
+        }
+    }
+}
+
+impl Search for Pane {
+    fn set_pattern(&mut self, pattern: Option<Pattern>) {
+        self.editor.set_pattern(pattern)
+    }
+
+    fn find_next(&mut self, forward: bool) -> bool {
+        self.editor.find_next(forward)
+    }
+
+    fn find_incremental(&mut self) -> bool {
+        self.editor.find_incremental()
+    }
+
+    fn replace_one(&mut self, replacement: &str) -> bool {
+        self.editor.replace_one(replacement)
+    }
+
+    fn replace_all(&mut self, replacement: &str) -> bool {
+        self.editor.replace_all(replacement)
+    }
+
+    fn selected_text(&self) -> Option<String> {
+        self.editor.selected_text()
+    }
+}
+
+impl Dispatch<MouseDown> for Pane {
+    fn dispatch(&mut self, ev: &MouseDown) -> bool {
+        if let Some(buttons) = self.buttons() {
+            if self.banner.contains([ev.x, ev.y]) {
+                match buttons.iter().position(|&(_, bb)| bb.contains([ev.x, ev.y])) {
+                    Some(i) => self.editor.press_banner(i),
+                    None => return false
+                }
+                return true;
+            }
+        }
+
+        // Clicking on a line number selects the whole line.
+        if self.gutter.bb().contains([ev.x, ev.y]) {
+            let toggle = self.gutter.toggle_at(ev.x);
+            return self.editor.click_gutter([ev.x, ev.y], toggle);
+        }
+        self.editor.dispatch(ev)
+    }
+}
+
+impl Dispatch<Command> for Pane {
+    fn dispatch(&mut self, ev: &Command) -> bool {
+        match &ev.0[..] {
+            "toggle-relative-numbers" => {
+                self.gutter.relative = !self.gutter.relative;
+                true
+            }
+            _ => self.editor.dispatch(ev)
+        }
+    }
+}
+
+macro_rules! forward_to_editor {
+    ($($event:ident),+) => {
+        $(impl Dispatch<$event> for Pane {
+            fn dispatch(&mut self, ev: &$event) -> bool {
+                self.editor.dispatch(ev)
+            }
+        })+
+    }
+}
+
+forward_to_editor!(MouseUp, MouseMove, MouseScroll, Update, TextInput, KeyDown, KeyUp, KeyPress);
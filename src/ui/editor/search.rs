@@ -0,0 +1,105 @@
+use std::cmp::{min, max};
+
+use ui::find::{Pattern, Search};
+
+use super::{Caret, Editor};
+use super::history::Kind;
+
+impl Editor {
+    fn select_match(&mut self, found: Option<(Caret, Caret)>) -> bool {
+        match found {
+            Some((start, end)) => {
+                self.extra_selections.clear();
+                self.selection_start = start;
+                self.move_to(end, true);
+                self.history.seal();
+                true
+            }
+            None => false
+        }
+    }
+}
+
+impl Search for Editor {
+    fn set_pattern(&mut self, pattern: Option<Pattern>) {
+        self.pattern = pattern;
+    }
+
+    fn find_next(&mut self, forward: bool) -> bool {
+        let (s1, s2) = (self.selection_start, self.caret);
+        let k = if forward { max(s1, s2) } else { min(s1, s2) };
+        let found = self.pattern.as_ref().and_then(|pattern| self.find_from(pattern, k, forward));
+        self.select_match(found)
+    }
+
+    fn find_incremental(&mut self) -> bool {
+        let k = min(self.selection_start, self.caret);
+        let found = self.pattern.as_ref().and_then(|pattern| self.find_from(pattern, k, true));
+        self.select_match(found)
+    }
+
+    fn replace_one(&mut self, replacement: &str) -> bool {
+        let (s1, s2) = (self.selection_start, self.caret);
+        let (s1, s2) = (min(s1, s2), max(s1, s2));
+
+        let replaced = if s1.row == s2.row {
+            self.pattern.as_ref().and_then(|pattern| {
+                let line = self.text.line(s1.row);
+                pattern.replacements(line, replacement).into_iter().find(|&(ref m, _)| {
+                    m.start == s1.offset && m.end == s2.offset
+                }).map(|(_, text)| text)
+            })
+        } else {
+            None
+        };
+
+        if let Some(text) = replaced {
+            self.extra_selections.clear();
+            self.begin_edit(Kind::Other);
+            let k = self.change(s1..s2, text.chars());
+            self.end_edit(k);
+        }
+        self.find_next(true);
+        true
+    }
+
+    fn replace_all(&mut self, replacement: &str) -> bool {
+        let mut all = vec![];
+        if let Some(ref pattern) = self.pattern {
+            for (row, line) in self.text.lines(0..self.text.len_lines()).enumerate() {
+                for (m, text) in pattern.replacements(line, replacement) {
+                    all.push((row, m, text));
+                }
+            }
+        }
+        if all.is_empty() {
+            return false;
+        }
+
+        // Replace from the end, so that earlier matches stay where they were found,
+        // leaving the caret after the last replacement, wherever it moves to.
+        self.extra_selections.clear();
+        self.begin_edit(Kind::Other);
+        let mut last = None;
+        for (row, m, text) in all.into_iter().rev() {
+            let (start, end) = (self.caret_at(row, m.start), self.caret_at(row, m.end));
+            let new_end = self.change(start..end, text.chars());
+            last = Some(match last {
+                Some(k) => self.shift_caret(k, end, new_end),
+                None => new_end
+            });
+        }
+        let k = last.unwrap_or(self.caret);
+        self.end_edit(k);
+        true
+    }
+
+    fn selected_text(&self) -> Option<String> {
+        let (s1, s2) = (self.selection_start, self.caret);
+        let (s1, s2) = (min(s1, s2), max(s1, s2));
+        if s1 == s2 || s1.row != s2.row {
+            return None;
+        }
+        Some(self.text.line(s1.row)[s1.offset..s2.offset].to_owned())
+    }
+}
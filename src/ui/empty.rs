@@ -33,3 +33,4 @@ impl Dispatch<TextInput> for Empty {}
 impl Dispatch<KeyDown> for Empty {}
 impl Dispatch<KeyUp> for Empty {}
 impl Dispatch<KeyPress> for Empty {}
+impl Dispatch<Command> for Empty {}
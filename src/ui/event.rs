@@ -43,52 +43,76 @@ impl<T> Mouse<T> {
 pub mod mouse {
     use ui::Px;
 
-    #[derive(Default)]
-    pub struct Down;
+    /// Number of clicks in a row, e.g. 2 for the second half of a double-click.
+    pub struct Down(pub usize);
     #[derive(Default)]
     pub struct Up;
     #[derive(Default)]
-    pub struct Click;
-    #[derive(Default)]
     pub struct Move;
 
     pub struct Scroll(pub [Px; 2]);
+
+    impl Default for Down {
+        fn default() -> Down { Down(1) }
+    }
 }
 
 pub type MouseDown = Mouse<mouse::Down>;
 pub type MouseUp = Mouse<mouse::Up>;
-pub type MouseClick = Mouse<mouse::Click>;
 pub type MouseMove = Mouse<mouse::Move>;
 pub type MouseScroll = Mouse<mouse::Scroll>;
 
+impl MouseDown {
+    pub fn clicks(&self) -> usize { self.data.0 }
+}
+
 impl MouseScroll {
     pub fn delta(&self) -> [Px; 2] { self.data.0 }
 }
 
 #[derive(Copy, Clone)]
-pub struct KeyDown(pub Key);
+pub struct KeyDown(pub Key, pub Modifiers);
 #[derive(Copy, Clone)]
-pub struct KeyUp(pub Key);
+pub struct KeyUp(pub Key, pub Modifiers);
 #[derive(Copy, Clone)]
-pub struct KeyPress(pub Key);
+pub struct KeyPress(pub Key, pub Modifiers);
+
+/// Modifier keys held while a key event happened.
+#[derive(Copy, Clone, Default, PartialEq, Debug)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub logo: bool
+}
+
+impl Modifiers {
+    /// Update from a key being pressed or released, returns true for modifier keys.
+    pub fn track(&mut self, key: Key, down: bool) -> bool {
+        match key {
+            Key::LControl | Key::RControl => self.ctrl = down,
+            Key::LShift | Key::RShift => self.shift = down,
+            Key::LAlt | Key::RAlt | Key::LMenu | Key::RMenu => self.alt = down,
+            Key::LWin | Key::RWin => self.logo = down,
+            _ => return false
+        }
+        true
+    }
+}
 
 pub struct TextInput(pub char);
 
-pub struct Update(pub f32);
+/// A named command, usually from a key binding.
+pub struct Command(pub String);
 
-pub enum KeyTracker {
-    /// No key is held.
-    NoKey,
+pub struct Update(pub f32);
 
-    /// One key is being held and the f32 stores the delay
-    /// until its next repeat.
-    OneKey(Key, f32)
-}
+#[derive(Default)]
+pub struct KeyTracker {
+    modifiers: Modifiers,
 
-impl Default for KeyTracker {
-    fn default() -> KeyTracker {
-        KeyTracker::NoKey
-    }
+    /// The key being held, if any, and the delay until its next repeat.
+    held: Option<(Key, f32)>
 }
 
 const KEY_REPEAT_DELAY: f32 = 0.660;
@@ -96,36 +120,75 @@ const KEY_REPEAT_SPACING: f32 = 1.0 / 25.0;
 
 pub type KeyPressIterator = iter::Take<iter::Repeat<KeyPress>>;
 
-fn key_press_iter(n: usize, key: Key) -> KeyPressIterator {
-    iter::repeat(KeyPress(key)).take(n)
+fn key_press_iter(n: usize, key: Key, modifiers: Modifiers) -> KeyPressIterator {
+    iter::repeat(KeyPress(key, modifiers)).take(n)
 }
 
 impl KeyTracker {
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
     pub fn down(&mut self, key: Key) -> KeyPressIterator {
-        *self = KeyTracker::OneKey(key, KEY_REPEAT_DELAY);
-        key_press_iter(1, key)
+        // Modifiers don't repeat, nor do they stop other keys from repeating.
+        if self.modifiers.track(key, true) {
+            return key_press_iter(0, key, self.modifiers);
+        }
+        self.held = Some((key, KEY_REPEAT_DELAY));
+        key_press_iter(1, key, self.modifiers)
     }
 
     pub fn up(&mut self, key: Key) -> KeyPressIterator {
-        if let KeyTracker::OneKey(k, _) = *self {
+        self.modifiers.track(key, false);
+        if let Some((k, _)) = self.held {
             if k == key {
-                *self = KeyTracker::NoKey;
+                self.held = None;
             }
         }
-        key_press_iter(0, key)
+        key_press_iter(0, key, self.modifiers)
     }
 
     pub fn update(&mut self, dt: f32) -> KeyPressIterator {
-        if let KeyTracker::OneKey(key, ref mut d) = *self {
+        let modifiers = self.modifiers;
+        if let Some((key, ref mut d)) = self.held {
             *d -= dt;
             let count = (1.0 - *d / KEY_REPEAT_SPACING).max(0.0);
             if *d <= 0.0 {
                 *d %= KEY_REPEAT_SPACING;
                 *d += KEY_REPEAT_SPACING;
             }
-            key_press_iter(count as usize, key)
+            key_press_iter(count as usize, key, modifiers)
         } else {
-            key_press_iter(0, Key::Escape)
+            key_press_iter(0, Key::Escape, modifiers)
+        }
+    }
+}
+
+/// Counts presses of a mouse button in quick succession at the same spot.
+#[derive(Default)]
+pub struct ClickTracker {
+    /// Where the last press was, and the time since then.
+    last: Option<([Px; 2], f32)>,
+    clicks: usize
+}
+
+const MULTI_CLICK_DELAY: f32 = 0.5;
+const MULTI_CLICK_DISTANCE: Px = 4.0;
+
+impl ClickTracker {
+    pub fn down(&mut self, x: Px, y: Px) -> MouseDown {
+        let repeated = self.last.map_or(false, |([lx, ly], t)| {
+            t <= MULTI_CLICK_DELAY &&
+            (x - lx).abs() <= MULTI_CLICK_DISTANCE && (y - ly).abs() <= MULTI_CLICK_DISTANCE
+        });
+        self.clicks = if repeated { self.clicks + 1 } else { 1 };
+        self.last = Some(([x, y], 0.0));
+        Mouse::with(x, y, mouse::Down(self.clicks))
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        if let Some((_, ref mut t)) = self.last {
+            *t += dt;
         }
     }
 }
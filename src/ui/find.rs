@@ -0,0 +1,336 @@
+use regex::{self, Regex};
+use std::ops::Range;
+
+use cfg::ColorScheme;
+
+use ui::{BB, Px};
+use ui::layout::{CollectCx, CollectBB, Layout};
+use ui::color::Scheme;
+use ui::draw::{Draw, DrawCx};
+use ui::event::Key;
+use ui::text::{self, Field, Label};
+
+/// Compiled search, matching within single lines.
+#[derive(Clone)]
+pub struct Pattern {
+    regex: Regex,
+    // Whether replacements can refer to groups, e.g. `$1`.
+    expand: bool
+}
+
+impl Pattern {
+    pub fn new(query: &str, is_regex: bool, case_sensitive: bool, whole_word: bool)
+               -> Result<Pattern, regex::Error> {
+        let mut source = if is_regex { query.to_owned() } else { regex::quote(query) };
+        if whole_word {
+            source = format!(r"\b(?:{})\b", source);
+        }
+        if !case_sensitive {
+            source = format!("(?i){}", source);
+        }
+        Regex::new(&source).map(|regex| Pattern {
+            regex: regex,
+            expand: is_regex
+        })
+    }
+
+    /// Byte ranges of all the non-empty matches in a line.
+    pub fn matches(&self, line: &str) -> Vec<Range<usize>> {
+        self.regex.find_iter(line).filter(|&(start, end)| start < end)
+                  .map(|(start, end)| start..end).collect()
+    }
+
+    /// Like `matches`, but also with the text each match should be replaced with.
+    pub fn replacements(&self, line: &str, replacement: &str) -> Vec<(Range<usize>, String)> {
+        self.regex.captures_iter(line).filter_map(|captures| {
+            let (start, end) = captures.pos(0).unwrap();
+            if start == end {
+                return None;
+            }
+            let text = if self.expand {
+                captures.expand(replacement)
+            } else {
+                replacement.to_owned()
+            };
+            Some((start..end, text))
+        }).collect()
+    }
+}
+
+/// Something that can be searched through the find bar, e.g. the editor.
+pub trait Search {
+    /// Highlight matches of `pattern`, or stop highlighting with `None`.
+    fn set_pattern(&mut self, pattern: Option<Pattern>);
+
+    /// Select the next (or previous) match after the selection, wrapping around.
+    fn find_next(&mut self, forward: bool) -> bool;
+
+    /// Select the first match starting at or after the start of the selection,
+    /// so that the selection grows while the query is being typed.
+    fn find_incremental(&mut self) -> bool;
+
+    /// Replace the selection if it's a match, then select the next match.
+    fn replace_one(&mut self, replacement: &str) -> bool;
+
+    /// Replace all the matches, as a single undo step.
+    fn replace_all(&mut self, replacement: &str) -> bool;
+
+    /// Text to search for when opening the find bar, if any.
+    fn selected_text(&self) -> Option<String>;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum Focus {
+    Find,
+    Replace
+}
+
+/// One of the search modes, shown as a small button.
+pub struct Toggle {
+    bb: BB<Px>,
+    label: Label<text::Mono>,
+    pub on: bool
+}
+
+const TOGGLE_PADDING: Px = 5.0;
+
+impl Toggle {
+    pub fn new(text: &'static str) -> Toggle {
+        Toggle {
+            bb: BB::default(),
+            label: Label::new(ColorScheme.normal(), text),
+            on: false
+        }
+    }
+}
+
+impl Layout for Toggle {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        let bb = cx.area(&mut self.bb, self.label.text);
+        let lb = self.label.collect(cx);
+        cx.distance(bb.x1, lb.x1, TOGGLE_PADDING);
+        cx.distance(lb.x2, bb.x2, TOGGLE_PADDING);
+        cx.distance(bb.y1, lb.y1, 3.0);
+        cx.distance(lb.y2, bb.y2, 3.0);
+        bb
+    }
+}
+
+impl Draw for Toggle {
+    fn draw(&self, cx: &mut DrawCx) {
+        if self.on {
+            cx.fill(self.bb, ColorScheme.focus());
+        } else {
+            cx.border(self.bb, ColorScheme.inactive(), 1.0, 0.0);
+        }
+        self.label.draw(cx);
+    }
+}
+
+/// The find/replace bar, docked above the current tab of a `tab::Set`.
+pub struct Bar {
+    bb: BB<Px>,
+    pub visible: bool,
+    pub find: Field,
+    pub replace: Field,
+    regex: Toggle,
+    case_sensitive: Toggle,
+    whole_word: Toggle,
+
+    // Set when the query doesn't compile (e.g. an unfinished regex).
+    error: bool
+}
+
+const BAR_PADDING: Px = 4.0;
+
+impl Bar {
+    pub fn new() -> Bar {
+        Bar {
+            bb: BB::default(),
+            visible: false,
+            find: Field::new(250.0, "Find"),
+            replace: Field::new(250.0, "Replace"),
+            regex: Toggle::new(".*"),
+            case_sensitive: Toggle::new("Aa"),
+            whole_word: Toggle::new("\\b"),
+            error: false
+        }
+    }
+
+    pub fn focus(&self) -> Option<Focus> {
+        if self.find.focused {
+            Some(Focus::Find)
+        } else if self.replace.focused {
+            Some(Focus::Replace)
+        } else {
+            None
+        }
+    }
+
+    pub fn set_focus(&mut self, focus: Option<Focus>) {
+        self.find.focused = focus == Some(Focus::Find);
+        self.replace.focused = focus == Some(Focus::Replace);
+    }
+
+    pub fn focused_field(&mut self) -> Option<&mut Field> {
+        match self.focus() {
+            Some(Focus::Find) => Some(&mut self.find),
+            Some(Focus::Replace) => Some(&mut self.replace),
+            None => None
+        }
+    }
+
+    /// Pattern for the current query, if the bar is open and the query valid.
+    pub fn pattern(&mut self) -> Option<Pattern> {
+        if !self.visible || self.find.text.is_empty() {
+            self.error = false;
+            return None;
+        }
+        match Pattern::new(&self.find.text, self.regex.on,
+                           self.case_sensitive.on, self.whole_word.on) {
+            Ok(pattern) => {
+                self.error = false;
+                Some(pattern)
+            }
+            Err(_) => {
+                self.error = true;
+                None
+            }
+        }
+    }
+
+    /// Flip one of the modes by name, returns false for unknown modes.
+    pub fn toggle(&mut self, mode: &str) -> bool {
+        let toggle = match mode {
+            "regex" => &mut self.regex,
+            "case" => &mut self.case_sensitive,
+            "word" => &mut self.whole_word,
+            _ => return false
+        };
+        toggle.on = !toggle.on;
+        true
+    }
+
+    /// Name of the mode whose button is at `pos`, if any.
+    pub fn toggle_at(&self, pos: [Px; 2]) -> Option<&'static str> {
+        if self.regex.bb.contains(pos) {
+            Some("regex")
+        } else if self.case_sensitive.bb.contains(pos) {
+            Some("case")
+        } else if self.whole_word.bb.contains(pos) {
+            Some("word")
+        } else {
+            None
+        }
+    }
+
+    /// Handle a key press in the focused field, returns false if it wasn't used.
+    pub fn key(&mut self, key: Key) -> bool {
+        match key {
+            Key::Tab => {
+                let focus = match self.focus() {
+                    Some(Focus::Find) => Focus::Replace,
+                    _ => Focus::Find
+                };
+                self.set_focus(Some(focus));
+                true
+            }
+            _ => self.focused_field().map_or(false, |field| field.key(key))
+        }
+    }
+}
+
+impl Layout for Bar {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        let bb = cx.area(&mut self.bb, "<findbar>");
+        let fb = self.find.collect(cx);
+        let tb = [
+            self.regex.collect(cx),
+            self.case_sensitive.collect(cx),
+            self.whole_word.collect(cx)
+        ];
+        let rb = self.replace.collect(cx);
+
+        cx.distance(bb.x1, fb.x1, BAR_PADDING);
+        cx.distance(fb.x2, tb[0].x1, BAR_PADDING);
+        cx.distance(tb[0].x2, tb[1].x1, 2.0);
+        cx.distance(tb[1].x2, tb[2].x1, 2.0);
+        cx.distance(tb[2].x2, rb.x1, BAR_PADDING * 4.0);
+
+        cx.distance(bb.y1, fb.y1, BAR_PADDING);
+        cx.distance(fb.y2, bb.y2, BAR_PADDING);
+        cx.equal(fb.y1, rb.y1);
+        for b in &tb {
+            cx.equal(fb.y1, b.y1);
+        }
+        bb
+    }
+}
+
+impl Draw for Bar {
+    fn draw(&self, cx: &mut DrawCx) {
+        cx.fill(self.bb, ColorScheme.background());
+        self.find.draw(cx);
+        if self.error {
+            cx.border(self.find.bb(), ColorScheme.negative(), 1.0, 0.0);
+        }
+        self.regex.draw(cx);
+        self.case_sensitive.draw(cx);
+        self.whole_word.draw(cx);
+        self.replace.draw(cx);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Pattern;
+
+    fn matches<'a>(query: &str, is_regex: bool, case_sensitive: bool, whole_word: bool,
+                   line: &'a str) -> Vec<&'a str> {
+        let pattern = Pattern::new(query, is_regex, case_sensitive, whole_word).unwrap();
+        pattern.matches(line).into_iter().map(|m| &line[m]).collect()
+    }
+
+    #[test]
+    fn plain_text() {
+        assert_eq!(matches("a.b", false, true, false, "a.b axb a.b"), ["a.b", "a.b"]);
+        assert_eq!(matches("(x)", false, true, false, "f(x) + (x"), ["(x)"]);
+    }
+
+    #[test]
+    fn case() {
+        assert_eq!(matches("foo", false, true, false, "foo Foo FOO"), ["foo"]);
+        assert_eq!(matches("foo", false, false, false, "foo Foo FOO"), ["foo", "Foo", "FOO"]);
+        assert_eq!(matches("f[a-z]o", true, false, false, "fOo FXO"), ["fOo", "FXO"]);
+    }
+
+    #[test]
+    fn whole_word() {
+        let line = "len = self.len + length(len_2)";
+        assert_eq!(matches("len", false, true, true, line), ["len", "len"]);
+        assert_eq!(matches("len", false, true, false, line).len(), 4);
+        // Alternatives are all kept to whole words.
+        assert_eq!(matches("len|self", true, true, true, line), ["len", "self", "len"]);
+    }
+
+    #[test]
+    fn regex() {
+        assert_eq!(matches(r"\d+", true, true, false, "a1 b22 c"), ["1", "22"]);
+        // Empty matches are left out.
+        assert_eq!(matches("x*", true, true, false, "axxb"), ["xx"]);
+        assert!(Pattern::new("(", true, true, false).is_err());
+        assert!(Pattern::new("(", false, true, false).is_ok());
+    }
+
+    #[test]
+    fn replacements() {
+        let pattern = Pattern::new(r"(\w+)\.len\(\)", true, true, false).unwrap();
+        assert_eq!(pattern.replacements("n = v.len();", "len($1)"), [(4..11, "len(v)".to_owned())]);
+
+        // Outside of regex mode, `$1` is just text.
+        let pattern = Pattern::new("a", false, true, false).unwrap();
+        assert_eq!(pattern.replacements("bab", "$1"), [(1..2, "$1".to_owned())]);
+    }
+}
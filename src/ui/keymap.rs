@@ -0,0 +1,311 @@
+use std::fs::File;
+use std::io::{self, Read};
+
+use cfg;
+use ui::event::{Key, KeyPress, Modifiers};
+
+/// Bindings used unless overridden by the user's `keymap` file.
+const DEFAULT_BINDINGS: &'static str = "
+# Not bindable: ctrl+click goes to the definition, alt+click adds a caret.
+ctrl+n = new
+ctrl+o = open
+ctrl+s = save
+ctrl+shift+s = save-as
+ctrl+alt+s = save-all
+ctrl+shift+r = reload
+ctrl+shift+m = keep-mine
+f5 = run
+ctrl+w = close
+ctrl+z = undo
+ctrl+shift+z = redo
+ctrl+y = redo
+ctrl+a = select-all
+ctrl+x = cut
+ctrl+c = copy
+ctrl+v = paste
+shift+delete = cut
+ctrl+insert = copy
+shift+insert = paste
+ctrl+f = find
+ctrl+h = replace
+f3 = find-next
+shift+f3 = find-previous
+ctrl+alt+enter = replace-all
+alt+r = find-toggle-regex
+alt+c = find-toggle-case
+alt+w = find-toggle-word
+ctrl+shift+f = find-in-files
+ctrl+b = toggle-tree
+ctrl+shift+e = focus-tree
+ctrl+d = add-next-occurrence
+alt+shift+up = add-caret-above
+alt+shift+down = add-caret-below
+alt+z = toggle-wrap
+alt+l = toggle-relative-numbers
+alt+t = toggle-trim-on-save
+alt+n = toggle-final-newline
+ctrl+shift+lbracket = fold
+ctrl+shift+rbracket = unfold
+ctrl+shift+d = duplicate
+alt+up = move-lines-up
+alt+down = move-lines-down
+ctrl+shift+k = delete-line
+ctrl+j = join-lines
+ctrl+slash = toggle-comment
+ctrl+shift+slash = toggle-block-comment
+f12 = go-to-definition
+alt+f12 = peek-definition
+shift+f12 = find-references
+f2 = rename-symbol
+ctrl+space = complete
+alt+left = go-back
+alt+right = go-forward
+";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Chord {
+    pub key: Key,
+    pub modifiers: Modifiers
+}
+
+impl From<KeyPress> for Chord {
+    fn from(KeyPress(key, modifiers): KeyPress) -> Chord {
+        Chord {
+            key: key,
+            modifiers: modifiers
+        }
+    }
+}
+
+impl Chord {
+    /// Parse a chord like `ctrl+shift+z`.
+    pub fn parse(s: &str) -> Result<Chord, String> {
+        let mut parts: Vec<_> = s.split('+').map(|part| part.trim().to_lowercase()).collect();
+        let key = parts.pop().unwrap();
+
+        let mut modifiers = Modifiers::default();
+        for part in parts {
+            match &part[..] {
+                "ctrl" => modifiers.ctrl = true,
+                "shift" => modifiers.shift = true,
+                "alt" => modifiers.alt = true,
+                "super" | "logo" => modifiers.logo = true,
+                _ => return Err(format!("unknown modifier `{}` in `{}`", part, s))
+            }
+        }
+
+        match key_by_name(&key) {
+            Some(key) => Ok(Chord {
+                key: key,
+                modifiers: modifiers
+            }),
+            None => Err(format!("unknown key `{}` in `{}`", key, s))
+        }
+    }
+}
+
+macro_rules! key_names {
+    ($($name:expr => $key:ident),+) => {
+        fn key_by_name(name: &str) -> Option<Key> {
+            match name {
+                $($name => Some(Key::$key),)+
+                _ => None
+            }
+        }
+    }
+}
+
+key_names! {
+    "a" => A, "b" => B, "c" => C, "d" => D, "e" => E, "f" => F, "g" => G,
+    "h" => H, "i" => I, "j" => J, "k" => K, "l" => L, "m" => M, "n" => N,
+    "o" => O, "p" => P, "q" => Q, "r" => R, "s" => S, "t" => T, "u" => U,
+    "v" => V, "w" => W, "x" => X, "y" => Y, "z" => Z,
+    "0" => Key0, "1" => Key1, "2" => Key2, "3" => Key3, "4" => Key4,
+    "5" => Key5, "6" => Key6, "7" => Key7, "8" => Key8, "9" => Key9,
+    "f1" => F1, "f2" => F2, "f3" => F3, "f4" => F4, "f5" => F5, "f6" => F6,
+    "f7" => F7, "f8" => F8, "f9" => F9, "f10" => F10, "f11" => F11, "f12" => F12,
+    "escape" => Escape, "enter" => Return, "tab" => Tab, "space" => Space,
+    "backspace" => Back, "delete" => Delete, "insert" => Insert,
+    "home" => Home, "end" => End, "pageup" => PageUp, "pagedown" => PageDown,
+    "left" => Left, "right" => Right, "up" => Up, "down" => Down,
+    "minus" => Minus, "equals" => Equals, "comma" => Comma, "period" => Period,
+    "slash" => Slash, "backslash" => Backslash, "semicolon" => Semicolon,
+    "apostrophe" => Apostrophe, "grave" => Grave,
+    "lbracket" => LBracket, "rbracket" => RBracket
+}
+
+pub enum Resolved<'a> {
+    /// The chord completed a binding.
+    Command(&'a str),
+    /// The chord started or continued a multi-chord binding.
+    Pending,
+    /// The chord broke off a multi-chord binding, and should be ignored.
+    Cancelled,
+    Unbound
+}
+
+pub struct Keymap {
+    // Later bindings override earlier ones.
+    bindings: Vec<(Vec<Chord>, String)>,
+
+    // Chords pressed so far, towards a multi-chord binding.
+    pending: Vec<Chord>
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        Keymap {
+            bindings: vec![],
+            pending: vec![]
+        }
+    }
+
+    /// Load the default bindings, then the user's `keymap` file, if any.
+    pub fn load() -> Keymap {
+        let mut keymap = Keymap::new();
+        keymap.parse(DEFAULT_BINDINGS).unwrap();
+
+        let path = match cfg::dir() {
+            Some(dir) => dir.join("keymap"),
+            None => return keymap
+        };
+        let mut source = String::new();
+        match File::open(&path).and_then(|mut f| f.read_to_string(&mut source)) {
+            Ok(_) => {
+                if let Err(e) = keymap.parse(&source) {
+                    println!("{}: {}", path.display(), e);
+                }
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => println!("couldn't read {}: {}", path.display(), e)
+        }
+        keymap
+    }
+
+    pub fn bind(&mut self, chords: Vec<Chord>, command: &str) {
+        assert!(!chords.is_empty());
+        self.bindings.push((chords, command.to_owned()));
+    }
+
+    /// Add bindings from lines like `ctrl+k ctrl+c = comment`, with `#` comments.
+    pub fn parse(&mut self, source: &str) -> Result<(), String> {
+        for (i, line) in source.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(2, '=');
+            let (chords, command) = match (parts.next(), parts.next()) {
+                (Some(chords), Some(command)) if !command.trim().is_empty() => {
+                    (chords, command.trim())
+                }
+                _ => return Err(format!("line {}: expected `keys = command`", i + 1))
+            };
+            let chords: Vec<_> = try!(chords.split_whitespace().map(Chord::parse)
+                                            .collect::<Result<_, _>>()
+                                            .map_err(|e| format!("line {}: {}", i + 1, e)));
+            if chords.is_empty() {
+                return Err(format!("line {}: no keys for `{}`", i + 1, command));
+            }
+            self.bind(chords, command);
+        }
+        Ok(())
+    }
+
+    pub fn resolve(&mut self, chord: Chord) -> Resolved {
+        self.pending.push(chord);
+
+        let mut prefix = false;
+        for &(ref chords, ref command) in self.bindings.iter().rev() {
+            if chords[..] == self.pending[..] {
+                self.pending.clear();
+                return Resolved::Command(command);
+            }
+            if chords.len() > self.pending.len() && chords[..self.pending.len()] == self.pending[..] {
+                prefix = true;
+            }
+        }
+
+        if prefix {
+            Resolved::Pending
+        } else if self.pending.len() > 1 {
+            self.pending.clear();
+            Resolved::Cancelled
+        } else {
+            self.pending.clear();
+            Resolved::Unbound
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ui::event::{Key, Modifiers};
+    use super::{Chord, Keymap, Resolved, DEFAULT_BINDINGS};
+
+    fn chord(s: &str) -> Chord {
+        Chord::parse(s).unwrap()
+    }
+
+    fn resolve(keymap: &mut Keymap, s: &str) -> String {
+        match keymap.resolve(chord(s)) {
+            Resolved::Command(command) => command.to_owned(),
+            Resolved::Pending => "<pending>".to_owned(),
+            Resolved::Cancelled => "<cancelled>".to_owned(),
+            Resolved::Unbound => "<unbound>".to_owned()
+        }
+    }
+
+    #[test]
+    fn parse_chord() {
+        assert_eq!(chord("f5"), Chord { key: Key::F5, modifiers: Modifiers::default() });
+        let c = chord("Ctrl + Shift+Z");
+        assert_eq!(c.key, Key::Z);
+        assert_eq!(c.modifiers, Modifiers { ctrl: true, shift: true, alt: false, logo: false });
+        assert_eq!(chord("super+lbracket").modifiers.logo, true);
+        assert!(Chord::parse("hyper+a").is_err());
+        assert!(Chord::parse("ctrl+nope").is_err());
+        assert!(Chord::parse("ctrl+").is_err());
+    }
+
+    #[test]
+    fn parse_keymap() {
+        let mut keymap = Keymap::new();
+        keymap.parse(DEFAULT_BINDINGS).unwrap();
+        keymap.parse("\n# comment\nctrl+k ctrl+c = comment # trailing\n").unwrap();
+        assert_eq!(keymap.bindings.last().unwrap().0, vec![chord("ctrl+k"), chord("ctrl+c")]);
+        assert_eq!(keymap.bindings.last().unwrap().1, "comment");
+
+        let errors = ["ctrl+a", "ctrl+a =", "= undo", "meta+a = undo", "ctrl+nope = undo"];
+        for source in &errors {
+            assert!(Keymap::new().parse(source).is_err(), "{:?} parsed", source);
+        }
+        assert_eq!(Keymap::new().parse("f1 = run\nctrl+a").unwrap_err(),
+                   "line 2: expected `keys = command`");
+    }
+
+    #[test]
+    fn later_bindings_win() {
+        let mut keymap = Keymap::new();
+        keymap.parse("ctrl+y = redo\nctrl+y = delete-line").unwrap();
+        assert_eq!(resolve(&mut keymap, "ctrl+y"), "delete-line");
+    }
+
+    #[test]
+    fn resolve_chords() {
+        let mut keymap = Keymap::new();
+        keymap.parse("ctrl+k ctrl+c = comment\nctrl+s = save").unwrap();
+
+        assert_eq!(resolve(&mut keymap, "ctrl+s"), "save");
+        assert_eq!(resolve(&mut keymap, "ctrl+shift+s"), "<unbound>");
+
+        assert_eq!(resolve(&mut keymap, "ctrl+k"), "<pending>");
+        assert_eq!(resolve(&mut keymap, "ctrl+c"), "comment");
+
+        assert_eq!(resolve(&mut keymap, "ctrl+k"), "<pending>");
+        assert_eq!(resolve(&mut keymap, "ctrl+s"), "<cancelled>");
+        // Cancelling starts over.
+        assert_eq!(resolve(&mut keymap, "ctrl+s"), "save");
+    }
+}
@@ -0,0 +1,60 @@
+use std::cmp::min;
+
+use ui::{BB, Px};
+use ui::event::MouseScroll;
+
+/// Scrolling through rows of the same height, e.g. files in the picker.
+/// Each list draws its rows itself, this only keeps track of which are in view.
+#[derive(Copy, Clone, Default)]
+pub struct Scroll {
+    // Zero until the fonts are known, at layout.
+    pub row_height: Px,
+    // First row in view.
+    pub start: usize
+}
+
+impl Scroll {
+    /// How many rows fit in `bb`.
+    pub fn visible_rows(&self, bb: BB<Px>) -> usize {
+        if self.row_height == 0.0 {
+            0
+        } else {
+            (bb.height() / self.row_height) as usize
+        }
+    }
+
+    /// The row at `[x, y]`, out of `len` rows listed in `bb`.
+    pub fn row_at(&self, bb: BB<Px>, len: usize, [x, y]: [Px; 2]) -> Option<usize> {
+        if self.row_height == 0.0 || !bb.contains([x, y]) {
+            return None;
+        }
+        let i = ((y - bb.y1) / self.row_height) as usize + self.start;
+        if i < len { Some(i) } else { None }
+    }
+
+    /// Scroll just enough for row `i` to be in view in `bb`.
+    pub fn show(&mut self, bb: BB<Px>, i: usize) {
+        let rows = self.visible_rows(bb);
+        if i < self.start {
+            self.start = i;
+        } else if rows > 0 && i >= self.start + rows {
+            self.start = i + 1 - rows;
+        }
+    }
+
+    /// Scroll with the mouse wheel, if it's over `bb`, out of `len` rows.
+    /// Returns true if the rows in view changed.
+    pub fn wheel(&mut self, bb: BB<Px>, len: usize, ev: &MouseScroll) -> bool {
+        if !bb.contains([ev.x, ev.y]) {
+            return false;
+        }
+        let [_, dy] = ev.delta();
+        let start = self.start;
+        self.start = if dy > 0.0 {
+            start.saturating_sub(dy as usize)
+        } else {
+            min(start + (-dy) as usize, len.saturating_sub(1))
+        };
+        self.start != start
+    }
+}
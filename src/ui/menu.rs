@@ -118,3 +118,4 @@ impl Dispatch<TextInput> for Button {}
 impl Dispatch<KeyDown> for Button {}
 impl Dispatch<KeyUp> for Button {}
 impl Dispatch<KeyPress> for Button {}
+impl Dispatch<Command> for Button {}
@@ -40,6 +40,7 @@ pub mod layout;
 pub mod render;
 pub mod draw;
 pub mod event;
+pub mod keymap;
 pub mod text;
 
 #[macro_use]
@@ -49,9 +50,15 @@ pub mod menu;
 #[macro_use]
 pub mod tool;
 pub mod tab;
+pub mod find;
+pub mod list;
+pub mod results;
+pub mod picker;
+pub mod tree;
 
 pub mod empty;
 pub mod dialog;
+pub mod clipboard;
 #[cfg(feature = "ide")]
 pub mod editor;
 
@@ -0,0 +1,562 @@
+use std::cmp::{min, Ordering};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use cfg::ColorScheme;
+use fuzzy::fuzzy_score;
+
+use ui::{BB, Px};
+use ui::layout::{CollectCx, CollectBB, Layout};
+use ui::color::Scheme;
+use ui::draw::{Draw, DrawCx, MouseCursor};
+use ui::event::*;
+use ui::list::Scroll;
+use ui::text::{self, Field};
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Mode {
+    /// Pick one or more existing files.
+    Open,
+    /// Pick a single path, which may not exist yet.
+    Save
+}
+
+struct Entry {
+    name: String,
+    is_dir: bool
+}
+
+/// Browser for picking files to open, or where to save one, without leaving rid3.
+/// Calls back with the mode it was shown in and the chosen paths.
+pub struct Picker<F> {
+    bb: BB<Px>,
+    pub visible: bool,
+    mode: Mode,
+    dir: PathBuf,
+    // Each directory leading to `dir`, shown as the path bar.
+    crumbs: Vec<(PathBuf, String)>,
+    // Horizontal extent of each crumb, from the start of the path bar.
+    crumb_spans: Vec<(Px, Px)>,
+
+    // Filters the entries, and is the file name when saving.
+    filter: Field,
+    entries: Vec<Entry>,
+    error: Option<String>,
+    // Entries matching the filter, best first.
+    shown: Vec<usize>,
+    // Index in `shown` of the highlighted entry.
+    cursor: usize,
+    // Whether the cursor was last moved by hand, rather than by typing.
+    browsing: bool,
+    // Entries selected along with the cursor, when opening.
+    marked: Vec<usize>,
+    // Existing file about to be saved over, waiting for a second Enter.
+    confirm: Option<PathBuf>,
+
+    scroll: Scroll,
+    over: bool,
+    modifiers: Modifiers,
+    callback: F
+}
+
+const PICKER_HEIGHT: Px = 300.0;
+const PICKER_PADDING: Px = 4.0;
+const CRUMB_SEPARATOR: &'static str = " / ";
+
+impl<F> Picker<F> {
+    pub fn new(callback: F) -> Picker<F> {
+        Picker {
+            bb: BB::default(),
+            visible: false,
+            mode: Mode::Open,
+            dir: PathBuf::new(),
+            crumbs: vec![],
+            crumb_spans: vec![],
+
+            filter: Field::new(400.0, "Filter"),
+            entries: vec![],
+            error: None,
+            shown: vec![],
+            cursor: 0,
+            browsing: false,
+            marked: vec![],
+            confirm: None,
+
+            scroll: Scroll::default(),
+            over: false,
+            modifiers: Modifiers::default(),
+            callback: callback
+        }
+    }
+
+    pub fn has_focus(&self) -> bool {
+        self.visible
+    }
+
+    /// Start browsing `dir`, with `name` filled in as the file name when saving.
+    pub fn show(&mut self, mode: Mode, dir: PathBuf, name: &str) {
+        self.visible = true;
+        self.mode = mode;
+        self.filter.focused = true;
+        self.filter.placeholder = match mode {
+            Mode::Open => "Filter",
+            Mode::Save => "File name"
+        };
+        self.navigate(dir);
+        if mode == Mode::Save {
+            self.filter.set_text(name.to_owned());
+            self.refilter();
+        }
+    }
+
+    pub fn hide(&mut self) {
+        self.visible = false;
+        self.filter.focused = false;
+    }
+
+    /// List the contents of `dir`, directories first.
+    fn navigate(&mut self, dir: PathBuf) {
+        let dir = fs::canonicalize(&dir).unwrap_or(dir);
+        self.entries.clear();
+        self.error = None;
+        if dir.parent().is_some() {
+            self.entries.push(Entry {
+                name: "..".to_owned(),
+                is_dir: true
+            });
+        }
+        match fs::read_dir(&dir) {
+            Ok(read) => {
+                let mut entries: Vec<_> = read.filter_map(|entry| entry.ok()).map(|entry| Entry {
+                    name: entry.file_name().to_string_lossy().into_owned(),
+                    is_dir: entry.path().is_dir()
+                }).collect();
+                entries.sort_by(|a, b| {
+                    match (a.is_dir, b.is_dir) {
+                        (true, false) => Ordering::Less,
+                        (false, true) => Ordering::Greater,
+                        _ => a.name.to_lowercase().cmp(&b.name.to_lowercase())
+                    }
+                });
+                self.entries.extend(entries);
+            }
+            Err(e) => self.error = Some(format!("couldn't list {}: {}", dir.display(), e))
+        }
+
+        self.crumbs.clear();
+        let mut path = PathBuf::new();
+        for component in dir.components() {
+            path.push(component.as_os_str());
+            let name = match component {
+                Component::Normal(name) => name.to_string_lossy().into_owned(),
+                _ => path.to_string_lossy().into_owned()
+            };
+            self.crumbs.push((path.clone(), name));
+        }
+        self.dir = dir;
+
+        self.marked.clear();
+        self.confirm = None;
+        if self.mode == Mode::Open {
+            self.filter.set_text(String::new());
+        }
+        self.refilter();
+    }
+
+    /// Match the entries against the filter, putting the cursor on the best one.
+    fn refilter(&mut self) {
+        let query = &self.filter.text;
+        // Hidden files only show up when asked for.
+        let hidden = query.starts_with('.');
+        let mut scored: Vec<_> = self.entries.iter().enumerate().filter(|&(_, entry)| {
+            hidden || !entry.name.starts_with('.') || entry.name == ".."
+        }).filter_map(|(i, entry)| {
+            if query.is_empty() {
+                Some((0, i))
+            } else {
+                fuzzy_score(query, &entry.name).map(|score| (score, i))
+            }
+        }).collect();
+        // Stable, so that equally good entries stay in listing order.
+        scored.sort_by(|a, b| b.0.cmp(&a.0));
+        self.shown = scored.into_iter().map(|(_, i)| i).collect();
+        self.cursor = 0;
+        self.scroll.start = 0;
+        self.browsing = false;
+        self.confirm = None;
+    }
+
+    fn current_entry(&self) -> Option<&Entry> {
+        self.shown.get(self.cursor).map(|&i| &self.entries[i])
+    }
+
+    fn entry_path(&self, entry: &Entry) -> PathBuf {
+        if entry.name == ".." {
+            self.dir.parent().map_or(self.dir.clone(), Path::to_path_buf)
+        } else {
+            self.dir.join(&entry.name)
+        }
+    }
+
+    fn finish(&mut self, paths: Vec<PathBuf>) where F: FnMut(Mode, Vec<PathBuf>) {
+        self.hide();
+        (self.callback)(self.mode, paths);
+    }
+
+    /// Open the highlighted directory, or pick what's selected or typed.
+    fn activate(&mut self) where F: FnMut(Mode, Vec<PathBuf>) {
+        let typed = if self.filter.text.is_empty() {
+            None
+        } else {
+            Some(self.dir.join(&self.filter.text))
+        };
+        if let Some(ref typed) = typed {
+            if typed.is_dir() {
+                let typed = typed.clone();
+                self.filter.set_text(String::new());
+                return self.navigate(typed);
+            }
+        }
+
+        let current = self.current_entry().map(|entry| (self.entry_path(entry), entry.is_dir));
+        match self.mode {
+            Mode::Open => {
+                if !self.marked.is_empty() {
+                    let paths = self.marked.iter().map(|&i| self.entry_path(&self.entries[i])).collect();
+                    return self.finish(paths);
+                }
+                match current {
+                    Some((path, true)) => self.navigate(path),
+                    Some((path, false)) => self.finish(vec![path]),
+                    // A path typed out in full, e.g. an absolute one.
+                    None => {
+                        if let Some(typed) = typed {
+                            if typed.is_file() {
+                                self.finish(vec![typed]);
+                            }
+                        }
+                    }
+                }
+            }
+            Mode::Save => {
+                // The typed name wins, unless an entry was picked since.
+                let path = match (current, typed) {
+                    (Some((path, is_dir)), typed) => {
+                        if self.browsing || typed.is_none() {
+                            if is_dir {
+                                return self.navigate(path);
+                            }
+                            path
+                        } else {
+                            typed.unwrap()
+                        }
+                    }
+                    (None, Some(typed)) => typed,
+                    (None, None) => return
+                };
+                if path.exists() && self.confirm.as_ref() != Some(&path) {
+                    self.confirm = Some(path);
+                    return;
+                }
+                self.finish(vec![path]);
+            }
+        }
+    }
+
+    /// Fill in the highlighted entry's name, going into it if it's a directory.
+    fn complete(&mut self) {
+        let (path, name, is_dir) = match self.current_entry() {
+            Some(entry) => (self.entry_path(entry), entry.name.clone(), entry.is_dir),
+            None => return
+        };
+        if is_dir {
+            self.filter.set_text(String::new());
+            self.navigate(path);
+        } else {
+            self.filter.set_text(name);
+            self.refilter();
+        }
+    }
+
+    fn toggle_mark(&mut self) {
+        if self.mode != Mode::Open {
+            return;
+        }
+        if let Some(&i) = self.shown.get(self.cursor) {
+            if self.entries[i].is_dir {
+                return;
+            }
+            match self.marked.iter().position(|&m| m == i) {
+                Some(j) => { self.marked.remove(j); }
+                None => self.marked.push(i)
+            }
+        }
+    }
+
+    fn move_cursor(&mut self, cursor: usize) {
+        self.cursor = min(cursor, self.shown.len().saturating_sub(1));
+        self.browsing = true;
+        self.confirm = None;
+
+        // Keep the cursor in view.
+        let bb = self.list_bb();
+        self.scroll.show(bb, self.cursor);
+    }
+
+    fn path_bar_bb(&self) -> BB<Px> {
+        BB {
+            x1: self.bb.x1 + PICKER_PADDING,
+            y1: self.bb.y1 + PICKER_PADDING,
+            y2: self.bb.y1 + PICKER_PADDING + self.scroll.row_height,
+            ..self.bb
+        }
+    }
+
+    /// Area below the filter, where the entries are listed.
+    fn list_bb(&self) -> BB<Px> {
+        BB {
+            y1: self.filter.bb().y2 + PICKER_PADDING,
+            ..self.bb
+        }
+    }
+
+    fn visible_rows(&self) -> usize {
+        self.scroll.visible_rows(self.list_bb())
+    }
+
+    fn row_at(&self, pos: [Px; 2]) -> Option<usize> {
+        self.scroll.row_at(self.list_bb(), self.shown.len(), pos)
+    }
+
+    fn crumb_at(&self, [x, y]: [Px; 2]) -> Option<usize> {
+        let bb = self.path_bar_bb();
+        if !bb.contains([x, y]) {
+            return None;
+        }
+        self.crumb_spans.iter().position(|&(x1, x2)| bb.x1 + x1 <= x && x < bb.x1 + x2)
+    }
+}
+
+impl<F> Layout for Picker<F> {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        let bb = cx.area(&mut self.bb, "<picker>");
+        if !self.visible {
+            cx.distance(bb.y1, bb.y2, 0.0);
+            return bb;
+        }
+        self.scroll.row_height = cx.fonts().metrics(text::Regular).height;
+
+        // Measure the path bar here, clicks on it don't have the fonts at hand.
+        self.crumb_spans.clear();
+        let mut x = 0.0;
+        for (i, &(_, ref name)) in self.crumbs.iter().enumerate() {
+            if i > 0 && !self.crumbs[i - 1].1.ends_with(&['/', '\\'][..]) {
+                x += cx.fonts().text_width(text::Regular, CRUMB_SEPARATOR);
+            }
+            let w = cx.fonts().text_width(text::Regular, name);
+            self.crumb_spans.push((x, x + w));
+            x += w;
+        }
+
+        let fb = self.filter.collect(cx);
+        cx.distance(bb.y1, bb.y2, PICKER_HEIGHT);
+        cx.distance(bb.x1, fb.x1, PICKER_PADDING);
+        cx.distance(bb.y1, fb.y1, self.scroll.row_height + PICKER_PADDING * 2.0);
+        bb
+    }
+}
+
+impl<F> Draw for Picker<F> {
+    fn draw(&self, cx: &mut DrawCx) {
+        if !self.visible {
+            return;
+        }
+        if self.over {
+            cx.cursor(MouseCursor::Hand);
+        }
+
+        cx.fill(self.bb, ColorScheme.background());
+        cx.fill(BB { y1: self.bb.y2 - 1.0, ..self.bb }, ColorScheme.inactive());
+
+        let pb = self.path_bar_bb();
+        for (i, (&(x1, _), &(_, ref name))) in self.crumb_spans.iter().zip(&self.crumbs).enumerate() {
+            if i > 0 && !self.crumbs[i - 1].1.ends_with(&['/', '\\'][..]) {
+                let w = cx.fonts().text_width(text::Regular, CRUMB_SEPARATOR);
+                cx.text(text::Regular, [pb.x1 + x1 - w, pb.y1], ColorScheme.inactive(), CRUMB_SEPARATOR);
+            }
+            let color = if i + 1 == self.crumbs.len() { ColorScheme.normal() } else { ColorScheme.link() };
+            cx.text(text::Regular, [pb.x1 + x1, pb.y1], color, name);
+        }
+
+        self.filter.draw(cx);
+
+        // What Enter would do, or why it can't.
+        let status = if let Some(ref error) = self.error {
+            error.clone()
+        } else if let Some(ref path) = self.confirm {
+            format!("{} already exists, press Enter again to replace it",
+                    path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()))
+        } else if !self.marked.is_empty() {
+            format!("{} files selected", self.marked.len())
+        } else {
+            match self.mode {
+                Mode::Open => "Enter to open, Ctrl+click or Shift+arrows to select several".to_owned(),
+                Mode::Save => "Enter to save, Tab to complete".to_owned()
+            }
+        };
+        let color = if self.error.is_some() || self.confirm.is_some() {
+            ColorScheme.negative()
+        } else {
+            ColorScheme.inactive()
+        };
+        let fb = self.filter.bb();
+        cx.text(text::Regular, [fb.x2 + PICKER_PADDING * 4.0, fb.y1 + 3.0], color, &status);
+
+        let bb = self.list_bb();
+        let rows = self.visible_rows();
+        let end = min(self.scroll.start + rows, self.shown.len());
+        for (row, &i) in self.shown[self.scroll.start..end].iter().enumerate() {
+            let entry = &self.entries[i];
+            let y = bb.y1 + row as Px * self.scroll.row_height;
+            let row_bb = BB { y1: y, y2: y + self.scroll.row_height, ..bb };
+            if self.marked.contains(&i) {
+                let mut color = ColorScheme.hover();
+                color[3] = 0.4;
+                cx.fill(row_bb, color);
+            }
+            if self.scroll.start + row == self.cursor {
+                cx.border(row_bb, ColorScheme.focus(), 1.0, 0.0);
+            }
+
+            let x = bb.x1 + PICKER_PADDING;
+            if entry.is_dir {
+                let name = if entry.name == ".." { entry.name.clone() } else { format!("{}/", entry.name) };
+                cx.text(text::Regular, [x, y], ColorScheme.link(), &name);
+            } else {
+                cx.text(text::Regular, [x, y], ColorScheme.normal(), &entry.name);
+            }
+        }
+    }
+}
+
+impl<F> Dispatch<MouseDown> for Picker<F> where F: FnMut(Mode, Vec<PathBuf>) {
+    fn dispatch(&mut self, ev: &MouseDown) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let pos = [ev.x, ev.y];
+        if let Some(i) = self.crumb_at(pos) {
+            let path = self.crumbs[i].0.clone();
+            self.navigate(path);
+            return true;
+        }
+        if let Some(row) = self.row_at(pos) {
+            self.move_cursor(row);
+            if self.modifiers.ctrl {
+                self.toggle_mark();
+            } else if ev.clicks() >= 2 {
+                self.activate();
+            }
+            return true;
+        }
+        false
+    }
+}
+
+impl<F> Dispatch<MouseUp> for Picker<F> {}
+
+impl<F> Dispatch<MouseMove> for Picker<F> {
+    fn dispatch(&mut self, ev: &MouseMove) -> bool {
+        let pos = [ev.x, ev.y];
+        let over = self.visible && (self.row_at(pos).is_some() || self.crumb_at(pos).is_some());
+        if over != self.over { self.over = over; true } else { false }
+    }
+}
+
+impl<F> Dispatch<MouseScroll> for Picker<F> {
+    fn dispatch(&mut self, ev: &MouseScroll) -> bool {
+        self.visible && self.scroll.wheel(self.list_bb(), self.shown.len(), ev)
+    }
+}
+
+impl<F> Dispatch<Update> for Picker<F> {}
+
+impl<F> Dispatch<TextInput> for Picker<F> {
+    fn dispatch(&mut self, &TextInput(c): &TextInput) -> bool {
+        if !self.has_focus() || !self.filter.input(c) {
+            return false;
+        }
+        // Typing out a directory goes into it.
+        if c == '/' || c == '\\' {
+            let typed = self.dir.join(&self.filter.text);
+            if typed.is_dir() {
+                self.filter.set_text(String::new());
+                self.navigate(typed);
+                return true;
+            }
+        }
+        self.refilter();
+        true
+    }
+}
+
+impl<F> Dispatch<KeyDown> for Picker<F> {
+    fn dispatch(&mut self, &KeyDown(_, modifiers): &KeyDown) -> bool {
+        self.modifiers = modifiers;
+        false
+    }
+}
+
+impl<F> Dispatch<KeyUp> for Picker<F> {
+    fn dispatch(&mut self, &KeyUp(_, modifiers): &KeyUp) -> bool {
+        self.modifiers = modifiers;
+        false
+    }
+}
+
+impl<F> Dispatch<KeyPress> for Picker<F> where F: FnMut(Mode, Vec<PathBuf>) {
+    fn dispatch(&mut self, &KeyPress(key, modifiers): &KeyPress) -> bool {
+        if !self.has_focus() {
+            return false;
+        }
+        let page = self.visible_rows().saturating_sub(1);
+        match key {
+            Key::Return => self.activate(),
+            Key::Escape => self.hide(),
+            Key::Tab => self.complete(),
+            Key::Up | Key::Down => {
+                if modifiers.shift {
+                    self.toggle_mark();
+                }
+                let cursor = self.cursor;
+                let cursor = if key == Key::Up { cursor.saturating_sub(1) } else { cursor + 1 };
+                self.move_cursor(cursor);
+            }
+            Key::PageUp => {
+                let cursor = self.cursor.saturating_sub(page);
+                self.move_cursor(cursor);
+            }
+            Key::PageDown => {
+                let cursor = self.cursor + page;
+                self.move_cursor(cursor);
+            }
+            Key::Back if self.filter.text.is_empty() => {
+                if let Some(parent) = self.dir.parent().map(Path::to_path_buf) {
+                    self.navigate(parent);
+                }
+            }
+            _ => {
+                if !self.filter.key(key) {
+                    return false;
+                }
+                if key == Key::Back || key == Key::Delete {
+                    self.refilter();
+                }
+            }
+        }
+        true
+    }
+}
+
+impl<F> Dispatch<Command> for Picker<F> {}
@@ -0,0 +1,294 @@
+use std::cmp::min;
+use std::path::{Path, PathBuf};
+
+use cfg::ColorScheme;
+use grep::{Grep, Hit};
+
+use ui::{BB, Px};
+use ui::layout::{CollectCx, CollectBB, Layout};
+use ui::color::Scheme;
+use ui::draw::{Draw, DrawCx, MouseCursor};
+use ui::event::*;
+use ui::find::{Pattern, Toggle};
+use ui::list::Scroll;
+use ui::text::{self, Field};
+
+/// Panel for searching through all the files in a directory,
+/// calling back with the path, row and byte offset of a clicked match.
+pub struct Panel<F> {
+    bb: BB<Px>,
+    pub visible: bool,
+    root: PathBuf,
+
+    query: Field,
+    regex: Toggle,
+    case_sensitive: Toggle,
+    whole_word: Toggle,
+
+    grep: Option<Grep>,
+    error: bool,
+    // Shown instead of the number of matches, for hits from `show_hits`.
+    label: Option<String>,
+
+    scroll: Scroll,
+    over: bool,
+    callback: F
+}
+
+const PANEL_HEIGHT: Px = 200.0;
+const PANEL_PADDING: Px = 4.0;
+
+impl<F> Panel<F> {
+    pub fn new(root: PathBuf, callback: F) -> Panel<F> {
+        let mut case_sensitive = Toggle::new("Aa");
+        case_sensitive.on = true;
+        Panel {
+            bb: BB::default(),
+            visible: false,
+            root: root,
+
+            query: Field::new(250.0, "Find in files"),
+            regex: Toggle::new(".*"),
+            case_sensitive: case_sensitive,
+            whole_word: Toggle::new("\\b"),
+
+            grep: None,
+            error: false,
+            label: None,
+
+            scroll: Scroll::default(),
+            over: false,
+            callback: callback
+        }
+    }
+
+    pub fn has_focus(&self) -> bool {
+        self.visible && self.query.focused
+    }
+
+    /// List hits found elsewhere, e.g. references to something.
+    pub fn show_hits(&mut self, label: String, hits: Vec<Hit>) {
+        self.visible = true;
+        self.query.focused = false;
+        self.scroll.start = 0;
+        self.label = Some(label);
+        self.grep = Some(Grep::finished(hits));
+    }
+
+    fn start(&mut self) {
+        self.scroll.start = 0;
+        self.label = None;
+        self.grep = None;
+        if self.query.text.is_empty() {
+            return;
+        }
+        match Pattern::new(&self.query.text, self.regex.on,
+                           self.case_sensitive.on, self.whole_word.on) {
+            Ok(pattern) => {
+                self.error = false;
+                self.grep = Some(Grep::start(self.root.clone(), pattern));
+            }
+            Err(_) => self.error = true
+        }
+    }
+
+    /// Area below the query, where the results are listed.
+    fn list_bb(&self) -> BB<Px> {
+        BB {
+            y1: self.query.bb().y2 + PANEL_PADDING,
+            ..self.bb
+        }
+    }
+
+    fn hit_count(&self) -> usize {
+        self.grep.as_ref().map_or(0, |grep| grep.hits.len())
+    }
+
+    fn hit_at(&self, pos: [Px; 2]) -> Option<usize> {
+        self.scroll.row_at(self.list_bb(), self.hit_count(), pos)
+    }
+}
+
+impl<F> Layout for Panel<F> {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        let bb = cx.area(&mut self.bb, "<results>");
+        if !self.visible {
+            cx.distance(bb.y1, bb.y2, 0.0);
+            return bb;
+        }
+        self.scroll.row_height = cx.fonts().metrics(text::Regular).height;
+
+        let qb = self.query.collect(cx);
+        let tb = [
+            self.regex.collect(cx),
+            self.case_sensitive.collect(cx),
+            self.whole_word.collect(cx)
+        ];
+        cx.distance(bb.y1, bb.y2, PANEL_HEIGHT);
+        cx.distance(bb.x1, qb.x1, PANEL_PADDING);
+        cx.distance(bb.y1, qb.y1, PANEL_PADDING);
+        cx.distance(qb.x2, tb[0].x1, PANEL_PADDING);
+        cx.distance(tb[0].x2, tb[1].x1, 2.0);
+        cx.distance(tb[1].x2, tb[2].x1, 2.0);
+        for b in &tb {
+            cx.equal(qb.y1, b.y1);
+        }
+        bb
+    }
+}
+
+impl<F> Draw for Panel<F> {
+    fn draw(&self, cx: &mut DrawCx) {
+        if !self.visible {
+            return;
+        }
+        if self.over {
+            cx.cursor(MouseCursor::Hand);
+        }
+
+        cx.fill(self.bb, ColorScheme.background());
+        cx.fill(BB { y2: self.bb.y1 + 1.0, ..self.bb }, ColorScheme.inactive());
+
+        self.query.draw(cx);
+        if self.error {
+            cx.border(self.query.bb(), ColorScheme.negative(), 1.0, 0.0);
+        }
+        self.regex.draw(cx);
+        self.case_sensitive.draw(cx);
+        self.whole_word.draw(cx);
+
+        let grep = match self.grep {
+            Some(ref grep) => grep,
+            None => return
+        };
+
+        let status = match self.label {
+            Some(ref label) => label.clone(),
+            None => format!("{} matches{}", grep.hits.len(), if grep.done { "" } else { "..." })
+        };
+        let x = self.whole_word.bb().x2 + PANEL_PADDING * 4.0;
+        cx.text(text::Regular, [x, self.query.bb().y1 + 3.0], ColorScheme.inactive(), &status);
+
+        let bb = self.list_bb();
+        let rows = self.scroll.visible_rows(bb);
+        let end = min(self.scroll.start + rows, grep.hits.len());
+        for (i, hit) in grep.hits[self.scroll.start..end].iter().enumerate() {
+            let path = hit.path.strip_prefix(&self.root).unwrap_or(&hit.path);
+            let prefix = format!("{}:{}: ", path.display(), hit.row + 1);
+            let line = hit.line.trim_left();
+            let skipped = hit.line.len() - line.len();
+
+            let [x, y] = [bb.x1 + PANEL_PADDING, bb.y1 + i as Px * self.scroll.row_height];
+            if hit.range.start >= skipped && hit.range.end <= hit.line.len() {
+                let (x1, w) = {
+                    let fonts = cx.fonts();
+                    let x1 = fonts.text_width(text::Regular, &prefix) +
+                             fonts.text_width(text::Regular, &hit.line[skipped..hit.range.start]);
+                    (x1, fonts.text_width(text::Regular, &hit.line[hit.range.clone()]))
+                };
+                let mut color = ColorScheme.hover();
+                color[3] = 0.4;
+                cx.fill(BB::rect(x + x1, y, w, self.scroll.row_height), color);
+            }
+            cx.text(text::Regular, [x, y], ColorScheme.inactive(), &prefix);
+            let w = cx.fonts().text_width(text::Regular, &prefix);
+            cx.text(text::Regular, [x + w, y], ColorScheme.normal(), line);
+        }
+    }
+}
+
+impl<F> Dispatch<MouseDown> for Panel<F> where F: FnMut(&Path, usize, usize) {
+    fn dispatch(&mut self, ev: &MouseDown) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let pos = [ev.x, ev.y];
+        let was_focused = self.query.focused;
+        self.query.focused = self.query.bb().contains(pos);
+
+        let toggled = {
+            let toggle = if self.regex.bb().contains(pos) {
+                Some(&mut self.regex)
+            } else if self.case_sensitive.bb().contains(pos) {
+                Some(&mut self.case_sensitive)
+            } else if self.whole_word.bb().contains(pos) {
+                Some(&mut self.whole_word)
+            } else {
+                None
+            };
+            toggle.map(|toggle| toggle.on = !toggle.on).is_some()
+        };
+        if toggled {
+            self.start();
+            return true;
+        }
+
+        if let Some(i) = self.hit_at(pos) {
+            let grep = self.grep.as_ref().unwrap();
+            let hit = &grep.hits[i];
+            (self.callback)(&hit.path, hit.row, hit.range.start);
+        }
+        was_focused != self.query.focused
+    }
+}
+
+impl<F> Dispatch<MouseUp> for Panel<F> {}
+
+impl<F> Dispatch<MouseMove> for Panel<F> {
+    fn dispatch(&mut self, ev: &MouseMove) -> bool {
+        let over = self.visible && self.hit_at([ev.x, ev.y]).is_some();
+        if over != self.over { self.over = over; true } else { false }
+    }
+}
+
+impl<F> Dispatch<MouseScroll> for Panel<F> {
+    fn dispatch(&mut self, ev: &MouseScroll) -> bool {
+        self.visible && self.scroll.wheel(self.list_bb(), self.hit_count(), ev)
+    }
+}
+
+impl<F> Dispatch<Update> for Panel<F> {
+    fn dispatch(&mut self, _: &Update) -> bool {
+        self.grep.as_mut().map_or(false, |grep| grep.update())
+    }
+}
+
+impl<F> Dispatch<TextInput> for Panel<F> {
+    fn dispatch(&mut self, &TextInput(c): &TextInput) -> bool {
+        self.has_focus() && self.query.input(c)
+    }
+}
+
+impl<F> Dispatch<KeyDown> for Panel<F> {}
+impl<F> Dispatch<KeyUp> for Panel<F> {}
+
+impl<F> Dispatch<KeyPress> for Panel<F> {
+    fn dispatch(&mut self, &KeyPress(key, _): &KeyPress) -> bool {
+        if !self.has_focus() {
+            return false;
+        }
+        match key {
+            Key::Return => self.start(),
+            Key::Escape => {
+                self.visible = false;
+                self.query.focused = false;
+            }
+            _ => return self.query.key(key)
+        }
+        true
+    }
+}
+
+impl<F> Dispatch<Command> for Panel<F> {
+    fn dispatch(&mut self, &Command(ref command): &Command) -> bool {
+        match &command[..] {
+            "find-in-files" => {
+                self.visible = true;
+                self.query.focused = true;
+                true
+            }
+            _ => false
+        }
+    }
+}
@@ -4,13 +4,19 @@ use ui::{BB, Px};
 use ui::layout::{CollectCx, CollectBB, Layout};
 use ui::color::Scheme;
 use ui::draw::{Draw, DrawCx};
+use ui::clipboard;
 use ui::event::*;
+use ui::find::{self, Focus, Search};
 use ui::text;
 
 pub struct Set<T> {
     bb: BB<Px>,
     tabs: Vec<T>,
-    current: usize
+    current: usize,
+
+    pub find: find::Bar,
+    // Whether the current tab has the pattern from the find bar.
+    find_synced: bool
 }
 
 const TAB_WIDTH: Px = 150.0;
@@ -20,7 +26,10 @@ impl<T> Set<T> {
         Set {
             bb: BB::default(),
             tabs: vec![],
-            current: 0
+            current: 0,
+
+            find: find::Bar::new(),
+            find_synced: true
         }
     }
 
@@ -29,12 +38,20 @@ impl<T> Set<T> {
             self.current += 1;
         }
         self.tabs.insert(self.current, x);
+        self.find_synced = false;
+    }
+
+    /// Add a tab after all the others, without switching to it.
+    pub fn push(&mut self, x: T) {
+        self.tabs.push(x);
+        self.find_synced = false;
     }
 
     pub fn remove(&mut self) -> Option<T> {
         if self.current >= self.tabs.len() {
             None
         } else {
+            self.find_synced = false;
             if self.current > 0 {
                 self.current -= 1;
             }
@@ -42,6 +59,18 @@ impl<T> Set<T> {
         }
     }
 
+    /// Remove any tab, keeping the current one if it's not the one removed.
+    pub fn remove_at(&mut self, i: usize) -> Option<T> {
+        if i >= self.tabs.len() {
+            return None;
+        }
+        if i < self.current || (i == self.current && i + 1 == self.tabs.len() && i > 0) {
+            self.current -= 1;
+        }
+        self.find_synced = false;
+        Some(self.tabs.remove(i))
+    }
+
     pub fn current(&self) -> Option<&T> {
         self.tabs.get(self.current)
     }
@@ -49,6 +78,25 @@ impl<T> Set<T> {
     pub fn current_mut(&mut self) -> Option<&mut T> {
         self.tabs.get_mut(self.current)
     }
+
+    pub fn tabs(&self) -> &[T] {
+        &self.tabs
+    }
+
+    pub fn tabs_mut(&mut self) -> &mut [T] {
+        &mut self.tabs
+    }
+
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+
+    pub fn set_current(&mut self, i: usize) {
+        if i < self.tabs.len() && i != self.current {
+            self.current = i;
+            self.find_synced = false;
+        }
+    }
 }
 
 impl<T: Layout> Layout for Set<T> {
@@ -62,7 +110,15 @@ impl<T: Layout> Layout for Set<T> {
 
             cx.equal(bb.x1, tb.x1);
             cx.equal(tb.x2, bb.x2);
-            cx.distance(bb.y1, tb.y1, height);
+            if self.find.visible {
+                let fb = self.find.collect(cx);
+                cx.equal(bb.x1, fb.x1);
+                cx.equal(fb.x2, bb.x2);
+                cx.distance(bb.y1, fb.y1, height);
+                cx.equal(fb.y2, tb.y1);
+            } else {
+                cx.distance(bb.y1, tb.y1, height);
+            }
             cx.equal(tb.y2, bb.y2);
         }
         bb
@@ -102,43 +158,210 @@ impl<T> Draw for Set<T> where T: Layout + Tab + Draw {
                                     ColorScheme.normal(), &text);
         }
 
-        self.current().map(|tab| tab.draw(cx));
+        if let Some(tab) = self.current() {
+            tab.draw(cx);
+            if self.find.visible {
+                self.find.draw(cx);
+            }
+        }
+    }
+}
+
+impl<T> Set<T> where T: Search {
+    /// Give the current tab the pattern from the find bar (or none, if it's closed).
+    fn sync_find(&mut self) {
+        let pattern = self.find.pattern();
+        if let Some(tab) = self.tabs.get_mut(self.current) {
+            tab.set_pattern(pattern);
+        }
+        self.find_synced = true;
+    }
+
+    /// Update the current tab after the query or a mode has changed.
+    fn query_changed(&mut self) {
+        self.sync_find();
+        if let Some(tab) = self.tabs.get_mut(self.current) {
+            tab.find_incremental();
+        }
+    }
+
+    fn open_find(&mut self, focus: Option<Focus>) {
+        let selected = self.current().and_then(|tab| tab.selected_text());
+        if let Some(text) = selected {
+            self.find.find.set_text(text);
+        }
+        self.find.visible = true;
+        if focus.is_some() {
+            self.find.set_focus(focus);
+        }
+        self.sync_find();
+    }
+
+    fn close_find(&mut self) {
+        self.find.visible = false;
+        self.find.set_focus(None);
+        self.sync_find();
     }
 }
 
 pub trait SetDispatch<E> {
     fn dispatch(&mut self, _ev: &E) -> bool { false }
+
+    /// Whether the event should be kept from the current tab.
+    fn grabs(&self, _ev: &E) -> bool { false }
 }
 
 impl<E, T> Dispatch<E> for Set<T> where Set<T>: SetDispatch<E>, T: Dispatch<E> {
     fn dispatch(&mut self, ev: &E) -> bool {
         if self.current < self.tabs.len() {
-            self.tabs[self.current].dispatch(ev) | SetDispatch::dispatch(self, ev)
+            let dirty = if SetDispatch::grabs(self, ev) {
+                false
+            } else {
+                self.tabs[self.current].dispatch(ev)
+            };
+            dirty | SetDispatch::dispatch(self, ev)
         } else {
             false
         }
     }
 }
 
-impl<T> SetDispatch<MouseDown> for Set<T> where T: Layout {
+impl<T> SetDispatch<MouseDown> for Set<T> where T: Layout + Search {
     fn dispatch(&mut self, ev: &MouseDown) -> bool {
         let pos = [ev.x, ev.y];
+
+        if self.find.visible && self.find.bb().contains(pos) {
+            if let Some(mode) = self.find.toggle_at(pos) {
+                self.find.toggle(mode);
+                self.query_changed();
+            } else if self.find.find.bb().contains(pos) {
+                self.find.set_focus(Some(Focus::Find));
+            } else if self.find.replace.bb().contains(pos) {
+                self.find.set_focus(Some(Focus::Replace));
+            }
+            return true;
+        }
+
+        // Clicking anywhere else takes the focus away from the find bar.
+        let dirty = self.find.focus().is_some();
+        self.find.set_focus(None);
+
         if self.bb.contains(pos) && !self.tabs[self.current].bb().contains(pos) {
             let new_tab = ((ev.x - self.bb.x1) / TAB_WIDTH) as usize;
             if new_tab < self.tabs.len() && new_tab != self.current {
                 self.current = new_tab;
+                self.find_synced = false;
                 return true;
             }
         }
-        false
+        dirty
     }
 }
 
 impl<T> SetDispatch<MouseUp> for Set<T> {}
 impl<T> SetDispatch<MouseMove> for Set<T> {}
 impl<T> SetDispatch<MouseScroll> for Set<T> {}
-impl<T> SetDispatch<Update> for Set<T> {}
-impl<T> SetDispatch<TextInput> for Set<T> {}
+
+impl<T> SetDispatch<Update> for Set<T> where T: Search {
+    fn dispatch(&mut self, _: &Update) -> bool {
+        if self.find_synced {
+            return false;
+        }
+        self.sync_find();
+        true
+    }
+}
+
+impl<T> SetDispatch<TextInput> for Set<T> where T: Search {
+    fn dispatch(&mut self, &TextInput(c): &TextInput) -> bool {
+        let changed = self.find.focused_field().map_or(false, |field| field.input(c));
+        if changed && self.find.focus() == Some(Focus::Find) {
+            self.query_changed();
+        }
+        changed
+    }
+
+    fn grabs(&self, _: &TextInput) -> bool {
+        self.find.focus().is_some()
+    }
+}
+
 impl<T> SetDispatch<KeyDown> for Set<T> {}
 impl<T> SetDispatch<KeyUp> for Set<T> {}
-impl<T> SetDispatch<KeyPress> for Set<T> {}
+
+impl<T> SetDispatch<KeyPress> for Set<T> where T: Search {
+    fn dispatch(&mut self, &KeyPress(key, modifiers): &KeyPress) -> bool {
+        if !self.find.visible {
+            return false;
+        }
+        if key == Key::Escape {
+            self.close_find();
+            return true;
+        }
+
+        match self.find.focus() {
+            Some(Focus::Find) if key == Key::Return => {
+                self.tabs[self.current].find_next(!modifiers.shift)
+            }
+            Some(Focus::Replace) if key == Key::Return => {
+                self.tabs[self.current].replace_one(&self.find.replace.text)
+            }
+            Some(_) => {
+                let query = self.find.find.text.clone();
+                let dirty = self.find.key(key);
+                if self.find.find.text != query {
+                    self.query_changed();
+                }
+                dirty
+            }
+            None => false
+        }
+    }
+
+    fn grabs(&self, _: &KeyPress) -> bool {
+        self.find.focus().is_some()
+    }
+}
+
+impl<T> SetDispatch<Command> for Set<T> where T: Search {
+    fn dispatch(&mut self, &Command(ref command): &Command) -> bool {
+        match &command[..] {
+            "find" => self.open_find(Some(Focus::Find)),
+            "replace" => self.open_find(Some(Focus::Replace)),
+            "find-next" | "find-previous" => {
+                if !self.find.visible {
+                    self.open_find(None);
+                }
+                self.tabs[self.current].find_next(command == "find-next");
+            }
+            "replace-all" => {
+                if !self.find.visible {
+                    return false;
+                }
+                return self.tabs[self.current].replace_all(&self.find.replace.text);
+            }
+            "find-toggle-regex" | "find-toggle-case" | "find-toggle-word" => {
+                self.find.toggle(&command["find-toggle-".len()..]);
+                self.query_changed();
+            }
+            // Paste the first line of the clipboard into the focused field.
+            "paste" if self.find.focus().is_some() => {
+                let text = clipboard::get().unwrap_or(String::new());
+                if let Some(field) = self.find.focused_field() {
+                    for c in text.lines().next().unwrap_or("").chars() {
+                        field.input(c);
+                    }
+                }
+                if self.find.focus() == Some(Focus::Find) {
+                    self.query_changed();
+                }
+            }
+            _ => return false
+        }
+        true
+    }
+
+    fn grabs(&self, _: &Command) -> bool {
+        self.find.focus().is_some()
+    }
+}
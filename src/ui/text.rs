@@ -1,8 +1,10 @@
+use cfg::ColorScheme;
 use glyph::{FontSize, Glyph, GlyphMetrics, GlyphCache};
 
 use ui::{BB, Px};
-use ui::color::Color;
+use ui::color::{Color, Scheme};
 use ui::draw::{Draw, DrawCx};
+use ui::event::Key;
 use ui::layout::{CollectCx, CollectBB, Layout};
 
 // TODO use a text layouting engine
@@ -83,3 +85,110 @@ impl<F> Draw for Label<F> where F: FontFace {
         cx.text(self.font, self.bb.top_left(), self.color, self.text);
     }
 }
+
+/// Single-line text entry, which only gets key presses and text input while focused.
+pub struct Field<F=Regular> {
+    bb: BB<Px>,
+    font: F,
+    width: Px,
+    pub placeholder: &'static str,
+    pub focused: bool,
+    pub text: String,
+
+    // Byte offset of the caret in `text`.
+    cursor: usize
+}
+
+const FIELD_PADDING: Px = 3.0;
+
+impl<F> Field<F> where F: Default {
+    pub fn new(width: Px, placeholder: &'static str) -> Field<F> {
+        Field {
+            bb: BB::default(),
+            font: F::default(),
+            width: width,
+            placeholder: placeholder,
+            focused: false,
+            text: String::new(),
+            cursor: 0
+        }
+    }
+}
+
+impl<F> Field<F> {
+    pub fn set_text(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    /// Insert a character at the caret, returns false for control characters.
+    pub fn input(&mut self, c: char) -> bool {
+        if c.is_control() {
+            return false;
+        }
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+        true
+    }
+
+    /// Handle editing and caret movement keys, returns false for other keys.
+    pub fn key(&mut self, key: Key) -> bool {
+        let cursor = self.cursor;
+        let prev = self.text[..cursor].chars().next_back().map_or(cursor, |c| cursor - c.len_utf8());
+        let next = self.text[cursor..].chars().next().map_or(cursor, |c| cursor + c.len_utf8());
+        match key {
+            Key::Left => self.cursor = prev,
+            Key::Right => self.cursor = next,
+            Key::Home => self.cursor = 0,
+            Key::End => self.cursor = self.text.len(),
+            Key::Back => {
+                if prev < cursor {
+                    self.text.remove(prev);
+                    self.cursor = prev;
+                }
+            }
+            Key::Delete => {
+                if next > cursor {
+                    self.text.remove(cursor);
+                }
+            }
+            _ => return false
+        }
+        true
+    }
+}
+
+impl<F> Layout for Field<F> where F: FontFace {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        let bb = cx.area(&mut self.bb, self.placeholder);
+        let h = cx.fonts().metrics(self.font).height;
+        cx.distance(bb.x1, bb.x2, self.width);
+        cx.distance(bb.y1, bb.y2, h + FIELD_PADDING * 2.0);
+        bb
+    }
+}
+
+impl<F> Draw for Field<F> where F: FontFace {
+    fn draw(&self, cx: &mut DrawCx) {
+        let bb = self.bb;
+        cx.fill(bb, ColorScheme.back_view());
+        let border = if self.focused { ColorScheme.focus() } else { ColorScheme.inactive() };
+        cx.border(bb, border, 1.0, 0.0);
+
+        let [x, y] = [bb.x1 + FIELD_PADDING, bb.y1 + FIELD_PADDING];
+        if self.text.is_empty() && !self.focused {
+            cx.text(self.font, [x, y], ColorScheme.inactive(), self.placeholder);
+        } else {
+            cx.text(self.font, [x, y], ColorScheme.normal(), &self.text);
+        }
+
+        if self.focused {
+            let (dx, h) = {
+                let fonts = cx.fonts();
+                (fonts.text_width(self.font, &self.text[..self.cursor]), fonts.metrics(self.font).height)
+            };
+            cx.fill(BB::rect((x + dx).round(), y, 1.0, h), ColorScheme.normal());
+        }
+    }
+}
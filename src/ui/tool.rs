@@ -133,3 +133,4 @@ impl<F> Dispatch<TextInput> for Button<F> {}
 impl<F> Dispatch<KeyDown> for Button<F> {}
 impl<F> Dispatch<KeyUp> for Button<F> {}
 impl<F> Dispatch<KeyPress> for Button<F> {}
+impl<F> Dispatch<Command> for Button<F> {}
@@ -0,0 +1,625 @@
+use std::cmp::{min, Ordering};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+
+use cfg::ColorScheme;
+use grep::Ignore;
+
+use ui::{BB, Px};
+use ui::layout::{CollectCx, CollectBB, Layout};
+use ui::color::Scheme;
+use ui::draw::{Draw, DrawCx, MouseCursor};
+use ui::event::*;
+use ui::list::Scroll;
+use ui::text::{self, Field};
+
+/// Changes made from the tree, which open tabs have to follow.
+pub enum Action {
+    Open(PathBuf),
+    Created(PathBuf),
+    Renamed(PathBuf, PathBuf),
+    Deleted(PathBuf)
+}
+
+#[derive(PartialEq)]
+struct Row {
+    path: PathBuf,
+    name: String,
+    depth: usize,
+    is_dir: bool
+}
+
+/// A name being typed in, at the bottom of the panel.
+enum Edit {
+    /// New file (or directory, if the name ends with `/`) in a directory.
+    Create(PathBuf),
+    Rename(PathBuf)
+}
+
+/// Side panel listing the files under the project's root directory,
+/// skipping anything ignored by `.gitignore`.
+pub struct Tree<F> {
+    bb: BB<Px>,
+    pub visible: bool,
+    focused: bool,
+    root: PathBuf,
+
+    expanded: HashSet<PathBuf>,
+    // Every visible entry, in order, with the children of expanded directories.
+    rows: Vec<Row>,
+    selected: usize,
+    scroll: Scroll,
+
+    // Paths of the open tabs, as given and canonicalized, and whether they have unsaved changes.
+    open: Vec<(PathBuf, PathBuf, bool)>,
+    edit: Option<(Edit, Field)>,
+    // Entry about to be deleted, waiting for a second Delete.
+    confirm_delete: Option<PathBuf>,
+    error: Option<String>,
+
+    // Time since the rows were last read from disk.
+    refresh: f32,
+    // Rows being read again on a background thread, so big trees don't hold up the UI.
+    pending: Option<Receiver<Vec<Row>>>,
+    over: bool,
+    callback: F
+}
+
+const TREE_WIDTH: Px = 200.0;
+const TREE_PADDING: Px = 4.0;
+const TREE_INDENT: Px = 12.0;
+/// How often expanded directories are listed again, to show changes made elsewhere.
+const TREE_REFRESH_SPACING: f32 = 2.0;
+
+/// Add the entries of `dir` to `rows`, with those of expanded subdirectories after each.
+fn list(dir: &Path, depth: usize, expanded: &HashSet<PathBuf>,
+        ignores: &mut Vec<Ignore>, rows: &mut Vec<Row>) {
+    let old_len = ignores.len();
+    Ignore::load(dir, ignores);
+
+    let mut entries: Vec<_> = match fs::read_dir(dir) {
+        Ok(entries) => entries.filter_map(|e| e.ok()).map(|e| {
+            let path = e.path();
+            let is_dir = path.is_dir();
+            (path, is_dir)
+        }).collect(),
+        Err(_) => vec![]
+    };
+    entries.sort_by(|&(ref a, a_dir), &(ref b, b_dir)| {
+        match (a_dir, b_dir) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            _ => a.cmp(b)
+        }
+    });
+
+    for (path, is_dir) in entries {
+        if path.file_name().map_or(false, |name| name == ".git") {
+            continue;
+        }
+        if Ignore::is_ignored(ignores, &path, is_dir) {
+            continue;
+        }
+        let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+        let open = is_dir && expanded.contains(&path);
+        rows.push(Row {
+            path: path.clone(),
+            name: name,
+            depth: depth,
+            is_dir: is_dir
+        });
+        if open {
+            list(&path, depth + 1, expanded, ignores, rows);
+        }
+    }
+
+    ignores.truncate(old_len);
+}
+
+impl<F> Tree<F> {
+    pub fn new(root: PathBuf, callback: F) -> Tree<F> {
+        let mut tree = Tree {
+            bb: BB::default(),
+            visible: true,
+            focused: false,
+            root: fs::canonicalize(&root).unwrap_or(root),
+
+            expanded: HashSet::new(),
+            rows: vec![],
+            selected: 0,
+            scroll: Scroll::default(),
+
+            open: vec![],
+            edit: None,
+            confirm_delete: None,
+            error: None,
+
+            refresh: 0.0,
+            pending: None,
+            over: false,
+            callback: callback
+        };
+        tree.rebuild();
+        tree
+    }
+
+    pub fn has_focus(&self) -> bool {
+        self.visible && self.focused
+    }
+
+    /// Keep track of the open tabs, returns true if they changed.
+    pub fn set_open(&mut self, open: Vec<(PathBuf, bool)>) -> bool {
+        let same = open.len() == self.open.len() &&
+                   open.iter().zip(&self.open).all(|(&(ref a, a_dirty), &(ref b, _, b_dirty))| {
+                       a == b && a_dirty == b_dirty
+                   });
+        if same {
+            return false;
+        }
+        self.open = open.into_iter().map(|(path, dirty)| {
+            let canonical = fs::canonicalize(&path).unwrap_or(path.clone());
+            (path, canonical, dirty)
+        }).collect();
+        true
+    }
+
+    /// List the expanded directories again, keeping the same entry selected.
+    /// Returns true if anything changed.
+    fn rebuild(&mut self) -> bool {
+        // Whatever is being read in the background is out of date now.
+        self.pending = None;
+        let mut rows = vec![];
+        list(&self.root, 0, &self.expanded, &mut vec![], &mut rows);
+        self.set_rows(rows)
+    }
+
+    /// Start listing the expanded directories again on a background thread,
+    /// to show changes made elsewhere, see `Dispatch<Update>`.
+    fn start_refresh(&mut self) {
+        let (root, expanded) = (self.root.clone(), self.expanded.clone());
+        let (rows_tx, rows_rx) = channel();
+        thread::spawn(move || {
+            let mut rows = vec![];
+            list(&root, 0, &expanded, &mut vec![], &mut rows);
+            let _ = rows_tx.send(rows);
+        });
+        self.pending = Some(rows_rx);
+    }
+
+    /// Show new rows, keeping the same entry selected. Returns true if anything changed.
+    fn set_rows(&mut self, rows: Vec<Row>) -> bool {
+        if rows == self.rows {
+            return false;
+        }
+        let selected = self.rows.get(self.selected).map(|row| row.path.clone());
+        self.rows = rows;
+        self.select_path(selected);
+        true
+    }
+
+    fn select_path(&mut self, path: Option<PathBuf>) {
+        let found = path.and_then(|path| self.rows.iter().position(|row| row.path == path));
+        let selected = found.unwrap_or(self.selected);
+        self.select(selected);
+    }
+
+    fn select(&mut self, i: usize) {
+        self.selected = min(i, self.rows.len().saturating_sub(1));
+        self.confirm_delete = None;
+
+        // Keep the selection in view.
+        let bb = self.list_bb();
+        self.scroll.show(bb, self.selected);
+    }
+
+    fn set_expanded(&mut self, i: usize, expand: bool) {
+        let path = match self.rows.get(i) {
+            Some(row) if row.is_dir => row.path.clone(),
+            _ => return
+        };
+        if expand {
+            self.expanded.insert(path);
+        } else {
+            // Forget about the subdirectories too, so they start out collapsed.
+            self.expanded.retain(|p| !p.starts_with(&path));
+        }
+        self.rebuild();
+    }
+
+    /// Open the selected file, or expand or collapse the selected directory.
+    fn activate(&mut self) where F: FnMut(Action) {
+        let (path, is_dir) = match self.rows.get(self.selected) {
+            Some(row) => (row.path.clone(), row.is_dir),
+            None => return
+        };
+        if is_dir {
+            let expand = !self.expanded.contains(&path);
+            let i = self.selected;
+            self.set_expanded(i, expand);
+        } else {
+            (self.callback)(Action::Open(path));
+        }
+    }
+
+    /// The directory new files go in: the selected one, or that of the selected file.
+    fn target_dir(&self) -> PathBuf {
+        match self.rows.get(self.selected) {
+            Some(row) if row.is_dir => row.path.clone(),
+            Some(row) => row.path.parent().map_or(self.root.clone(), Path::to_path_buf),
+            None => self.root.clone()
+        }
+    }
+
+    fn rename_selected(&mut self) {
+        if let Some(path) = self.rows.get(self.selected).map(|row| row.path.clone()) {
+            self.start_edit(Edit::Rename(path));
+        }
+    }
+
+    fn start_edit(&mut self, edit: Edit) {
+        let mut field = Field::new(TREE_WIDTH - TREE_PADDING * 2.0, "");
+        match edit {
+            Edit::Create(_) => field.placeholder = "New file, or dir/",
+            Edit::Rename(ref path) => {
+                field.placeholder = "New name";
+                field.set_text(path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned()));
+            }
+        }
+        field.focused = true;
+        self.edit = Some((edit, field));
+        self.confirm_delete = None;
+        self.error = None;
+    }
+
+    fn commit_edit(&mut self) where F: FnMut(Action) {
+        let (edit, field) = match self.edit.take() {
+            Some(edit) => edit,
+            None => return
+        };
+        let name = field.text.trim();
+        if name.is_empty() {
+            return;
+        }
+        let result = match edit {
+            Edit::Create(dir) => {
+                let path = dir.join(name);
+                let created = if name.ends_with('/') {
+                    fs::create_dir_all(&path)
+                } else {
+                    path.parent().map_or(Ok(()), |parent| fs::create_dir_all(parent)).and_then(|_| {
+                        fs::OpenOptions::new().write(true).create_new(true).open(&path).map(|_| ())
+                    })
+                };
+                created.map(|_| {
+                    self.expanded.insert(dir);
+                    if let Some(parent) = path.parent() {
+                        self.expanded.insert(parent.to_path_buf());
+                    }
+                    self.rebuild();
+                    self.select_path(Some(path.clone()));
+                    if !name.ends_with('/') {
+                        (self.callback)(Action::Created(path));
+                    }
+                })
+            }
+            Edit::Rename(old) => {
+                let new = old.with_file_name(name);
+                if new.exists() {
+                    self.error = Some(format!("{} already exists", name));
+                    return;
+                }
+                fs::rename(&old, &new).map(|_| {
+                    // Keep whatever was expanded inside a renamed directory.
+                    let moved: Vec<_> = self.expanded.iter().filter(|p| p.starts_with(&old)).cloned().collect();
+                    for path in moved {
+                        self.expanded.remove(&path);
+                        if let Ok(rest) = path.strip_prefix(&old) {
+                            self.expanded.insert(new.join(rest));
+                        }
+                    }
+                    self.rebuild();
+                    self.select_path(Some(new.clone()));
+                    (self.callback)(Action::Renamed(old, new));
+                })
+            }
+        };
+        if let Err(e) = result {
+            self.error = Some(e.to_string());
+        }
+    }
+
+    /// Delete the selected entry, after being asked twice.
+    fn delete(&mut self) where F: FnMut(Action) {
+        let (path, is_dir) = match self.rows.get(self.selected) {
+            Some(row) => (row.path.clone(), row.is_dir),
+            None => return
+        };
+        if self.confirm_delete.as_ref() != Some(&path) {
+            self.confirm_delete = Some(path);
+            return;
+        }
+        self.confirm_delete = None;
+        let result = if is_dir { fs::remove_dir_all(&path) } else { fs::remove_file(&path) };
+        match result {
+            Ok(()) => {
+                self.expanded.retain(|p| !p.starts_with(&path));
+                self.rebuild();
+                (self.callback)(Action::Deleted(path));
+            }
+            Err(e) => self.error = Some(e.to_string())
+        }
+    }
+
+    /// Height of the strip at the bottom, for typing names and messages.
+    fn status_height(&self) -> Px {
+        if self.edit.is_some() || self.confirm_delete.is_some() || self.error.is_some() {
+            self.scroll.row_height + TREE_PADDING * 4.0
+        } else {
+            0.0
+        }
+    }
+
+    fn list_bb(&self) -> BB<Px> {
+        BB {
+            y2: self.bb.y2 - self.status_height(),
+            ..self.bb
+        }
+    }
+
+    fn visible_rows(&self) -> usize {
+        self.scroll.visible_rows(self.list_bb())
+    }
+
+    fn row_at(&self, pos: [Px; 2]) -> Option<usize> {
+        self.scroll.row_at(self.list_bb(), self.rows.len(), pos)
+    }
+}
+
+impl<F> Layout for Tree<F> {
+    fn bb(&self) -> BB<Px> { self.bb }
+    fn collect<'a>(&'a mut self, cx: &mut CollectCx<'a>) -> CollectBB<'a> {
+        let bb = cx.area(&mut self.bb, "<tree>");
+        if !self.visible {
+            cx.distance(bb.x1, bb.x2, 0.0);
+            return bb;
+        }
+        self.scroll.row_height = cx.fonts().metrics(text::Regular).height;
+        cx.distance(bb.x1, bb.x2, TREE_WIDTH);
+        if let Some((_, ref mut field)) = self.edit {
+            let fb = field.collect(cx);
+            cx.distance(bb.x1, fb.x1, TREE_PADDING);
+            cx.distance(fb.y2, bb.y2, TREE_PADDING);
+        }
+        bb
+    }
+}
+
+impl<F> Draw for Tree<F> {
+    fn draw(&self, cx: &mut DrawCx) {
+        if !self.visible {
+            return;
+        }
+        if self.over {
+            cx.cursor(MouseCursor::Hand);
+        }
+
+        cx.fill(self.bb, ColorScheme.background());
+        cx.fill(BB { x1: self.bb.x2 - 1.0, ..self.bb }, ColorScheme.inactive());
+
+        let bb = self.list_bb();
+        let rows = self.visible_rows();
+        let end = min(self.scroll.start + rows, self.rows.len());
+        for (i, row) in self.rows[self.scroll.start..end].iter().enumerate() {
+            let y = bb.y1 + i as Px * self.scroll.row_height;
+            if self.scroll.start + i == self.selected {
+                let row_bb = BB { y1: y, y2: y + self.scroll.row_height, x2: bb.x2 - 1.0, ..bb };
+                if self.focused {
+                    let mut color = ColorScheme.focus();
+                    color[3] = 0.4;
+                    cx.fill(row_bb, color);
+                } else {
+                    cx.border(row_bb, ColorScheme.inactive(), 1.0, 0.0);
+                }
+            }
+
+            let x = bb.x1 + TREE_PADDING + row.depth as Px * TREE_INDENT;
+            if row.is_dir {
+                let toggle = if self.expanded.contains(&row.path) { "-" } else { "+" };
+                cx.text(text::Regular, [x, y], ColorScheme.inactive(), toggle);
+            }
+            let x = x + TREE_INDENT;
+
+            // Open files are marked, with unsaved ones the same way as their tabs.
+            let open = self.open.iter().find(|&&(_, ref path, _)| *path == row.path);
+            match open {
+                Some(&(_, _, true)) => {
+                    cx.text(text::Regular, [x, y], ColorScheme.neutral(), &format!("{}*", row.name));
+                }
+                Some(&(_, _, false)) => cx.text(text::Regular, [x, y], ColorScheme.link(), &row.name),
+                None => cx.text(text::Regular, [x, y], ColorScheme.normal(), &row.name)
+            }
+        }
+
+        let y = bb.y2 + TREE_PADDING * 2.0;
+        if let Some((_, ref field)) = self.edit {
+            field.draw(cx);
+        } else if let Some(ref path) = self.confirm_delete {
+            let name = path.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+            cx.text(text::Regular, [bb.x1 + TREE_PADDING, y], ColorScheme.negative(),
+                    &format!("Delete {}? Press Delete again", name));
+        } else if let Some(ref error) = self.error {
+            cx.text(text::Regular, [bb.x1 + TREE_PADDING, y], ColorScheme.negative(), error);
+        }
+    }
+}
+
+impl<F> Dispatch<MouseDown> for Tree<F> where F: FnMut(Action) {
+    fn dispatch(&mut self, ev: &MouseDown) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let was_focused = self.focused;
+        self.focused = self.bb.contains([ev.x, ev.y]);
+        if !self.focused {
+            self.edit = None;
+            self.confirm_delete = None;
+            return was_focused;
+        }
+        self.error = None;
+        if let Some(i) = self.row_at([ev.x, ev.y]) {
+            self.select(i);
+            self.activate();
+        }
+        true
+    }
+}
+
+impl<F> Dispatch<MouseUp> for Tree<F> {}
+
+impl<F> Dispatch<MouseMove> for Tree<F> {
+    fn dispatch(&mut self, ev: &MouseMove) -> bool {
+        let over = self.visible && self.row_at([ev.x, ev.y]).is_some();
+        if over != self.over { self.over = over; true } else { false }
+    }
+}
+
+impl<F> Dispatch<MouseScroll> for Tree<F> {
+    fn dispatch(&mut self, ev: &MouseScroll) -> bool {
+        self.visible && self.scroll.wheel(self.list_bb(), self.rows.len(), ev)
+    }
+}
+
+impl<F> Dispatch<Update> for Tree<F> {
+    fn dispatch(&mut self, &Update(dt): &Update) -> bool {
+        if !self.visible {
+            return false;
+        }
+        let listed = match self.pending.as_ref().map(|rows_rx| rows_rx.try_recv()) {
+            Some(Ok(rows)) => Some(rows),
+            Some(Err(TryRecvError::Empty)) => return false,
+            Some(Err(TryRecvError::Disconnected)) | None => None
+        };
+        self.pending = None;
+        if let Some(rows) = listed {
+            return self.set_rows(rows);
+        }
+
+        self.refresh += dt;
+        if self.refresh >= TREE_REFRESH_SPACING {
+            self.refresh = 0.0;
+            self.start_refresh();
+        }
+        false
+    }
+}
+
+impl<F> Dispatch<TextInput> for Tree<F> {
+    fn dispatch(&mut self, &TextInput(c): &TextInput) -> bool {
+        if !self.has_focus() {
+            return false;
+        }
+        match self.edit {
+            Some((_, ref mut field)) => field.input(c),
+            None => false
+        }
+    }
+}
+
+impl<F> Dispatch<KeyDown> for Tree<F> {}
+impl<F> Dispatch<KeyUp> for Tree<F> {}
+
+impl<F> Dispatch<KeyPress> for Tree<F> where F: FnMut(Action) {
+    fn dispatch(&mut self, &KeyPress(key, _): &KeyPress) -> bool {
+        if !self.has_focus() {
+            return false;
+        }
+        self.error = None;
+
+        if self.edit.is_some() {
+            match key {
+                Key::Return => self.commit_edit(),
+                Key::Escape => self.edit = None,
+                _ => {
+                    if let Some((_, ref mut field)) = self.edit {
+                        return field.key(key);
+                    }
+                }
+            }
+            return true;
+        }
+
+        let selected = self.selected;
+        match key {
+            Key::Up => self.select(selected.saturating_sub(1)),
+            Key::Down => self.select(selected + 1),
+            Key::PageUp | Key::PageDown => {
+                let page = self.visible_rows().saturating_sub(1);
+                let i = if key == Key::PageUp { selected.saturating_sub(page) } else { selected + page };
+                self.select(i);
+            }
+            Key::Home => self.select(0),
+            Key::End => self.select(usize::max_value()),
+            Key::Left => {
+                let expanded = self.rows.get(selected).map_or(false, |row| self.expanded.contains(&row.path));
+                if expanded {
+                    self.set_expanded(selected, false);
+                } else if let Some(depth) = self.rows.get(selected).map(|row| row.depth) {
+                    // Go up to the parent directory.
+                    if let Some(i) = self.rows[..selected].iter().rposition(|row| row.depth < depth) {
+                        self.select(i);
+                    }
+                }
+            }
+            Key::Right => {
+                let row = self.rows.get(selected).map(|row| (row.is_dir, self.expanded.contains(&row.path)));
+                match row {
+                    Some((true, false)) => self.set_expanded(selected, true),
+                    Some((true, true)) => self.select(selected + 1),
+                    _ => {}
+                }
+            }
+            Key::Return => self.activate(),
+            Key::Insert => {
+                let dir = self.target_dir();
+                self.start_edit(Edit::Create(dir));
+            }
+            Key::F2 => self.rename_selected(),
+            Key::Delete => self.delete(),
+            Key::Escape => {
+                if self.confirm_delete.take().is_none() {
+                    self.focused = false;
+                }
+            }
+            _ => return false
+        }
+        true
+    }
+}
+
+impl<F> Dispatch<Command> for Tree<F> {
+    fn dispatch(&mut self, &Command(ref command): &Command) -> bool {
+        match &command[..] {
+            "toggle-tree" => {
+                self.visible = !self.visible;
+                if !self.visible {
+                    self.focused = false;
+                }
+                true
+            }
+            "focus-tree" => {
+                self.visible = true;
+                self.focused = !self.focused;
+                true
+            }
+            // Bound to F2 by default, which renames files here.
+            "rename-symbol" if self.has_focus() && self.edit.is_none() => {
+                self.rename_selected();
+                true
+            }
+            _ => false
+        }
+    }
+}